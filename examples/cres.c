@@ -55,6 +55,16 @@ int main(int argc, char** argv) {
   opt.jet_def.algorithm = AntiKt;
   opt.jet_def.radius = 0.4;
   opt.jet_def.min_pt = 30.;
+  /* only keep jets with |y| < 4.5 */
+  opt.jet_def.rapidity_kind = Rapidity;
+  opt.jet_def.max_rapidity = 4.5;
+  /* no excluded rapidity range */
+  opt.jet_def.excluded_min_rapidity = 0.;
+  opt.jet_def.excluded_max_rapidity = 0.;
+  /* only used for the lepton-collider algorithms `Durham` and `EeGenKt` */
+  opt.jet_def.exponent = -1.;
+  opt.jet_def.n_jets = 0;
+  opt.jet_def.y_cut = 0.;
   /* maximum cell size, INFINITY means effectively unlimited */
   opt.max_cell_size = INFINITY;
 
@@ -71,8 +81,37 @@ int main(int argc, char** argv) {
    * see `user_distance.c` for an example of a user-defined distance
    */
   opt.distance = NULL;
+  opt.builtin_distance = EuclWithScaledPt;
   opt.ptweight = 0.;
 
+  /* alternatively, the energy mover's distance
+   * (https://arxiv.org/abs/1902.02346) can be chosen with
+   * `opt.builtin_distance = EnergyMovers`. Unless `opt.emd_radius` is
+   * at least half the largest distance between particles, this is
+   * not a metric and `opt.neighbour_search = Naive` should be used.
+   * Its parameters are
+   */
+  opt.emd_radius = 1.;
+  opt.emd_beta = 1.;
+
+  /* the contribution of particles without a partner in the other
+   * event is multiplied by opt.unmatched_penalty
+   */
+  opt.unmatched_penalty = 1.;
+
+  /* optional settings for specific particle types
+   *
+   * Here, distances between dressed leptons (particle id 82) count
+   * five times as much as those between other particles
+   */
+  TypeWeights lepton_weights;
+  lepton_weights.pid = 82;
+  lepton_weights.scale = 5.;
+  lepton_weights.ptweight = opt.ptweight;
+  lepton_weights.unmatched_penalty = opt.unmatched_penalty;
+  opt.type_weights = &lepton_weights;
+  opt.n_type_weights = 1;
+
   /* build and run the resampler */
   res = cres_run(&opt);
   if(res != 0) cres_print_last_err();
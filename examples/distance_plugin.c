@@ -0,0 +1,91 @@
+/* distance plugin for the cres command-line tool
+ *
+ * Compile the plugin, for example with
+ * ```
+ * cc -shared -fPIC -o libdistance_plugin.so examples/distance_plugin.c -lm
+ * ```
+ * where `cres.h` has to be in the include path (see `cres.c`).
+ *
+ * Then run cres, which has to be installed with the `distance-plugin`
+ * feature, with
+ * ```
+ * cres --distance-plugin ./libdistance_plugin.so --distance-plugin-config 0.5 ...
+ * ```
+ * To use a different function name than the default `cres_distance`,
+ * add it after the library path, e.g.
+ * `--distance-plugin ./libdistance_plugin.so:my_distance`.
+ * The optional initialisation and teardown functions then have to be
+ * called `my_distance_init` and `my_distance_teardown`.
+ */
+
+#include "cres.h"
+
+#include <math.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+/* optional: called once with the string given with
+ * `--distance-plugin-config`, or an empty string
+ *
+ * `data` is passed to each call of the distance function
+ *
+ * returns 0 on success
+ */
+int32_t cres_distance_init(char const* config, void** data) {
+  double* pt_fact = malloc(sizeof(double));
+  if(pt_fact == NULL) return 1;
+  *pt_fact = 1.;
+  if(config[0] != '\0') {
+    char* end;
+    *pt_fact = strtod(config, &end);
+    if(*end != '\0') {
+      fprintf(stderr, "Invalid configuration: '%s'\n", config);
+      free(pt_fact);
+      return 2;
+    }
+  }
+  *data = pt_fact;
+  return 0;
+}
+
+/* optional: called once at the end */
+void cres_distance_teardown(void* data) {
+  free(data);
+}
+
+static double pt(const double* p) {
+  return sqrt(p[1]*p[1] + p[2]*p[2]);
+}
+
+/* the distance function
+ *
+ * this function has to be thread-safe and must never return NaN!
+ *
+ * for demonstration, we compare the scalar sums of transverse momenta
+ * and energies of the particles in the two events
+ */
+double cres_distance(
+  void* data,
+  EventView const * ev1,
+  EventView const * ev2
+) {
+  const double pt_fact = * (const double*) data;
+  double ht = 0.;
+  double energy = 0.;
+
+  for(uintptr_t t = 0; t < ev1->n_type_sets; ++t) {
+    for(uintptr_t i = 0; i < ev1->type_sets[t].n_momenta; ++i) {
+      const double* p = ev1->type_sets[t].momenta[i];
+      ht += pt(p);
+      energy += p[0];
+    }
+  }
+  for(uintptr_t t = 0; t < ev2->n_type_sets; ++t) {
+    for(uintptr_t i = 0; i < ev2->type_sets[t].n_momenta; ++i) {
+      const double* p = ev2->type_sets[t].momenta[i];
+      ht -= pt(p);
+      energy -= p[0];
+    }
+  }
+  return pt_fact * fabs(ht) + fabs(energy);
+}
@@ -63,6 +63,13 @@ int main(int argc, char** argv) {
   opt.jet_def.algorithm = AntiKt;
   opt.jet_def.radius = 0.4;
   opt.jet_def.min_pt = 30.;
+  opt.jet_def.rapidity_kind = Rapidity;
+  opt.jet_def.max_rapidity = INFINITY;
+  opt.jet_def.excluded_min_rapidity = 0.;
+  opt.jet_def.excluded_max_rapidity = 0.;
+  opt.jet_def.exponent = -1.;
+  opt.jet_def.n_jets = 0;
+  opt.jet_def.y_cut = 0.;
   opt.neighbour_search = Tree;
   opt.max_cell_size = INFINITY;
 
@@ -0,0 +1,216 @@
+//! Solver for the linear assignment problem
+//!
+//! This is the shortest augmenting path algorithm by Jonker and
+//! Volgenant for dense square cost matrices, initialised with the
+//! column minima as dual variables. Since the assignment has to be
+//! solved for a large number of event pairs, all buffers are kept in
+//! per-thread [Scratch] space that is reused between calls.
+use std::cell::RefCell;
+
+thread_local! {
+    static SCRATCH: RefCell<Scratch> = const { RefCell::new(Scratch::new()) };
+}
+
+/// Call `f` with the scratch space of the current thread
+pub(crate) fn with_scratch<T>(f: impl FnOnce(&mut Scratch) -> T) -> T {
+    SCRATCH.with(|scratch| f(&mut scratch.borrow_mut()))
+}
+
+/// Reusable buffers for solving assignment problems
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Scratch {
+    n: usize,
+    cost: Vec<f64>,
+    // dual variables for rows and columns
+    u: Vec<f64>,
+    v: Vec<f64>,
+    // row assigned to each column, shifted by one, zero if unassigned
+    row: Vec<usize>,
+    // previous column on the current augmenting path
+    way: Vec<usize>,
+    min_slack: Vec<f64>,
+    used: Vec<bool>,
+}
+
+impl Scratch {
+    const fn new() -> Self {
+        Self {
+            n: 0,
+            cost: Vec::new(),
+            u: Vec::new(),
+            v: Vec::new(),
+            row: Vec::new(),
+            way: Vec::new(),
+            min_slack: Vec::new(),
+            used: Vec::new(),
+        }
+    }
+
+    /// Set up an `n`×`n` cost matrix filled by `cost(row, column)`
+    ///
+    /// All costs have to be finite.
+    pub(crate) fn set_costs(
+        &mut self,
+        n: usize,
+        mut cost: impl FnMut(usize, usize) -> f64,
+    ) {
+        self.n = n;
+        self.cost.clear();
+        self.cost.reserve(n * n);
+        for i in 0..n {
+            for j in 0..n {
+                self.cost.push(cost(i, j));
+            }
+        }
+    }
+
+    /// Set up an `n`×`n` cost matrix to be filled row by row
+    ///
+    /// All costs have to be finite.
+    pub(crate) fn costs_mut(&mut self, n: usize) -> &mut [f64] {
+        self.n = n;
+        self.cost.clear();
+        self.cost.resize(n * n, 0.);
+        &mut self.cost
+    }
+
+    fn cost(&self, row: usize, col: usize) -> f64 {
+        self.cost[row * self.n + col]
+    }
+
+    /// Cheap lower bound on the minimum total cost
+    ///
+    /// Every row and every column contributes at least its smallest
+    /// entry, so both the sum of row minima and the sum of column
+    /// minima are lower bounds.
+    pub(crate) fn lower_bound(&self) -> f64 {
+        if self.n == 0 {
+            return 0.;
+        }
+        let row_min: f64 = self
+            .cost
+            .chunks_exact(self.n)
+            .map(|row| row.iter().copied().fold(f64::INFINITY, f64::min))
+            .sum();
+        let col_min: f64 = self.column_minima().sum();
+        row_min.max(col_min)
+    }
+
+    fn column_minima(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.n).map(|j| {
+            (0..self.n)
+                .map(|i| self.cost(i, j))
+                .fold(f64::INFINITY, f64::min)
+        })
+    }
+
+    /// Minimum total cost of assigning each row to a distinct column
+    pub(crate) fn solve(&mut self) -> f64 {
+        let n = self.n;
+        // index 0 is an auxiliary column and row
+        self.u.clear();
+        self.u.resize(n + 1, 0.);
+        self.v.clear();
+        self.v.push(0.);
+        let col_min: Vec<_> = self.column_minima().collect();
+        self.v.extend(col_min);
+        self.row.clear();
+        self.row.resize(n + 1, 0);
+        self.way.clear();
+        self.way.resize(n + 1, 0);
+
+        for i in 1..=n {
+            self.augment(i);
+        }
+
+        (1..=n).map(|j| self.cost(self.row[j] - 1, j - 1)).sum()
+    }
+
+    // assign row `i` by finding the shortest augmenting path
+    fn augment(&mut self, i: usize) {
+        let n = self.n;
+        self.row[0] = i;
+        self.min_slack.clear();
+        self.min_slack.resize(n + 1, f64::INFINITY);
+        self.used.clear();
+        self.used.resize(n + 1, false);
+        let mut col = 0;
+        loop {
+            self.used[col] = true;
+            let i0 = self.row[col];
+            let mut delta = f64::INFINITY;
+            let mut next_col = 0;
+            for j in 1..=n {
+                if self.used[j] {
+                    continue;
+                }
+                let slack = self.cost(i0 - 1, j - 1) - self.u[i0] - self.v[j];
+                if slack < self.min_slack[j] {
+                    self.min_slack[j] = slack;
+                    self.way[j] = col;
+                }
+                if self.min_slack[j] < delta {
+                    delta = self.min_slack[j];
+                    next_col = j;
+                }
+            }
+            for j in 0..=n {
+                if self.used[j] {
+                    self.u[self.row[j]] += delta;
+                    self.v[j] -= delta;
+                } else {
+                    self.min_slack[j] -= delta;
+                }
+            }
+            col = next_col;
+            if self.row[col] == 0 {
+                break;
+            }
+        }
+        // flip the augmenting path
+        while col != 0 {
+            let prev = self.way[col];
+            self.row[col] = self.row[prev];
+            col = prev;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use permutohedron::LexicalPermutation;
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256Plus;
+
+    fn brute_force(n: usize, cost: &[f64]) -> f64 {
+        let mut perm: Vec<_> = (0..n).collect();
+        let mut min = f64::INFINITY;
+        loop {
+            let total = perm.iter().enumerate().map(|(i, j)| cost[i * n + j]);
+            min = min.min(total.sum());
+            if !perm.next_permutation() {
+                return min;
+            }
+        }
+    }
+
+    #[test]
+    fn tst_assignment() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        for n in 0..7 {
+            for _ in 0..20 {
+                let cost: Vec<f64> =
+                    (0..n * n).map(|_| rng.gen_range(0. ..10.)).collect();
+                let expected = brute_force(n, &cost);
+                let (bound, min) = with_scratch(|scratch| {
+                    scratch.set_costs(n, |i, j| cost[i * n + j]);
+                    (scratch.lower_bound(), scratch.solve())
+                });
+                assert!((min - expected).abs() < 1e-10);
+                assert!(bound <= min + 1e-10);
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+mod opt;
+
+#[cfg(feature = "multiweight")]
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use crate::opt::{parse_compr, FileFormat};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use cres::{
+    compression::Compression,
+    event::EventBuilder,
+    reader::CombinedReader,
+    sidecar::{CheckedReader, SidecarReader},
+    traits::{Rewind, Write},
+    writer::{per_input_filenames, FileWriter},
+    GIT_BRANCH, GIT_REV, VERSION,
+};
+use env_logger::Env;
+use itertools::Itertools;
+use log::{debug, info};
+use noisy_float::prelude::*;
+
+/// Combine event weights from a sidecar file with the original events
+///
+/// The sidecar file has to be created by `cres` with the `--sidecar`
+/// option. The input files have to be the same as for the original
+/// `cres` run, and passed in the same order. Events with a different
+/// weight than recorded in the sidecar file are reported as errors.
+#[derive(Debug, Parser)]
+#[clap(about, author, version)]
+struct Opt {
+    /// Output file.
+    ///
+    /// If the file name contains `{stem}` or `{index}`, a separate
+    /// output file is written for each input file. `{stem}` is
+    /// replaced by the input file name without extensions and
+    /// `{index}` by the position of the input file in the list of
+    /// input files, starting at 0. STRIPPER XML normalisation files
+    /// are not counted.
+    #[clap(long, short, value_parser)]
+    outfile: PathBuf,
+
+    /// Sidecar file with event weights.
+    #[clap(long, value_parser)]
+    sidecar: PathBuf,
+
+    /// Output format.
+    #[clap(value_enum, long, default_value_t)]
+    outformat: FileFormat,
+
+    #[clap(long, value_parser = parse_compr,
+                help = "Compress output file.
+Possible settings are 'bzip2', 'gzip', 'zstd', 'lz4'.
+Compression levels can be set with algorithm_level e.g. 'zstd_5'.
+Maximum levels are 'gzip_9', 'zstd_19', 'lz4_16'.")]
+    compression: Option<Compression>,
+
+    /// Verbosity level
+    ///
+    /// Possible values with increasing amount of output are
+    /// 'off', 'error', 'warn', 'info', 'debug', 'trace'.
+    #[clap(short, long, default_value = "Info")]
+    loglevel: String,
+
+    /// Input files
+    #[clap(name = "INFILES", value_parser)]
+    infiles: Vec<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let args = argfile::expand_args_from(
+        std::env::args_os(),
+        argfile::parse_fromfile,
+        argfile::PREFIX,
+    )
+    .with_context(|| "Failed to read argument file")?;
+    let opt = Opt::parse_from(args);
+
+    let env = Env::default().filter_or("CRES_LOG", &opt.loglevel);
+    env_logger::init_from_env(env);
+
+    if let (Some(rev), Some(branch)) = (GIT_REV, GIT_BRANCH) {
+        info!("cres-apply {VERSION} rev {rev} ({branch})");
+    } else {
+        info!("cres-apply {VERSION}");
+    }
+
+    debug!("settings: {:#?}", opt);
+
+    info!("Reading weights from {:?}", opt.sidecar);
+    let sidecar = SidecarReader::new(&opt.sidecar).with_context(|| {
+        format!("Failed to open sidecar file {:?}", opt.sidecar)
+    })?;
+    #[cfg(feature = "multiweight")]
+    let weight_names: HashSet<_> =
+        sidecar.weight_names().iter().cloned().collect();
+    let records: Vec<_> =
+        sidecar.collect::<Result<_, _>>().with_context(|| {
+            format!("Failed to read from sidecar file {:?}", opt.sidecar)
+        })?;
+    if records.windows(2).any(|r| r[0].id >= r[1].id) {
+        anyhow::bail!("Events in sidecar file are not ordered by index");
+    }
+    let events: Vec<_> = records
+        .iter()
+        .map(|record| {
+            let mut event = EventBuilder::new();
+            #[cfg(feature = "multiweight")]
+            event.weights(record.weights.iter().copied().map(n64).collect());
+            #[cfg(not(feature = "multiweight"))]
+            event.weights(n64(record.weights[0]));
+            let mut event = event.build();
+            event.id = record.id;
+            event
+        })
+        .collect();
+    info!("Read weights for {} events", events.len());
+
+    let mut reader = CombinedReader::from_files(opt.infiles)?;
+    reader.rewind()?;
+    let event_files: Vec<_> = reader.files().collect();
+    let per_input = per_input_filenames(&opt.outfile, &event_files)?;
+    if let Some(file) = per_input.iter().flatten().duplicates().next() {
+        anyhow::bail!("Several input files would be written to {file:?}. Use `{{index}}` in the output file name to distinguish them.");
+    }
+    // check that the input matches the sidecar while writing
+    let mut reader = CheckedReader::new(reader, &records);
+    let writer = FileWriter::builder()
+        .filename(opt.outfile)
+        .per_source_filenames(per_input.unwrap_or_default())
+        .format(opt.outformat.into())
+        .compression(opt.compression);
+    #[cfg(feature = "multiweight")]
+    let writer = writer.overwrite_weights(weight_names);
+    let mut writer = writer.build();
+    writer.write(&mut reader, &events)?;
+    info!("done");
+    Ok(())
+}
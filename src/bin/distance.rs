@@ -0,0 +1,240 @@
+use crate::opt::{DistanceKind, Opt, Search};
+
+use std::hash::{Hash, Hasher};
+
+use anyhow::Result;
+#[cfg(feature = "distance-plugin")]
+use cres::c_api::plugin::DistancePlugin;
+use cres::{
+    distance::{
+        Distance, EnergyMoversDistance, EuclWithScaledPt, ObservableDistance,
+        PtRapidityPhi, TypeWeights,
+    },
+    event::Event,
+    event_store::StoredEvent,
+    flat_store::FlatDistance,
+};
+use log::warn;
+use noisy_float::prelude::*;
+
+/// Distance function selected on the command line
+#[derive(Clone, Debug)]
+pub(crate) enum EventDistance {
+    EuclWithScaledPt(EuclWithScaledPt),
+    PtRapidityPhi(PtRapidityPhi),
+    Observables(ObservableDistance),
+    EnergyMovers(EnergyMoversDistance),
+    Flat(FlatDistance),
+    #[cfg(feature = "distance-plugin")]
+    Plugin(DistancePlugin),
+}
+
+impl EventDistance {
+    pub(crate) fn new(opt: &Opt) -> Result<Self> {
+        if let Some(plugin) = &opt.distance_plugin {
+            return load_plugin(plugin, opt.distance_plugin_config.as_deref());
+        }
+        let distance = match opt.distance {
+            DistanceKind::EuclWithScaledPt => {
+                let ptweight = n64(opt.ptweight);
+                let penalty = n64(opt.unmatched_penalty);
+                let mut distance = EuclWithScaledPt::new(ptweight)
+                    .with_unmatched_penalty(penalty);
+                for w in &opt.particle_weights {
+                    let weights = TypeWeights {
+                        scale: n64(w.scale),
+                        pt_weight: w.ptweight.map(n64).unwrap_or(ptweight),
+                        unmatched_penalty: w
+                            .unmatched_penalty
+                            .map(n64)
+                            .unwrap_or(penalty),
+                    };
+                    distance = distance.with_type_weights(w.pid, weights);
+                }
+                let non_metric = opt
+                    .particle_weights
+                    .iter()
+                    .filter_map(|w| w.unmatched_penalty)
+                    .chain([opt.unmatched_penalty])
+                    .any(|penalty| penalty != 1.);
+                if non_metric && opt.search == Search::Tree {
+                    warn!("Unmatched penalties other than 1 can violate the triangle inequality. The tree search may miss nearest neighbours, consider using `--search naive`.");
+                }
+                Self::EuclWithScaledPt(distance)
+            }
+            DistanceKind::PtYPhi => {
+                let [pt, y, phi] = opt.pt_y_phi_weights.map(n64);
+                Self::PtRapidityPhi(PtRapidityPhi::new(pt, y, phi))
+            }
+            DistanceKind::Observables => {
+                let mut distance =
+                    ObservableDistance::new(opt.observable_norm.into());
+                for obs in &opt.observables {
+                    distance = distance
+                        .with_observable(obs.observable, n64(obs.weight));
+                }
+                Self::Observables(distance)
+            }
+            DistanceKind::Emd => {
+                if opt.search == Search::Tree && opt.check_metric.is_none() {
+                    warn!("The energy mover's distance is only a metric if R is at least half the largest distance between particles. The tree search may miss nearest neighbours, consider using `--search naive` or `--check-metric`.");
+                }
+                let distance = EnergyMoversDistance::new(n64(opt.emd_radius))
+                    .with_beta(n64(opt.emd_beta));
+                Self::EnergyMovers(distance)
+            }
+        };
+        Ok(distance)
+    }
+}
+
+// identifies the distance function in checkpoints
+//
+// Flat stores and plugins are identified by the command line options
+// instead.
+impl Hash for EventDistance {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::EuclWithScaledPt(d) => d.hash(state),
+            Self::PtRapidityPhi(d) => d.hash(state),
+            Self::Observables(d) => d.hash(state),
+            Self::EnergyMovers(d) => d.hash(state),
+            Self::Flat(_) => {}
+            #[cfg(feature = "distance-plugin")]
+            Self::Plugin(_) => {}
+        }
+    }
+}
+
+#[cfg(feature = "distance-plugin")]
+fn load_plugin(spec: &str, config: Option<&str>) -> Result<EventDistance> {
+    use cres::c_api::plugin::DEFAULT_SYMBOL;
+    use std::path::Path;
+
+    let (path, symbol) = match spec.rsplit_once(':') {
+        Some((path, symbol)) if !symbol.is_empty() && !symbol.contains('/') => {
+            (path, symbol)
+        }
+        _ => (spec, DEFAULT_SYMBOL),
+    };
+    // Safety: the user is responsible for providing a suitable plugin
+    let plugin =
+        unsafe { DistancePlugin::load(Path::new(path), symbol, config) }?;
+    Ok(EventDistance::Plugin(plugin))
+}
+
+#[cfg(not(feature = "distance-plugin"))]
+fn load_plugin(_spec: &str, _config: Option<&str>) -> Result<EventDistance> {
+    anyhow::bail!("Cannot load distance plugins. Reinstall cres with `cargo install cres --features = distance-plugin`")
+}
+
+impl Distance for EventDistance {
+    fn distance(&self, ev1: &Event, ev2: &Event) -> N64 {
+        match self {
+            Self::EuclWithScaledPt(d) => d.distance(ev1, ev2),
+            Self::PtRapidityPhi(d) => d.distance(ev1, ev2),
+            Self::Observables(d) => d.distance(ev1, ev2),
+            Self::EnergyMovers(d) => d.distance(ev1, ev2),
+            Self::Flat(d) => d.distance(ev1, ev2),
+            #[cfg(feature = "distance-plugin")]
+            Self::Plugin(d) => d.distance(ev1, ev2),
+        }
+    }
+
+    fn distance_within(
+        &self,
+        ev1: &Event,
+        ev2: &Event,
+        max_dist: N64,
+    ) -> Option<N64> {
+        match self {
+            Self::EuclWithScaledPt(d) => d.distance_within(ev1, ev2, max_dist),
+            Self::PtRapidityPhi(d) => d.distance_within(ev1, ev2, max_dist),
+            Self::Observables(d) => d.distance_within(ev1, ev2, max_dist),
+            Self::EnergyMovers(d) => d.distance_within(ev1, ev2, max_dist),
+            Self::Flat(d) => d.distance_within(ev1, ev2, max_dist),
+            #[cfg(feature = "distance-plugin")]
+            Self::Plugin(d) => d.distance_within(ev1, ev2, max_dist),
+        }
+    }
+
+    fn lower_bound(&self, ev1: &Event, ev2: &Event) -> N64 {
+        match self {
+            Self::EuclWithScaledPt(d) => d.lower_bound(ev1, ev2),
+            Self::PtRapidityPhi(d) => d.lower_bound(ev1, ev2),
+            Self::Observables(d) => d.lower_bound(ev1, ev2),
+            Self::EnergyMovers(d) => d.lower_bound(ev1, ev2),
+            Self::Flat(d) => d.lower_bound(ev1, ev2),
+            #[cfg(feature = "distance-plugin")]
+            Self::Plugin(d) => d.lower_bound(ev1, ev2),
+        }
+    }
+}
+
+impl<'a> Distance<StoredEvent<'a>> for EventDistance {
+    fn distance(&self, ev1: &StoredEvent<'a>, ev2: &StoredEvent<'a>) -> N64 {
+        match self {
+            Self::EuclWithScaledPt(d) => d.distance(ev1, ev2),
+            Self::PtRapidityPhi(d) => d.distance(ev1, ev2),
+            Self::Observables(d) => d.distance(ev1, ev2),
+            Self::EnergyMovers(d) => d.distance(ev1, ev2),
+            Self::Flat(_) => {
+                unreachable!(
+                    "Flat event stores cannot be used with stored events"
+                )
+            }
+            #[cfg(feature = "distance-plugin")]
+            Self::Plugin(_) => {
+                unreachable!(
+                    "Distance plugins cannot be used with stored events"
+                )
+            }
+        }
+    }
+
+    fn distance_within(
+        &self,
+        ev1: &StoredEvent<'a>,
+        ev2: &StoredEvent<'a>,
+        max_dist: N64,
+    ) -> Option<N64> {
+        match self {
+            Self::EuclWithScaledPt(d) => d.distance_within(ev1, ev2, max_dist),
+            Self::PtRapidityPhi(d) => d.distance_within(ev1, ev2, max_dist),
+            Self::Observables(d) => d.distance_within(ev1, ev2, max_dist),
+            Self::EnergyMovers(d) => d.distance_within(ev1, ev2, max_dist),
+            Self::Flat(_) => {
+                unreachable!(
+                    "Flat event stores cannot be used with stored events"
+                )
+            }
+            #[cfg(feature = "distance-plugin")]
+            Self::Plugin(_) => {
+                unreachable!(
+                    "Distance plugins cannot be used with stored events"
+                )
+            }
+        }
+    }
+
+    fn lower_bound(&self, ev1: &StoredEvent<'a>, ev2: &StoredEvent<'a>) -> N64 {
+        match self {
+            Self::EuclWithScaledPt(d) => d.lower_bound(ev1, ev2),
+            Self::PtRapidityPhi(d) => d.lower_bound(ev1, ev2),
+            Self::Observables(d) => d.lower_bound(ev1, ev2),
+            Self::EnergyMovers(d) => d.lower_bound(ev1, ev2),
+            Self::Flat(_) => {
+                unreachable!(
+                    "Flat event stores cannot be used with stored events"
+                )
+            }
+            #[cfg(feature = "distance-plugin")]
+            Self::Plugin(_) => {
+                unreachable!(
+                    "Distance plugins cannot be used with stored events"
+                )
+            }
+        }
+    }
+}
@@ -1,30 +1,48 @@
+mod distance;
 mod opt;
 
 use std::cell::RefCell;
+use std::collections::HashMap;
 #[cfg(feature = "multiweight")]
 use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
 
+use crate::distance::EventDistance;
 use crate::opt::{Opt, Search};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use cres::converter::ClusteringConverter;
-use cres::reader::CombinedReader;
-use cres::writer::FileWriter;
+use cres::reader::{is_stream, CombinedReader};
+use cres::sidecar::SidecarWriter;
+use cres::writer::{per_input_filenames, FileWriter};
 use cres::{
     cell_collector::CellCollector,
-    distance::{EuclWithScaledPt, DistWrapper},
+    checkpoint::{settings_hash, Checkpointing},
+    distance::DistWrapper,
+    event_cache::{cache_key, EventCache},
+    event_store::{EventStore, StoredDistance, StoringConverter},
+    flat_store::{FlatDistance, FlatEventStore, Precision},
+    metric_check::{MetricCheck, MetricReport},
     neighbour_search::{
         NaiveNeighbourSearch, NeighbourData, NeighbourSearch, TreeSearch,
     },
     prelude::*,
+    report::CellSizeScanPoint,
+    traits::Write,
     resampler::DefaultResamplerBuilder,
     FEATURES, GIT_BRANCH, GIT_REV, VERSION,
 };
 use env_logger::Env;
-use log::{debug, info};
+use itertools::Itertools;
+use log::{debug, info, warn};
 use noisy_float::prelude::*;
+use particle_id::ParticleID;
 use rand::SeedableRng;
 use rand_xoshiro::Xoshiro256Plus;
 
@@ -52,8 +70,12 @@ fn cres_with_search<N>(opt: Opt) -> Result<()>
 where
     N: NeighbourData + Clone + Send + Sync,
     for<'x, 'y, 'z> &'x N:
-        NeighbourSearch<DistWrapper<'y, 'z, EuclWithScaledPt>>,
-    for<'x, 'y, 'z> <&'x N as NeighbourSearch<DistWrapper<'y, 'z, EuclWithScaledPt>>>::Iter:
+        NeighbourSearch<DistWrapper<'y, 'z, EventDistance>>,
+    for<'x, 'y, 'z> <&'x N as NeighbourSearch<DistWrapper<'y, 'z, EventDistance>>>::Iter:
+        Iterator<Item = (usize, N64)>,
+    for<'x, 'y, 'z> &'x N:
+        NeighbourSearch<DistWrapper<'y, 'z, StoredDistance<EventDistance>>>,
+    for<'x, 'y, 'z> <&'x N as NeighbourSearch<DistWrapper<'y, 'z, StoredDistance<EventDistance>>>>::Iter:
         Iterator<Item = (usize, N64)>,
 {
     let env = Env::default().filter_or("CRES_LOG", &opt.loglevel);
@@ -71,27 +93,24 @@ where
 
     debug!("settings: {:#?}", opt);
 
-    let reader = CombinedReader::from_files(opt.infiles)?;
+    let reader = CombinedReader::from_files(&opt.infiles)?;
+    let distance = EventDistance::new(&opt)?;
 
     let cell_collector = if opt.dumpcells {
         Some(Rc::new(RefCell::new(CellCollector::new())))
     } else {
         None
     };
-    let resampler = DefaultResamplerBuilder::default()
-        .max_cell_size(opt.max_cell_size)
-        .ptweight(opt.ptweight)
-        .strategy(opt.strategy)
-        .cell_collector(cell_collector.clone())
-        .neighbour_search::<N>()
-        .build();
-
+    let cell_ids = opt
+        .cell_ids
+        .then(|| Rc::new(RefCell::new(HashMap::new())));
     let rng = Xoshiro256Plus::seed_from_u64(opt.unweight.seed);
 
     let unweighter = Unweighter::new(opt.unweight.minweight, rng);
     #[cfg(feature = "multiweight")]
     let weights: HashSet<_> = opt.weights.into_iter().collect();
     let mut converter = ClusteringConverter::new(opt.jet_def.into())
+        .with_jet_flavours(opt.jet_flavours)
         .include_neutrinos(opt.include_neutrinos);
     #[cfg(feature = "multiweight")]
     {
@@ -100,40 +119,281 @@ where
     if opt.lepton_def.leptonalgorithm.is_some() {
         converter = converter.with_lepton_def(opt.lepton_def.into())
     }
-    let writer = FileWriter::builder()
-        .filename(opt.outfile.clone())
-        .format(opt.outformat.into())
-        .compression(opt.compression)
-        .cell_collector(cell_collector);
-    #[cfg(feature = "multiweight")]
-    let writer = writer.overwrite_weights(weights);
-    let writer = writer.build();
-
-    let mut cres = CresBuilder {
-        reader,
-        converter,
-        resampler,
-        unweighter,
-        writer,
+    if opt.photon_def.photonisolation.is_some() {
+        converter = converter.with_photon_def(opt.photon_def.into())
     }
-    .build();
-    cres.run()?;
+    if opt.missing_pt {
+        let invisible = opt.invisible.into_iter().map(ParticleID::new);
+        converter = converter.with_missing_pt(invisible)
+    }
+    let checkpointing = if let Some(file) = opt.checkpoint {
+        let settings = (
+            &distance,
+            &opt.distance_plugin,
+            &opt.distance_plugin_config,
+            opt.flat_store,
+            opt.single_precision,
+            &converter,
+        );
+        // include the identity of the input files, unless they are
+        // streams that cannot be identified
+        let settings = if opt.infiles.iter().any(is_stream) {
+            settings_hash(&(&opt.infiles, settings))
+        } else {
+            cache_key(&opt.infiles, &settings)
+                .with_context(|| "Failed to identify input files")?
+        };
+        Some(Checkpointing {
+            file,
+            interval: opt.checkpoint_interval,
+            resume: opt.resume,
+            settings,
+        })
+    } else {
+        None
+    };
+    let resampler = DefaultResamplerBuilder::default()
+        .max_cell_size(opt.max_cell_size)
+        .distance(distance.clone())
+        .strategy(opt.strategy)
+        .cell_collector(cell_collector.clone())
+        .cell_ids(cell_ids.clone())
+        .checkpointing(checkpointing)
+        .metric_check(opt.check_metric.map(|samples| MetricCheck {
+            samples,
+            seed: opt.unweight.seed,
+            naive_search_fallback: opt.naive_search_fallback,
+            ..Default::default()
+        }));
+
+    let cache = if opt.infiles.iter().any(is_stream) {
+        if opt.cache.is_some() {
+            warn!("Not caching events read from a stream");
+        }
+        None
+    } else if let Some(dir) = opt.cache {
+        let key = cache_key(&opt.infiles, &converter)
+            .with_context(|| "Failed to compute event cache key")?;
+        Some(EventCache::new(dir, key))
+    } else {
+        None
+    };
+    let event_files: Vec<_> = reader.files().collect();
+    let per_input = per_input_filenames(&opt.outfile, &event_files)?;
+    if let Some(outfiles) = &per_input {
+        if opt.sidecar.is_some() {
+            bail!("Separate output files for each input file are not supported with --sidecar");
+        }
+        if let Some(file) = outfiles.iter().duplicates().next() {
+            bail!("Several input files would be written to {file:?}. Use `{{index}}` in the output file name to distinguish them.");
+        }
+    }
+    let report_file = opt.report.then(|| {
+        if per_input.is_some() {
+            let outfile = opt.outfile.to_string_lossy();
+            let outfile = outfile.replace("{stem}", "all").replace("{index}", "all");
+            report_path(outfile.as_ref())
+        } else {
+            report_path(&opt.outfile)
+        }
+    });
+    let writer: Box<dyn Write<_, Error = _>> = if let Some(format) = opt.sidecar
+    {
+        let writer = SidecarWriter::builder()
+            .filename(opt.outfile.clone())
+            .format(format)
+            .cell_ids(cell_ids);
+        #[cfg(feature = "multiweight")]
+        let writer = writer.overwrite_weights(weights);
+        Box::new(writer.build())
+    } else {
+        let writer = FileWriter::builder()
+            .filename(opt.outfile.clone())
+            .per_source_filenames(per_input.unwrap_or_default())
+            .format(opt.outformat.into())
+            .compression(opt.compression)
+            .cell_collector(cell_collector);
+        #[cfg(feature = "multiweight")]
+        let writer = writer.overwrite_weights(weights);
+        Box::new(writer.build())
+    };
 
+    let report = if let Some(dir) = opt.out_of_core {
+        info!("Storing particle momenta in {dir:?}");
+        let store = Arc::new(EventStore::new_in(&dir).with_context(|| {
+            format!("Failed to create event store in {dir:?}")
+        })?);
+        let distance = StoredDistance::new(store.clone(), distance);
+        let resampler =
+            resampler.distance(distance).neighbour_search::<N>().build();
+        let converter = StoringConverter::new(converter, store);
+        let mut cres = CresBuilder {
+            reader,
+            converter,
+            resampler,
+            unweighter,
+            writer,
+        }
+        .build();
+        cres.run()?
+    } else if opt.flat_store {
+        let EventDistance::EuclWithScaledPt(distance) = distance else {
+            bail!("--flat-store only supports the default distance");
+        };
+        let precision = if opt.single_precision {
+            Precision::Single
+        } else {
+            Precision::Double
+        };
+        let store = Arc::new(FlatEventStore::with_precision(precision));
+        let distance =
+            EventDistance::Flat(FlatDistance::new(store.clone(), distance));
+        let resampler =
+            resampler.distance(distance).neighbour_search::<N>().build();
+        let converter = StoringConverter::new(converter, store);
+        let mut cres = CresBuilder {
+            reader,
+            converter,
+            resampler,
+            unweighter,
+            writer,
+        }
+        .build();
+        cres.run()?
+    } else {
+        let resampler = resampler.neighbour_search::<N>().build();
+        let mut cres = CresBuilder {
+            reader,
+            converter,
+            resampler,
+            unweighter,
+            writer,
+        }
+        .build();
+        if let Some(cache) = cache {
+            cres = cres.with_cache(cache);
+        }
+        if !opt.scan_max_cell_size.is_empty() {
+            let events = cres.read_events()?;
+            let resampler = CresBuilder::from(cres).resampler;
+            let scan = resampler
+                .scan_max_cell_size(&events, &opt.scan_max_cell_size);
+            return write_scan(&opt.outfile, &scan);
+        }
+        if opt.check_metric_only {
+            let events = cres.read_events()?;
+            let samples = opt.check_metric.unwrap_or_default();
+            let check = MetricCheck {
+                samples,
+                seed: opt.unweight.seed,
+                ..Default::default()
+            };
+            let report = check.check(&distance, &events);
+            return write_metric_report(&opt.outfile, &report);
+        }
+        cres.with_validation(opt.histograms).run()?
+    };
+
+    if let Some(report_file) = report_file {
+        info!("Writing run report to {report_file:?}");
+        let out = File::create(&report_file).with_context(|| {
+            format!("Failed to create report file {report_file:?}")
+        })?;
+        serde_json::to_writer_pretty(BufWriter::new(out), &report)
+            .with_context(|| "Failed to write run report")?;
+    }
+
+    if let Some(validation) = report.validation {
+        let mut failed = Vec::new();
+        for hist in validation.histograms {
+            let chi2_per_ndf = hist.chi2_per_ndf();
+            info!(
+                "{}: χ²/ndf = {:.3}/{} = {chi2_per_ndf:.3}",
+                hist.histogram, hist.chi2, hist.ndf
+            );
+            for bin in &hist.bins {
+                debug!(
+                    "[{:.3e}, {:.3e}): {:.3e} ± {:.3e} -> {:.3e} ± {:.3e}, pull {:.2}",
+                    bin.lower,
+                    bin.upper,
+                    bin.initial,
+                    bin.initial_err,
+                    bin.resampled,
+                    bin.resampled_err,
+                    bin.pull
+                );
+            }
+            if chi2_per_ndf > opt.max_chi2_per_ndf {
+                failed.push(hist.histogram);
+            }
+        }
+        if !failed.is_empty() {
+            bail!(
+                "Validation failed: χ²/ndf exceeds {} for {}",
+                opt.max_chi2_per_ndf,
+                failed.join(", ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn write_scan(outfile: &Path, scan: &[CellSizeScanPoint]) -> Result<()> {
+    info!("max cell size | neg. weight fraction | cells | median radius");
+    for point in scan {
+        info!(
+            "{:>13} | {:>20.3} | {:>5} | {:>13.3}",
+            point.max_cell_size,
+            point.neg_weight_fraction,
+            point.ncells,
+            point.median_radius
+        );
+    }
+    info!("Writing scan results to {outfile:?}");
+    let mut writer = csv::Writer::from_path(outfile)
+        .with_context(|| format!("Failed to create {outfile:?}"))?;
+    for point in scan {
+        writer.serialize(point)?;
+    }
+    writer.flush()?;
     Ok(())
 }
 
+fn write_metric_report(outfile: &Path, report: &MetricReport) -> Result<()> {
+    report.log();
+    info!("Writing metric check results to {outfile:?}");
+    let out = File::create(outfile)
+        .with_context(|| format!("Failed to create {outfile:?}"))?;
+    serde_json::to_writer_pretty(BufWriter::new(out), report)
+        .with_context(|| "Failed to write metric check results")?;
+    if !report.passed() {
+        bail!(
+            "Found {} violations of metric properties",
+            report.violations.len()
+        );
+    }
+    Ok(())
+}
+
+fn report_path(outfile: &std::path::Path) -> PathBuf {
+    let mut path = OsString::from(outfile);
+    path.push(".report.json");
+    path.into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::time::Duration;
+
     #[cfg(unix)]
     #[test]
     fn test_cres() {
-        use std::path::PathBuf;
-
         use cres::cluster::JetAlgorithm;
 
-        use crate::opt::{JetDefinition, LeptonDefinition};
+        use crate::opt::{JetDefinition, LeptonDefinition, PhotonDefinition};
 
         let opt = Opt {
             outfile: PathBuf::from("/dev/null"),
@@ -141,17 +401,52 @@ mod tests {
                 jetalgorithm: JetAlgorithm::AntiKt,
                 jetradius: 0.4,
                 jetpt: 30.,
+                jetrapidity: None,
             },
             lepton_def: LeptonDefinition {
                 leptonalgorithm: Some(JetAlgorithm::AntiKt),
                 leptonradius: Some(0.1),
                 leptonpt: Some(30.),
+                leptonrapidity: None,
+            },
+            photon_def: PhotonDefinition {
+                photonisolation: None,
+                photonpt: 0.,
             },
             max_cell_size: Some(100.),
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(30 * 60),
+            resume: false,
+            out_of_core: None,
+            cache: None,
+            flat_store: false,
+            single_precision: false,
+            sidecar: None,
+            cell_ids: false,
+            report: false,
+            scan_max_cell_size: Vec::new(),
+            histograms: Vec::new(),
+            max_chi2_per_ndf: 2.,
+            check_metric: None,
+            naive_search_fallback: false,
+            check_metric_only: false,
             infiles: vec![PathBuf::from("test_data/showered.hepmc.zst")],
             include_neutrinos: Default::default(),
+            jet_flavours: Vec::new(),
+            missing_pt: false,
+            invisible: Vec::new(),
             unweight: Default::default(),
+            distance: Default::default(),
             ptweight: Default::default(),
+            particle_weights: Vec::new(),
+            unmatched_penalty: 1.,
+            observables: Vec::new(),
+            observable_norm: Default::default(),
+            distance_plugin: None,
+            distance_plugin_config: None,
+            pt_y_phi_weights: [1., 50., 50.],
+            emd_radius: 1.,
+            emd_beta: 1.,
             dumpcells: Default::default(),
             compression: Default::default(),
             outformat: Default::default(),
@@ -1,11 +1,20 @@
 use std::fmt::{self, Display};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
-use cres::cluster::JetAlgorithm;
+use cres::cluster::{
+    JetAlgorithm, JetFlavour, PhotonIsolation, RapidityCut, RapidityKind,
+};
 use cres::compression::Compression;
+use cres::distance::Norm;
+use cres::observable::{parse_class, Observable, ObservableParseError};
 use cres::seeds::Strategy;
+use cres::sidecar::SidecarFormat;
+use cres::validation::HistogramSpec;
 
 use clap::{Parser, ValueEnum};
+use particle_id::ParticleID;
 use cres::writer::OutputFormat;
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -31,6 +40,176 @@ impl Display for UnknownStrategy {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct ParticleWeights {
+    pub(crate) pid: ParticleID,
+    pub(crate) scale: f64,
+    pub(crate) ptweight: Option<f64>,
+    pub(crate) unmatched_penalty: Option<f64>,
+}
+
+#[derive(Debug, Clone, Error)]
+pub(crate) enum ParseParticleWeightsErr {
+    #[error("Failed to parse particle weights `{0}`: expected `CLASS:SCALE[:PTWEIGHT[:PENALTY]]`")]
+    InvalidSyntax(String),
+    #[error(transparent)]
+    UnknownClass(#[from] ObservableParseError),
+    #[error("Invalid weight `{0}`")]
+    InvalidWeight(String),
+}
+
+impl FromStr for ParticleWeights {
+    type Err = ParseParticleWeightsErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ParseParticleWeightsErr::*;
+
+        let parts: Vec<_> = s.split(':').map(|p| p.trim()).collect();
+        if !(2..=4).contains(&parts.len()) {
+            return Err(InvalidSyntax(s.to_owned()));
+        }
+        let pid = parse_class(parts[0])?;
+        let weight = |idx: usize| -> Result<Option<f64>, Self::Err> {
+            match parts.get(idx) {
+                None | Some(&"") => Ok(None),
+                Some(w) => {
+                    w.parse().map(Some).map_err(|_| InvalidWeight(w.to_string()))
+                }
+            }
+        };
+        // all weights have to be non-negative
+        let non_negative = |idx: usize| -> Result<Option<f64>, Self::Err> {
+            match weight(idx)? {
+                Some(w) if w < 0. || w.is_nan() => {
+                    Err(InvalidWeight(parts[idx].to_owned()))
+                }
+                w => Ok(w),
+            }
+        };
+        let Some(scale) = non_negative(1)? else {
+            return Err(InvalidSyntax(s.to_owned()));
+        };
+        Ok(Self {
+            pid,
+            scale,
+            ptweight: non_negative(2)?,
+            unmatched_penalty: non_negative(3)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Expected three comma-separated non-negative weights, found `{0}`")]
+pub(crate) struct ParseWeightsErr(String);
+
+fn parse_pt_y_phi_weights(s: &str) -> Result<[f64; 3], ParseWeightsErr> {
+    let weights: Vec<f64> = s
+        .split(',')
+        .map(|w| match w.trim().parse() {
+            Ok(w) if w >= 0. && f64::is_finite(w) => Ok(w),
+            _ => Err(ParseWeightsErr(s.to_owned())),
+        })
+        .collect::<Result<_, _>>()?;
+    weights
+        .try_into()
+        .map_err(|_| ParseWeightsErr(s.to_owned()))
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Expected a non-negative number, found `{0}`")]
+pub(crate) struct ParseNonNegativeErr(String);
+
+fn parse_non_negative(s: &str) -> Result<f64, ParseNonNegativeErr> {
+    match s.trim().parse() {
+        Ok(x) if x >= 0. => Ok(x),
+        _ => Err(ParseNonNegativeErr(s.to_owned())),
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Expected a positive finite number, found `{0}`")]
+pub(crate) struct ParsePositiveErr(String);
+
+fn parse_positive(s: &str) -> Result<f64, ParsePositiveErr> {
+    match s.trim().parse() {
+        Ok(x) if x > 0. && f64::is_finite(x) => Ok(x),
+        _ => Err(ParsePositiveErr(s.to_owned())),
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid interval `{0}`: expected a non-negative number of minutes")]
+pub(crate) struct ParseIntervalErr(String);
+
+fn parse_minutes(s: &str) -> Result<Duration, ParseIntervalErr> {
+    let err = || ParseIntervalErr(s.to_owned());
+    let minutes: f64 = s.trim().parse().map_err(|_| err())?;
+    Duration::try_from_secs_f64(60. * minutes).map_err(|_| err())
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Failed to parse rapidity cut `{0}`: expected `[eta:]MAX[:EXCLUDED_MIN:EXCLUDED_MAX]`")]
+pub(crate) struct ParseRapidityCutErr(String);
+
+fn parse_rapidity_cut(s: &str) -> Result<RapidityCut, ParseRapidityCutErr> {
+    let err = || ParseRapidityCutErr(s.to_owned());
+    let (kind, limits) = match s.trim().strip_prefix("eta:") {
+        Some(limits) => (RapidityKind::Pseudorapidity, limits),
+        None => (RapidityKind::Rapidity, s),
+    };
+    let limits: Vec<f64> = limits
+        .split(':')
+        .map(|l| l.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| err())?;
+    let excluded = match limits[1..] {
+        [] => None,
+        [min, max] if min < max => Some([min, max]),
+        _ => return Err(err()),
+    };
+    Ok(RapidityCut {
+        kind,
+        max: limits[0],
+        excluded,
+    })
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Failed to parse photon isolation `{0}`: expected `cone:RADIUS:EPSILON` or `frixione:RADIUS:EPSILON[:EXPONENT]`")]
+pub(crate) struct ParsePhotonIsolationErr(String);
+
+fn parse_photon_isolation(
+    s: &str,
+) -> Result<PhotonIsolation, ParsePhotonIsolationErr> {
+    let err = || ParsePhotonIsolationErr(s.to_owned());
+    let (kind, params) = s.trim().split_once(':').ok_or_else(err)?;
+    let params: Vec<f64> = params
+        .split(':')
+        .map(|p| p.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| err())?;
+    match (kind, params.as_slice()) {
+        ("cone", &[radius, epsilon]) => {
+            Ok(PhotonIsolation::Cone { radius, epsilon })
+        }
+        ("frixione" | "Frixione", &[radius, epsilon]) => {
+            Ok(PhotonIsolation::Frixione {
+                radius,
+                epsilon,
+                exponent: 1.,
+            })
+        }
+        ("frixione" | "Frixione", &[radius, epsilon, exponent]) => {
+            Ok(PhotonIsolation::Frixione {
+                radius,
+                epsilon,
+                exponent,
+            })
+        }
+        _ => Err(err()),
+    }
+}
+
 #[derive(Debug, Clone, Error)]
 pub(crate) enum ParseCompressionErr {
     #[error("Unknown compression algorithm: {0}")]
@@ -118,15 +297,27 @@ pub(crate) struct JetDefinition {
     #[clap(
         short = 'a',
         long,
-        help = "Jet algorithm.\nPossible settings are 'anti-kt', 'kt', 'Cambridge-Aachen'."
+        help = "Jet algorithm.\nPossible settings are 'anti-kt', 'kt', 'Cambridge-Aachen', and the lepton-collider algorithms 'durham:n=NJETS', 'durham:ycut=YCUT', 'ee-genkt:P'."
     )]
     pub jetalgorithm: JetAlgorithm,
     /// Jet radius parameter.
+    ///
+    /// Not used by the Durham algorithm.
     #[clap(short = 'R', long)]
     pub jetradius: f64,
     #[clap(short = 'p', long)]
     /// Minimum jet transverse momentum in GeV.
+    ///
+    /// For lepton-collider algorithms, this is the minimum jet energy.
     pub jetpt: f64,
+    /// Maximum absolute jet rapidity.
+    ///
+    /// The format is `[eta:]MAX[:EXCLUDED_MIN:EXCLUDED_MAX]`. With
+    /// the `eta:` prefix, the cut is on the pseudorapidity
+    /// instead. Jets with an absolute (pseudo)rapidity between
+    /// `EXCLUDED_MIN` and `EXCLUDED_MAX` are also discarded.
+    #[clap(long, value_parser = parse_rapidity_cut)]
+    pub jetrapidity: Option<RapidityCut>,
 }
 
 impl std::convert::From<JetDefinition> for cres::cluster::JetDefinition {
@@ -135,6 +326,7 @@ impl std::convert::From<JetDefinition> for cres::cluster::JetDefinition {
             algorithm: j.jetalgorithm,
             radius: j.jetradius,
             min_pt: j.jetpt,
+            rapidity_cut: j.jetrapidity,
         }
     }
 }
@@ -153,6 +345,15 @@ pub(crate) struct LeptonDefinition {
     #[clap(long)]
     /// Minimum lepton transverse momentum in GeV.
     pub leptonpt: Option<f64>,
+    /// Maximum absolute lepton rapidity.
+    ///
+    /// The format is the same as for --jetrapidity.
+    #[clap(
+        long,
+        requires = "leptonalgorithm",
+        value_parser = parse_rapidity_cut
+    )]
+    pub leptonrapidity: Option<RapidityCut>,
 }
 
 impl std::convert::From<LeptonDefinition> for cres::cluster::JetDefinition {
@@ -161,6 +362,36 @@ impl std::convert::From<LeptonDefinition> for cres::cluster::JetDefinition {
             algorithm: l.leptonalgorithm.unwrap(),
             radius: l.leptonradius.unwrap(),
             min_pt: l.leptonpt.unwrap(),
+            rapidity_cut: l.leptonrapidity,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Parser)]
+pub(crate) struct PhotonDefinition {
+    /// Isolation criterion for photons.
+    ///
+    /// The format is `cone:RADIUS:EPSILON` or
+    /// `frixione:RADIUS:EPSILON[:EXPONENT]`, where EXPONENT defaults
+    /// to 1. Photons are isolated if the summed transverse momentum
+    /// of all other particles within RADIUS is at most EPSILON times
+    /// the photon transverse momentum, or the Frixione criterion is
+    /// fulfilled. If this option is set, leptons are only dressed
+    /// with photons within --leptonradius, and isolated photons are
+    /// kept as a separate class. All other photons are clustered into
+    /// jets.
+    #[clap(long, value_parser = parse_photon_isolation)]
+    pub photonisolation: Option<PhotonIsolation>,
+    /// Minimum transverse momentum of isolated photons in GeV.
+    #[clap(long, default_value = "0.", requires = "photonisolation")]
+    pub photonpt: f64,
+}
+
+impl std::convert::From<PhotonDefinition> for cres::cluster::PhotonDefinition {
+    fn from(p: PhotonDefinition) -> Self {
+        Self {
+            min_pt: p.photonpt,
+            isolation: p.photonisolation.unwrap(),
         }
     }
 }
@@ -172,6 +403,71 @@ pub(crate) enum Search {
     Naive,
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub(crate) enum DistanceKind {
+    /// Distance defined in arXiv:2109.07851, with --ptweight.
+    #[default]
+    EuclWithScaledPt,
+    /// Distance in transverse momentum, rapidity, and azimuthal angle, with --pt-y-phi-weights.
+    PtYPhi,
+    /// Distance between the values of the observables given with --observable.
+    Observables,
+    /// Energy mover's distance, with --emd-radius and --emd-beta.
+    Emd,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub(crate) enum ObservableNorm {
+    /// Sum of the absolute differences.
+    L1,
+    /// Euclidean distance.
+    #[default]
+    L2,
+}
+
+impl From<ObservableNorm> for Norm {
+    fn from(norm: ObservableNorm) -> Self {
+        match norm {
+            ObservableNorm::L1 => Norm::L1,
+            ObservableNorm::L2 => Norm::L2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct WeightedObservable {
+    pub(crate) observable: Observable,
+    pub(crate) weight: f64,
+}
+
+#[derive(Debug, Clone, Error)]
+pub(crate) enum ParseWeightedObservableErr {
+    #[error(transparent)]
+    ObservableErr(#[from] ObservableParseError),
+    #[error("Invalid observable weight `{0}`")]
+    WeightErr(String),
+}
+
+impl FromStr for WeightedObservable {
+    type Err = ParseWeightedObservableErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (observable, weight) = match s.rsplit_once(':') {
+            Some((observable, weight)) => {
+                let weight = weight.trim().parse().map_err(|_| {
+                    ParseWeightedObservableErr::WeightErr(weight.to_owned())
+                })?;
+                (observable, weight)
+            }
+            None => (s, 1.),
+        };
+        Ok(Self {
+            observable: observable.parse()?,
+            weight,
+        })
+    }
+}
+
 #[derive(Debug, Default, Copy, Clone, Parser)]
 pub(crate) struct UnweightOpt {
     /// Weight below which events are unweighted. '0' means no unweighting.
@@ -188,6 +484,7 @@ pub(crate) struct UnweightOpt {
 pub(crate) enum FileFormat {
     #[default]
     HepMC2,
+    HepMC3,
     #[cfg(feature = "lhef")]
     Lhef,
     #[cfg(feature = "ntuple")]
@@ -201,6 +498,7 @@ impl From<FileFormat> for OutputFormat {
     fn from(source: FileFormat) -> Self {
         match source {
             FileFormat::HepMC2 => OutputFormat::HepMC2,
+            FileFormat::HepMC3 => OutputFormat::HepMC3,
             #[cfg(feature = "lhef")]
             FileFormat::Lhef => OutputFormat::Lhef,
             #[cfg(feature = "ntuple")]
@@ -215,6 +513,13 @@ impl From<FileFormat> for OutputFormat {
 #[clap(about, author, version)]
 pub(crate) struct Opt {
     /// Output file.
+    ///
+    /// If the file name contains `{stem}` or `{index}`, a separate
+    /// output file is written for each input file. `{stem}` is
+    /// replaced by the input file name without extensions and
+    /// `{index}` by the position of the input file in the list of
+    /// input files, starting at 0. STRIPPER XML normalisation files
+    /// are not counted.
     #[clap(long, short, value_parser)]
     pub(crate) outfile: PathBuf,
 
@@ -224,17 +529,131 @@ pub(crate) struct Opt {
     #[clap(flatten)]
     pub(crate) lepton_def: LeptonDefinition,
 
+    #[clap(flatten)]
+    pub(crate) photon_def: PhotonDefinition,
+
     /// Include neutrinos in the distance measure
     #[clap(long, default_value_t)]
     pub(crate) include_neutrinos: bool,
 
+    /// Replace invisible particles by their missing transverse momentum.
+    ///
+    /// The momenta of all neutrinos and particles given with
+    /// --invisible are summed into a single pseudo particle of class
+    /// `met` without longitudinal momentum.
+    #[clap(long, default_value_t, conflicts_with = "include_neutrinos")]
+    pub(crate) missing_pt: bool,
+
+    /// Comma-separated list of ids of additional invisible particles.
+    ///
+    /// Antiparticles are also invisible.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        allow_negative_numbers = true,
+        requires = "missing_pt"
+    )]
+    pub(crate) invisible: Vec<i32>,
+
+    /// Comma-separated list of jet flavours to distinguish.
+    ///
+    /// Possible flavours are `b` and `c`. Jets are labelled by ghost
+    /// association with bottom and charm hadrons, or outgoing quarks
+    /// at parton level. Jets of the given flavours are treated as
+    /// separate particle classes `bjet` and `cjet`, and all remaining
+    /// jets as `jet`.
+    #[clap(long, value_delimiter = ',')]
+    pub(crate) jet_flavours: Vec<JetFlavour>,
+
     #[clap(flatten)]
     pub(crate) unweight: UnweightOpt,
 
+    /// Distance function between events.
+    #[clap(value_enum, long, default_value_t)]
+    pub(crate) distance: DistanceKind,
+
     /// Weight of transverse momentum when calculating particle momentum distances.
     #[clap(long, default_value = "0.")]
     pub(crate) ptweight: f64,
 
+    /// Weights for distances between particles of a given type.
+    ///
+    /// The format is `CLASS:SCALE[:PTWEIGHT[:PENALTY]]`, where
+    /// `CLASS` is `jet`, `bjet`, `cjet`, `lepton`, `photon`, `met`, or
+    /// a particle id. All distances between particles of this type are
+    /// multiplied by `SCALE`. `PTWEIGHT` and `PENALTY` replace
+    /// --ptweight and --unmatched-penalty for this type and have to be
+    /// non-negative, as does `SCALE`. This option can be given several
+    /// times and only applies to the `eucl-with-scaled-pt` distance.
+    #[clap(long = "particle-weight")]
+    pub(crate) particle_weights: Vec<ParticleWeights>,
+
+    /// Factor for the contribution of particles without partner in the other event.
+    ///
+    /// Values other than 1 can violate the triangle inequality, so that
+    /// the default tree search may miss nearest neighbours. Use
+    /// `--search naive` in this case.
+    #[clap(long, default_value = "1.", value_parser = parse_non_negative)]
+    pub(crate) unmatched_penalty: f64,
+
+    /// Observable for the `observables` distance.
+    ///
+    /// The format is `OBSERVABLE[:WEIGHT]`, for example
+    /// `m(jet,1,jet,2):0.5`. The default weight is 1. Supported
+    /// observables are `pt(CLASS,N)`, `y(CLASS,N)`,
+    /// `m(CLASS,N,CLASS,M)`, `dy(CLASS,N,CLASS,M)`, `ht(CLASS)`, and
+    /// `n(CLASS)`, where `CLASS` is `jet`, `bjet`, `cjet`, `lepton`,
+    /// `photon`, `met`, or a particle id and particles are numbered by
+    /// decreasing transverse momentum. This option can be given several times.
+    #[clap(long = "observable", required_if_eq("distance", "observables"))]
+    pub(crate) observables: Vec<WeightedObservable>,
+
+    /// Norm for the `observables` distance.
+    #[clap(value_enum, long, default_value_t)]
+    pub(crate) observable_norm: ObservableNorm,
+
+    /// Load the distance function from a shared library.
+    ///
+    /// The format is `PATH[:SYMBOL]`. The library has to export a
+    /// thread-safe function `SYMBOL` (default: `cres_distance`) with
+    /// the signature `double SYMBOL(void* data, EventView const* ev1,
+    /// EventView const* ev2)`, see the C API header `cres.h`. If it
+    /// also exports `int32_t SYMBOL_init(char const* config, void**
+    /// data)`, this is called before resampling. Likewise, `void
+    /// SYMBOL_teardown(void* data)` is called at the end. Requires the
+    /// `distance-plugin` feature.
+    #[clap(long, conflicts_with_all = ["distance", "out_of_core"])]
+    pub(crate) distance_plugin: Option<String>,
+
+    /// Configuration string passed to the initialisation function of
+    /// the distance plugin.
+    #[clap(long, requires = "distance_plugin")]
+    pub(crate) distance_plugin_config: Option<String>,
+
+    /// Comma-separated weights of transverse momentum, rapidity, and
+    /// azimuthal angle for the `pt-y-phi` distance.
+    ///
+    /// The rapidity and angle weights are in GeV. All weights have to
+    /// be finite and non-negative.
+    #[clap(long, default_value = "1,50,50", value_parser = parse_pt_y_phi_weights)]
+    pub(crate) pt_y_phi_weights: [f64; 3],
+
+    /// Radius parameter R for the `emd` distance.
+    ///
+    /// Moving transverse momentum by a distance R in the
+    /// rapidity-azimuth plane costs as much as removing it. The
+    /// distance is only a metric for β = 1 and R at least half the
+    /// largest distance between any two particles, which is usually
+    /// not the case for the default R = 1. Otherwise, the default tree
+    /// search may miss nearest neighbours, so `--search naive` or
+    /// `--check-metric` with `--naive-search-fallback` should be used.
+    #[clap(long, default_value = "1.", value_parser = parse_positive)]
+    pub(crate) emd_radius: f64,
+
+    /// Angular exponent β for the `emd` distance.
+    #[clap(long, default_value = "1.", value_parser = parse_positive)]
+    pub(crate) emd_beta: f64,
+
     /// Whether to dump selected cells of interest.
     #[clap(short = 'd', long)]
     pub(crate) dumpcells: bool,
@@ -295,6 +714,144 @@ variable."
     #[clap(long)]
     pub(crate) max_cell_size: Option<f64>,
 
+    /// Periodically save the resampling progress to this file.
+    #[clap(long, value_parser)]
+    pub(crate) checkpoint: Option<PathBuf>,
+
+    /// Minimum time between checkpoints in minutes.
+    #[clap(long, default_value = "30", value_parser = parse_minutes)]
+    pub(crate) checkpoint_interval: Duration,
+
+    /// Resume from the file given by --checkpoint.
+    ///
+    /// The remaining options and the input files have to be the same
+    /// as for the original run. Input files are identified by their
+    /// path, size, and modification time. If the checkpoint file does
+    /// not exist yet, resampling starts from scratch. The output is
+    /// identical to an uninterrupted run when using a single thread.
+    /// Information on the cells constructed before the checkpoint is
+    /// not saved, so this cannot be combined with options that output
+    /// it.
+    #[clap(
+        long,
+        requires = "checkpoint",
+        conflicts_with_all = ["dumpcells", "cell_ids", "report"]
+    )]
+    pub(crate) resume: bool,
+
+    /// Keep particle momenta in temporary files in the given directory.
+    ///
+    /// This greatly reduces memory usage for large event samples,
+    /// at the cost of slower resampling. The files are removed
+    /// automatically.
+    #[clap(long, value_parser)]
+    pub(crate) out_of_core: Option<PathBuf>,
+
+    /// Cache converted events in the given directory.
+    ///
+    /// Later runs with the same input files and the same jet and
+    /// lepton definitions read the events from the cache instead of
+    /// clustering them again.
+    #[clap(long, value_parser, conflicts_with = "out_of_core")]
+    pub(crate) cache: Option<PathBuf>,
+
+    /// Keep particle momenta in a single contiguous buffer.
+    ///
+    /// This speeds up the computation of distances with vectorised
+    /// instructions. Only the default distance is supported.
+    #[clap(
+        long,
+        conflicts_with_all = ["out_of_core", "cache", "distance_plugin", "scan_max_cell_size", "check_metric_only"]
+    )]
+    pub(crate) flat_store: bool,
+
+    /// Store particle momenta in single precision.
+    ///
+    /// This halves the memory needed for the momenta. Distances are
+    /// still computed in double precision.
+    #[clap(long, requires = "flat_store")]
+    pub(crate) single_precision: bool,
+
+    /// Only write event weights to the output file.
+    ///
+    /// Instead of the full events, the output file contains the
+    /// index of each event in the input together with its original
+    /// and new weights. Possible formats are 'csv' and 'binary'. Use
+    /// `cres-apply` to combine the weights with the input events.
+    #[clap(long)]
+    pub(crate) sidecar: Option<SidecarFormat>,
+
+    /// Include cell ids in the weights-only output.
+    #[clap(long, requires = "sidecar")]
+    pub(crate) cell_ids: bool,
+
+    /// Write a summary of the run in JSON format.
+    ///
+    /// The summary is written to the output file name with
+    /// `.report.json` appended. For separate output files for each
+    /// input file, `{stem}` and `{index}` in the output file name are
+    /// replaced by `all`.
+    #[clap(long)]
+    pub(crate) report: bool,
+
+    /// Only scan the given comma-separated maximum cell sizes.
+    ///
+    /// For each maximum cell size, the events are resampled
+    /// separately and the remaining negative weight fraction, the
+    /// number of cells, and the median cell radius are reported. The
+    /// results are written to the output file in CSV format instead
+    /// of the events.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        conflicts_with_all = ["out_of_core", "checkpoint", "sidecar", "report"]
+    )]
+    pub(crate) scan_max_cell_size: Vec<f64>,
+
+    /// Compare a histogram before and after resampling.
+    ///
+    /// The format is `OBSERVABLE:NBINS:MIN:MAX`, for example
+    /// `pt(jet,1):50:0:500`. Supported observables are `pt(CLASS,N)`,
+    /// `y(CLASS,N)`, `m(CLASS,N,CLASS,M)`, `dy(CLASS,N,CLASS,M)`,
+    /// `ht(CLASS)`, and `n(CLASS)`, where `CLASS` is `jet`, `bjet`,
+    /// `cjet`, `lepton`, `photon`, `met`, or a particle id. This option
+    /// can be given several times.
+    #[clap(long = "validate", conflicts_with_all = ["out_of_core", "flat_store"])]
+    pub(crate) histograms: Vec<HistogramSpec>,
+
+    /// Maximum χ²/ndf for the histograms set with `--validate`.
+    ///
+    /// If any histogram exceeds this value, cres exits with an
+    /// error after writing the output.
+    #[clap(long, default_value_t = 2.)]
+    pub(crate) max_chi2_per_ndf: f64,
+
+    /// Check the metric properties of the distance for the given
+    /// number of random event triples before resampling.
+    ///
+    /// Violations of identity, non-negativity, symmetry, and the
+    /// triangle inequality are reported together with the ids of the
+    /// offending events.
+    #[clap(long, value_name = "SAMPLES")]
+    pub(crate) check_metric: Option<usize>,
+
+    /// Use the naive nearest neighbour search if `--check-metric`
+    /// finds violations of metric properties.
+    #[clap(long, requires = "check_metric")]
+    pub(crate) naive_search_fallback: bool,
+
+    /// Only run the check requested with `--check-metric`.
+    ///
+    /// The results are written to the output file in JSON format
+    /// instead of the events. If any violations are found, cres
+    /// exits with an error.
+    #[clap(
+        long,
+        requires = "check_metric",
+        conflicts_with_all = ["naive_search_fallback", "out_of_core", "checkpoint", "sidecar", "scan_max_cell_size", "report"]
+    )]
+    pub(crate) check_metric_only: bool,
+
     /// Comma-separated list of weights to include in the resampling
     ///
     /// In addition to the main event weight, weights with the given
@@ -306,6 +863,9 @@ variable."
     pub(crate) weights: Vec<String>,
 
     /// Input files
+    ///
+    /// Use `-` to read from standard input. Input from standard input
+    /// or named pipes is copied to a temporary file first.
     #[clap(name = "INFILES", value_parser)]
     pub(crate) infiles: Vec<PathBuf>,
 }
@@ -322,6 +882,7 @@ impl Opt {
             leptonalgorithm,
             leptonpt,
             leptonradius,
+            ..
         } = &self.lepton_def;
         match (leptonalgorithm, leptonpt, leptonradius) {
             (Some(_), Some(_), Some(_)) => Ok(self),
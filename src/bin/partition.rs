@@ -176,6 +176,12 @@ fn main() -> Result<()> {
                 .collect();
             Writers::HepMC(writers?)
         }
+        FileFormat::HepMC3 => {
+            let writers: Result<Vec<_>, _> = outfiles
+                .map(|f| cres::hepmc3::Writer::try_new(&f, opt.compression))
+                .collect();
+            Writers::HepMC3(writers?)
+        }
         #[cfg(feature = "lhef")]
         FileFormat::Lhef => {
             let writers: Result<Vec<_>, _> = outfiles
@@ -215,6 +221,13 @@ fn main() -> Result<()> {
                 }
             }
         }
+        Writers::HepMC3(writers) => {
+            for writer in writers {
+                if let Err(err) = writer.finish() {
+                    error!("{err}")
+                }
+            }
+        }
         #[cfg(feature = "lhef")]
         Writers::Lhef(writers) => {
             for writer in writers {
@@ -240,6 +253,7 @@ fn main() -> Result<()> {
 
 enum Writers {
     HepMC(Vec<cres::hepmc2::Writer<Box<dyn Write>>>),
+    HepMC3(Vec<cres::hepmc3::Writer<Box<dyn Write>>>),
     #[cfg(feature = "lhef")]
     Lhef(Vec<cres::lhef::Writer<Box<dyn Write>>>),
     #[cfg(feature = "ntuple")]
@@ -254,6 +268,9 @@ impl Writers {
             Writers::HepMC(writers) => {
                 writers[idx].write(event).map_err(|e| e.into())
             }
+            Writers::HepMC3(writers) => {
+                writers[idx].write(event).map_err(|e| e.into())
+            }
             #[cfg(feature = "lhef")]
             Writers::Lhef(writers) => {
                 writers[idx].write(event).map_err(|e| e.into())
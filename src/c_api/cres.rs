@@ -4,7 +4,9 @@ use crate::c_api::distance::DistanceFn;
 use crate::c_api::error::LAST_ERROR;
 use crate::cluster;
 use crate::converter::ClusteringConverter;
-use crate::distance::{Distance, EuclWithScaledPt, DistWrapper};
+use crate::distance::{
+    self, Distance, DistWrapper, EnergyMoversDistance, EuclWithScaledPt,
+};
 use crate::prelude::{CresBuilder, NO_UNWEIGHTING};
 use crate::reader::CombinedReader;
 use crate::resampler::ResamplerBuilder;
@@ -20,8 +22,9 @@ use std::os::raw::{c_char, c_double};
 use std::os::unix::ffi::OsStrExt;
 
 use anyhow::{anyhow, Error};
-use log::debug;
+use log::{debug, warn};
 use noisy_float::prelude::*;
+use particle_id::ParticleID;
 
 /// Resampling options
 #[repr(C)]
@@ -48,6 +51,43 @@ pub struct Opt {
     /// it corresponds to the τ parameter of
     /// [arXiv:2109.07851](https://arxiv.org/abs/2109.07851)
     ptweight: c_double,
+    /// Weights for particles of specific types
+    ///
+    /// This parameter is ignored when using a custom distance. It can
+    /// be `NULL` if `n_type_weights` is zero.
+    type_weights: *const TypeWeights,
+    /// Number of entries in `type_weights`
+    n_type_weights: usize,
+    /// Factor for the contribution of particles without partner
+    ///
+    /// This parameter is ignored when using a custom distance.
+    /// Otherwise, the contribution of each particle without partner in
+    /// the other event is multiplied by this factor. Set to 1 for the
+    /// distance from [arXiv:2109.07851](https://arxiv.org/abs/2109.07851)
+    ///
+    /// Negative values are rejected. Values other than 1 can violate
+    /// the triangle inequality, so `neighbour_search` should be set to
+    /// `Naive`.
+    unmatched_penalty: c_double,
+    /// Which built-in distance function to use
+    ///
+    /// This parameter is ignored when using a custom distance.
+    builtin_distance: BuiltinDistance,
+    /// Radius parameter R of the energy mover's distance
+    ///
+    /// This parameter is only used for the built-in `EnergyMovers`
+    /// distance. Moving transverse momentum by a distance R in the
+    /// rapidity-azimuth plane costs as much as removing it. Has to be
+    /// positive and finite.
+    emd_radius: c_double,
+    /// Angular exponent β of the energy mover's distance
+    ///
+    /// This parameter is only used for the built-in `EnergyMovers`
+    /// distance. The distance is only a metric if β = 1 and
+    /// `emd_radius` is at least half the largest distance θ between
+    /// any two particles. Otherwise, `neighbour_search` should be set
+    /// to `Naive`. Has to be positive and finite.
+    emd_beta: c_double,
     /// Jet definition
     jet_def: JetDefinition,
     /// Algorithm for finding nearest-neigbour events,
@@ -58,6 +98,58 @@ pub struct Opt {
     max_cell_size: c_double,
 }
 
+/// Built-in distance functions
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum BuiltinDistance {
+    /// The distance from [arXiv:2109.07851](https://arxiv.org/abs/2109.07851)
+    ///
+    /// Uses the `ptweight`, `type_weights`, and `unmatched_penalty`
+    /// parameters.
+    EuclWithScaledPt,
+    /// The [energy mover's distance](https://arxiv.org/abs/1902.02346)
+    ///
+    /// Uses the `emd_radius` and `emd_beta` parameters.
+    EnergyMovers,
+}
+
+/// Distance weights for particles of a given type
+///
+/// Jets have the particle id 81 and dressed leptons the id 82
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct TypeWeights {
+    /// Particle id
+    pub pid: i32,
+    /// Factor for all distances between particles of this type
+    ///
+    /// Negative values are rejected.
+    pub scale: c_double,
+    /// Replaces `ptweight` for particles of this type
+    ///
+    /// Negative values are rejected.
+    pub ptweight: c_double,
+    /// Replaces `unmatched_penalty` for particles of this type
+    ///
+    /// The same restrictions as for `unmatched_penalty` apply.
+    pub unmatched_penalty: c_double,
+}
+
+impl From<TypeWeights> for distance::TypeWeights {
+    fn from(w: TypeWeights) -> Self {
+        Self {
+            scale: n64(w.scale as f64),
+            pt_weight: n64(w.ptweight as f64),
+            unmatched_penalty: n64(w.unmatched_penalty as f64),
+        }
+    }
+}
+
+/// Jet definition
+///
+/// A zero-initialised rapidity cut, i.e. `max_rapidity`,
+/// `excluded_min_rapidity`, and `excluded_max_rapidity` all set to
+/// zero, accepts jets at any rapidity.
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct JetDefinition {
@@ -67,6 +159,46 @@ pub struct JetDefinition {
     pub radius: c_double,
     /// Minimum jet transverse momentum
     pub min_pt: c_double,
+    /// Variable for the acceptance cut
+    pub rapidity_kind: RapidityKind,
+    /// Maximum absolute (pseudo)rapidity
+    ///
+    /// Set to 0 or INFINITY to accept jets at any rapidity
+    pub max_rapidity: c_double,
+    /// Lower end of the excluded absolute (pseudo)rapidity range
+    pub excluded_min_rapidity: c_double,
+    /// Upper end of the excluded absolute (pseudo)rapidity range
+    ///
+    /// Set to `excluded_min_rapidity` or below to exclude no range
+    pub excluded_max_rapidity: c_double,
+    /// Exponent p for the `EeGenKt` algorithm
+    pub exponent: c_double,
+    /// Number of exclusive jets for the `Durham` algorithm
+    ///
+    /// Set to 0 to cluster up to `y_cut` instead
+    pub n_jets: usize,
+    /// Resolution parameter for exclusive clustering with the `Durham`
+    /// algorithm
+    pub y_cut: c_double,
+}
+
+/// Rapidity variable used in acceptance cuts
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum RapidityKind {
+    /// Rapidity y
+    Rapidity,
+    /// Pseudorapidity η
+    Pseudorapidity,
+}
+
+impl From<RapidityKind> for cluster::RapidityKind {
+    fn from(kind: RapidityKind) -> Self {
+        match kind {
+            RapidityKind::Rapidity => Self::Rapidity,
+            RapidityKind::Pseudorapidity => Self::Pseudorapidity,
+        }
+    }
 }
 
 /// Nearest-neighbour search algorithms
@@ -81,10 +213,41 @@ pub enum Search {
 
 impl From<JetDefinition> for cluster::JetDefinition {
     fn from(j: JetDefinition) -> Self {
+        let [min, max] = [j.excluded_min_rapidity, j.excluded_max_rapidity];
+        let excluded = (min < max).then_some([min as f64, max as f64]);
+        // zero means no maximum, so that a zero-initialised
+        // definition does not reject all jets
+        let max = (j.max_rapidity > 0. && j.max_rapidity.is_finite())
+            .then_some(j.max_rapidity as f64);
+        let has_cut = max.is_some() || excluded.is_some();
+        let rapidity_cut = has_cut.then(|| cluster::RapidityCut {
+            kind: j.rapidity_kind.into(),
+            max: max.unwrap_or(f64::INFINITY),
+            excluded,
+        });
+        let algorithm = match j.algorithm {
+            JetAlgorithm::AntiKt => cluster::JetAlgorithm::AntiKt,
+            JetAlgorithm::CambridgeAachen => {
+                cluster::JetAlgorithm::CambridgeAachen
+            }
+            JetAlgorithm::Kt => cluster::JetAlgorithm::Kt,
+            JetAlgorithm::Durham => {
+                let mode = if j.n_jets > 0 {
+                    cluster::ExclusiveMode::NJets(j.n_jets)
+                } else {
+                    cluster::ExclusiveMode::YCut(j.y_cut as f64)
+                };
+                cluster::JetAlgorithm::Durham(mode)
+            }
+            JetAlgorithm::EeGenKt => {
+                cluster::JetAlgorithm::EeGenKt(j.exponent as f64)
+            }
+        };
         Self {
-            algorithm: j.algorithm.into(),
+            algorithm,
             radius: j.radius as f64,
             min_pt: j.min_pt as f64,
+            rapidity_cut,
         }
     }
 }
@@ -99,17 +262,15 @@ pub enum JetAlgorithm {
     CambridgeAachen,
     /// The [kt](https://arxiv.org/abs/hep-ph/9305266) algorithm
     Kt,
-}
-
-impl From<JetAlgorithm> for cluster::JetAlgorithm {
-    fn from(j: JetAlgorithm) -> Self {
-        use crate::cluster::JetAlgorithm::*;
-        match j {
-            JetAlgorithm::AntiKt => AntiKt,
-            JetAlgorithm::CambridgeAachen => CambridgeAachen,
-            JetAlgorithm::Kt => Kt,
-        }
-    }
+    /// The e⁺e⁻ [Durham](https://doi.org/10.1016/0370-2693(91)90214-M) algorithm
+    ///
+    /// Clusters exclusively into `n_jets` jets or up to `y_cut`. The
+    /// jet radius is not used and `min_pt` is a minimum jet energy.
+    Durham,
+    /// The generalised e⁺e⁻ kt algorithm with exponent `exponent`
+    ///
+    /// `min_pt` is a minimum jet energy.
+    EeGenKt,
 }
 
 /// Run the cell resampler with the given options
@@ -137,13 +298,70 @@ pub extern "C" fn cres_run(opt: &Opt) -> i32 {
 }
 
 fn cres_run_internal(opt: &Opt) -> Result<(), Error> {
-    if opt.distance.is_null() {
-        debug!("Using built-in distance function");
-        cres_run_with_dist(opt, EuclWithScaledPt::new(n64(opt.ptweight)))
-    } else {
+    if !opt.distance.is_null() {
         let distance = unsafe { *opt.distance };
         debug!("Using custom distance function {distance:?}");
-        cres_run_with_dist(opt, distance)
+        return cres_run_with_dist(opt, distance);
+    }
+    debug!(
+        "Using built-in distance function {:?}",
+        opt.builtin_distance
+    );
+    match opt.builtin_distance {
+        BuiltinDistance::EuclWithScaledPt => {
+            let type_weights = if opt.n_type_weights > 0 {
+                unsafe {
+                    std::slice::from_raw_parts(
+                        opt.type_weights,
+                        opt.n_type_weights,
+                    )
+                }
+            } else {
+                &[]
+            };
+            let negative = |x: f64| x < 0. || x.is_nan();
+            let penalties = || {
+                let type_penalties =
+                    type_weights.iter().map(|w| w.unmatched_penalty);
+                std::iter::once(opt.unmatched_penalty).chain(type_penalties)
+            };
+            if penalties().any(negative) {
+                return Err(anyhow!("Negative unmatched penalty"));
+            }
+            if type_weights.iter().any(|w| negative(w.scale)) {
+                return Err(anyhow!("Negative type weight scale"));
+            }
+            if type_weights.iter().any(|w| negative(w.ptweight)) {
+                return Err(anyhow!("Negative type weight ptweight"));
+            }
+            let non_metric = penalties().any(|penalty| penalty != 1.);
+            if non_metric && matches!(opt.neighbour_search, Search::Tree) {
+                warn!("Unmatched penalties other than 1 can violate the triangle inequality. The tree search may miss nearest neighbours, consider using the naive search.");
+            }
+            let mut distance = EuclWithScaledPt::new(n64(opt.ptweight))
+                .with_unmatched_penalty(n64(opt.unmatched_penalty));
+            for w in type_weights {
+                let pid = ParticleID::new(w.pid);
+                distance = distance.with_type_weights(pid, (*w).into());
+            }
+            cres_run_with_dist(opt, distance)
+        }
+        BuiltinDistance::EnergyMovers => {
+            let positive = |x: f64| x > 0. && x.is_finite();
+            if !positive(opt.emd_radius) {
+                return Err(anyhow!("Non-positive EMD radius"));
+            }
+            if !positive(opt.emd_beta) {
+                return Err(anyhow!("Non-positive EMD exponent"));
+            }
+            if matches!(opt.neighbour_search, Search::Tree) {
+                warn!("The energy mover's distance is only a metric if the radius is at least half the largest distance between particles. The tree search may miss nearest neighbours, consider using the naive search.");
+            }
+            let distance =
+                EnergyMoversDistance::new(n64(opt.emd_radius as f64))
+                    .with_beta(n64(opt.emd_beta as f64));
+            cres_run_with_dist(opt, distance)
+        }
     }
 }
 
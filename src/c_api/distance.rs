@@ -39,31 +39,41 @@ unsafe impl Sync for DistanceFn {}
 impl Distance for DistanceFn {
     fn distance(&self, ev1: &Event, ev2: &Event) -> N64 {
         trace!("Compute distance between {:?} and {:?}", ev1, ev2);
-        let type_sets1 = extract_typesets(ev1);
-        let type_set_views1: Vec<_> =
-            type_sets1.iter().map(TypeSet::view).collect();
-        let event_view1 = EventView {
-            id: ev1.id(),
-            weights: ev1.weights.data_ptr() as *const f64,
-            n_weights: ev1.n_weights(),
-            type_sets: type_set_views1.as_ptr(),
-            n_type_sets: type_set_views1.len(),
-        };
-        let type_sets2 = extract_typesets(ev2);
-        let type_set_views2: Vec<_> =
-            type_sets2.iter().map(TypeSet::view).collect();
-        let event_view2 = EventView {
-            id: ev2.id(),
-            weights: ev2.weights.data_ptr() as *const f64,
-            n_weights: ev2.n_weights(),
-            type_sets: type_set_views2.as_ptr(),
-            n_type_sets: type_set_views2.len(),
-        };
-        let dist = unsafe { (self.fun)(self.data, &event_view1, &event_view2) };
+        let dist = with_event_views(ev1, ev2, |ev1, ev2| unsafe {
+            (self.fun)(self.data, ev1, ev2)
+        });
         n64(dist)
     }
 }
 
+/// Call `f` with views into the events `ev1` and `ev2`
+pub(crate) fn with_event_views<F>(ev1: &Event, ev2: &Event, f: F) -> c_double
+where
+    F: FnOnce(&EventView, &EventView) -> c_double,
+{
+    let type_sets1 = extract_typesets(ev1);
+    let type_set_views1: Vec<_> =
+        type_sets1.iter().map(TypeSet::view).collect();
+    let event_view1 = EventView {
+        id: ev1.id(),
+        weights: ev1.weights.data_ptr() as *const f64,
+        n_weights: ev1.n_weights(),
+        type_sets: type_set_views1.as_ptr(),
+        n_type_sets: type_set_views1.len(),
+    };
+    let type_sets2 = extract_typesets(ev2);
+    let type_set_views2: Vec<_> =
+        type_sets2.iter().map(TypeSet::view).collect();
+    let event_view2 = EventView {
+        id: ev2.id(),
+        weights: ev2.weights.data_ptr() as *const f64,
+        n_weights: ev2.n_weights(),
+        type_sets: type_set_views2.as_ptr(),
+        n_type_sets: type_set_views2.len(),
+    };
+    f(&event_view1, &event_view2)
+}
+
 fn extract_typesets(ev: &Event) -> Vec<TypeSet> {
     ev.outgoing()
         .iter()
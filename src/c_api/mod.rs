@@ -7,3 +7,5 @@ pub mod distance;
 pub mod error;
 pub mod event;
 pub mod log;
+#[cfg(feature = "distance-plugin")]
+pub mod plugin;
@@ -0,0 +1,174 @@
+//! Distance functions loaded from shared libraries
+//!
+//! A plugin is a shared library exporting a thread-safe distance
+//! function
+//! ```c
+//! double NAME(void* data, EventView const* ev1, EventView const* ev2);
+//! ```
+//! where `NAME` defaults to `cres_distance`. Optionally, the library
+//! can also export
+//! ```c
+//! int32_t NAME_init(char const* config, void** data);
+//! void NAME_teardown(void* data);
+//! ```
+//! `NAME_init` is called once after loading the library with the
+//! user-defined configuration string and can set `data`, which is
+//! then passed to every call of the distance function. It should
+//! return `0` on success. `NAME_teardown` is called once before
+//! unloading the library.
+//!
+//! See `examples/distance_plugin.c` for an example.
+use std::ffi::{c_void, CString, NulError};
+use std::fmt::{self, Debug, Formatter};
+use std::os::raw::{c_char, c_double};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use libloading::Library;
+use log::{debug, trace};
+use noisy_float::prelude::*;
+use thiserror::Error;
+
+use crate::c_api::distance::with_event_views;
+use crate::c_api::event::EventView;
+use crate::event::Event;
+use crate::traits::Distance;
+
+/// Default name of the distance function exported by a plugin
+pub const DEFAULT_SYMBOL: &str = "cres_distance";
+
+type DistanceSym = unsafe extern "C" fn(
+    *mut c_void,
+    *const EventView,
+    *const EventView,
+) -> c_double;
+type InitSym = unsafe extern "C" fn(*const c_char, *mut *mut c_void) -> i32;
+type TeardownSym = unsafe extern "C" fn(*mut c_void);
+
+/// Error loading a distance plugin
+#[derive(Debug, Error)]
+pub enum PluginError {
+    /// Failed to load the library
+    #[error("Failed to load distance plugin {0:?}")]
+    LoadErr(PathBuf, #[source] libloading::Error),
+    /// Failed to find the distance function
+    #[error("Distance plugin {0:?} does not export `{1}`")]
+    SymbolErr(PathBuf, String, #[source] libloading::Error),
+    /// Configuration given, but no initialisation function found
+    #[error("Distance plugin {0:?} does not export `{1}`, but a configuration was given")]
+    NoInitErr(PathBuf, String),
+    /// Initialisation function returned an error
+    #[error(
+        "Initialisation of distance plugin {0:?} failed with error code {1}"
+    )]
+    InitErr(PathBuf, i32),
+    /// Invalid configuration string
+    #[error("Invalid distance plugin configuration")]
+    ConfigErr(#[from] NulError),
+}
+
+/// A distance function loaded from a shared library
+///
+/// Clones share the same library, which is unloaded after the last
+/// clone is dropped.
+#[derive(Clone, Debug)]
+pub struct DistancePlugin(Arc<Plugin>);
+
+impl DistancePlugin {
+    /// Load the distance function `symbol` from the library at `path`
+    ///
+    /// If the library exports an initialisation function
+    /// `{symbol}_init`, it is called with `config`, or an empty string
+    /// if `config` is `None`.
+    ///
+    /// # Safety
+    ///
+    /// Loading the library runs its initialisation routines. The
+    /// exported functions must have the signatures given in the
+    /// [module documentation](self) and the distance function must be
+    /// thread-safe.
+    pub unsafe fn load(
+        path: &Path,
+        symbol: &str,
+        config: Option<&str>,
+    ) -> Result<Self, PluginError> {
+        use PluginError::*;
+
+        debug!("Loading distance plugin {path:?}");
+        let library =
+            Library::new(path).map_err(|err| LoadErr(path.to_owned(), err))?;
+        let fun = library
+            .get::<DistanceSym>(symbol.as_bytes())
+            .map(|f| *f)
+            .map_err(|err| {
+                SymbolErr(path.to_owned(), symbol.to_owned(), err)
+            })?;
+        let init_name = format!("{symbol}_init");
+        let init = library.get::<InitSym>(init_name.as_bytes()).ok();
+        let teardown = library
+            .get::<TeardownSym>(format!("{symbol}_teardown").as_bytes())
+            .ok()
+            .map(|t| *t);
+        let mut data = std::ptr::null_mut();
+        match init {
+            Some(init) => {
+                let config = CString::new(config.unwrap_or_default())?;
+                debug!("Calling {init_name} with configuration {config:?}");
+                let res = init(config.as_ptr(), &mut data);
+                if res != 0 {
+                    return Err(InitErr(path.to_owned(), res));
+                }
+            }
+            None if config.is_some() => {
+                return Err(NoInitErr(path.to_owned(), init_name));
+            }
+            None => {}
+        }
+        Ok(Self(Arc::new(Plugin {
+            fun,
+            data,
+            teardown,
+            _library: library,
+        })))
+    }
+}
+
+impl Distance for DistancePlugin {
+    fn distance(&self, ev1: &Event, ev2: &Event) -> N64 {
+        trace!("Compute distance between {:?} and {:?}", ev1, ev2);
+        let plugin = &self.0;
+        let dist = with_event_views(ev1, ev2, |ev1, ev2| unsafe {
+            (plugin.fun)(plugin.data, ev1, ev2)
+        });
+        n64(dist)
+    }
+}
+
+struct Plugin {
+    fun: DistanceSym,
+    data: *mut c_void,
+    teardown: Option<TeardownSym>,
+    // has to be dropped after calling `teardown`
+    _library: Library,
+}
+
+// the distance function is required to be thread-safe
+unsafe impl Send for Plugin {}
+unsafe impl Sync for Plugin {}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        if let Some(teardown) = self.teardown {
+            unsafe { teardown(self.data) }
+        }
+    }
+}
+
+impl Debug for Plugin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugin")
+            .field("fun", &(self.fun as *const ()))
+            .field("data", &self.data)
+            .finish()
+    }
+}
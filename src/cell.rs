@@ -26,6 +26,34 @@ impl<'a> Cell<'a> {
         distance: &F,
         neighbour_search: N,
     ) -> Self
+    where
+        for<'x, 'y> N: NeighbourSearch<DistWrapper<'x, 'y, F>>,
+        for<'x, 'y> <N as NeighbourSearch<DistWrapper<'x, 'y, F>>>::Iter:
+            Iterator<Item = (usize, N64)>,
+    {
+        let max_radius = n64(f64::INFINITY);
+        Self::with_max_radius(
+            events,
+            seed_idx,
+            distance,
+            neighbour_search,
+            max_radius,
+        )
+    }
+
+    /// Construct a new cell like with [Cell::new], but only
+    /// including events up to a distance of `max_radius` from the
+    /// seed
+    ///
+    /// This is in addition to any distance limit imposed by the
+    /// `neighbour_search`.
+    pub fn with_max_radius<'b: 'a, 'c, F: Distance + Sync + Send, N>(
+        events: &'b [Event],
+        seed_idx: usize,
+        distance: &F,
+        neighbour_search: N,
+        max_radius: N64,
+    ) -> Self
     where
         for<'x, 'y> N: NeighbourSearch<DistWrapper<'x, 'y, F>>,
         for<'x, 'y> <N as NeighbourSearch<DistWrapper<'x, 'y, F>>>::Iter:
@@ -38,7 +66,8 @@ impl<'a> Cell<'a> {
         let mut radius = n64(0.);
 
         let neighbours = neighbour_search
-            .nearest_in(&seed_idx, DistWrapper::new(distance, events));
+            .nearest_in(&seed_idx, DistWrapper::new(distance, events))
+            .take_while(|(_, dist)| *dist <= max_radius);
 
         for (next_idx, dist) in neighbours {
             trace!(
@@ -0,0 +1,254 @@
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, info};
+use noisy_float::prelude::*;
+use siphasher::sip::SipHasher13;
+use thiserror::Error;
+
+use crate::event::Event;
+
+const MAGIC: &[u8; 8] = b"CRESCKPT";
+const VERSION: u64 = 3;
+// identifies the algorithm used for the settings hash in the header:
+// SipHash-1-3 with both keys set to zero
+const HASH_ALGORITHM: u64 = 1;
+
+/// Settings for periodically saving the resampling progress
+///
+/// A checkpoint consists of the number of cell seeds that have been
+/// processed and the current weights of all events. Observations of
+/// the cells constructed before the checkpoint, for example cell ids
+/// and the cell summary in the run report, are not restored.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Checkpointing {
+    /// File the checkpoints are written to
+    pub file: PathBuf,
+    /// Minimum time between two checkpoints
+    pub interval: Duration,
+    /// Whether to resume from an existing checkpoint in `file`
+    pub resume: bool,
+    /// Hash of the settings that determine the resampling result
+    ///
+    /// Resuming from a checkpoint written with different settings
+    /// fails. [DefaultResampler](crate::resampler::DefaultResampler)
+    /// additionally takes into account the seed selection strategy
+    /// and the maximum cell size. Use [settings_hash] to compute the
+    /// hash, for example from the parameters of the distance function.
+    pub settings: u64,
+}
+
+/// Compute the hash of resampling settings for [Checkpointing]
+///
+/// The hash is computed with SipHash-1-3 with fixed keys, so that it
+/// does not change between runs or different builds of cres.
+pub fn settings_hash<S: Hash + ?Sized>(settings: &S) -> u64 {
+    let mut hasher = stable_hasher();
+    VERSION.hash(&mut hasher);
+    settings.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Hasher with a fixed, specified algorithm
+//
+// In contrast to `DefaultHasher`, the result is the same for all
+// builds on all platforms with the same endianness.
+pub(crate) fn stable_hasher() -> SipHasher13 {
+    SipHasher13::new_with_keys(0, 0)
+}
+
+/// Error reading or writing a checkpoint
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    /// I/O error
+    #[error("I/O error")]
+    IoErr(#[from] io::Error),
+    /// File is not a checkpoint
+    #[error("Not a cres checkpoint file")]
+    FormatErr,
+    /// Unsupported checkpoint version
+    #[error("Unsupported checkpoint version {0}")]
+    VersionErr(u64),
+    /// Unsupported algorithm for the settings hash
+    #[error("Unsupported settings hash algorithm {0}")]
+    HashErr(u64),
+    /// Checkpoint does not match the current run
+    #[error("Checkpoint mismatch: {0} is {1}, expected {2}")]
+    MismatchErr(&'static str, u64, u64),
+    /// Checkpoint was written with different settings
+    #[error("Checkpoint was written with different resampling settings")]
+    SettingsErr,
+}
+
+/// Write a checkpoint
+///
+/// The checkpoint is first written to a temporary file, which then
+/// replaces `file`. This ensures that `file` always contains a
+/// complete checkpoint.
+pub(crate) fn write(
+    file: &Path,
+    settings: u64,
+    events: &[Event],
+    nseeds_done: usize,
+    nseeds: usize,
+) -> Result<(), CheckpointError> {
+    debug!("Writing checkpoint after {nseeds_done} seeds to {file:?}");
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    out.write_all(MAGIC)?;
+    let nweights = events.first().map(|e| e.n_weights()).unwrap_or_default();
+    let header = [
+        VERSION,
+        HASH_ALGORITHM,
+        settings,
+        events.len() as u64,
+        nweights as u64,
+        nseeds as u64,
+    ];
+    for entry in header {
+        out.write_all(&entry.to_le_bytes())?;
+    }
+    out.write_all(&(nseeds_done as u64).to_le_bytes())?;
+    for event in events {
+        let weights = event.weights.read();
+        #[cfg(feature = "multiweight")]
+        for wt in weights.iter() {
+            out.write_all(&f64::from(*wt).to_le_bytes())?;
+        }
+        #[cfg(not(feature = "multiweight"))]
+        out.write_all(&f64::from(*weights).to_le_bytes())?;
+    }
+    out.into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+    fs::rename(&tmp, file)?;
+    Ok(())
+}
+
+/// Restore event weights from a checkpoint
+///
+/// Returns the number of processed seeds.
+pub(crate) fn resume(
+    file: &Path,
+    settings: u64,
+    events: &[Event],
+    nseeds: usize,
+) -> Result<usize, CheckpointError> {
+    use CheckpointError::*;
+
+    info!("Resuming from checkpoint {file:?}");
+    let mut input = BufReader::new(File::open(file)?);
+    let mut magic = [0u8; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(FormatErr);
+    }
+    let version = read_u64(&mut input)?;
+    if version != VERSION {
+        return Err(VersionErr(version));
+    }
+    let hash_algorithm = read_u64(&mut input)?;
+    if hash_algorithm != HASH_ALGORITHM {
+        return Err(HashErr(hash_algorithm));
+    }
+    if read_u64(&mut input)? != settings {
+        return Err(SettingsErr);
+    }
+    let nweights = events.first().map(|e| e.n_weights()).unwrap_or_default();
+    let expected = [
+        ("number of events", events.len()),
+        ("number of weights", nweights),
+        ("number of seeds", nseeds),
+    ];
+    for (what, expected) in expected {
+        let found = read_u64(&mut input)?;
+        if found != expected as u64 {
+            return Err(MismatchErr(what, found, expected as u64));
+        }
+    }
+    let nseeds_done = read_u64(&mut input)? as usize;
+    if nseeds_done > nseeds {
+        return Err(FormatErr);
+    }
+    for event in events {
+        let mut weights = event.weights.write();
+        #[cfg(feature = "multiweight")]
+        for wt in weights.iter_mut() {
+            *wt = read_weight(&mut input)?;
+        }
+        #[cfg(not(feature = "multiweight"))]
+        {
+            *weights = read_weight(&mut input)?;
+        }
+    }
+    info!("{nseeds_done} of {nseeds} seeds were already processed");
+    Ok(nseeds_done)
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64, io::Error> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_weight(r: &mut impl Read) -> Result<N64, CheckpointError> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    N64::try_new(f64::from_le_bytes(buf)).ok_or(CheckpointError::FormatErr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::event::test_event;
+
+    fn events(weights: &[f64]) -> Vec<Event> {
+        weights.iter().map(|&wt| test_event(0, wt, &[])).collect()
+    }
+
+    #[test]
+    fn tst_checkpoint_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("checkpoint");
+        write(&file, 0, &events(&[1., -2., 3.]), 1, 2).unwrap();
+
+        let restored = events(&[0., 0., 0.]);
+        assert_eq!(resume(&file, 0, &restored, 2).unwrap(), 1);
+        for (ev, wt) in restored.iter().zip([1., -2., 3.]) {
+            assert_eq!(ev.weight(), wt);
+            #[cfg(feature = "multiweight")]
+            assert_eq!(ev.weights.read()[1], 2. * wt);
+        }
+
+        assert!(matches!(
+            resume(&file, 0, &restored, 3),
+            Err(CheckpointError::MismatchErr(_, 2, 3))
+        ));
+        assert!(matches!(
+            resume(&file, 0, &restored[1..], 2),
+            Err(CheckpointError::MismatchErr(_, 3, 2))
+        ));
+
+        write(&file, 0, &events(&[1., -2., 3.]), 3, 2).unwrap();
+        assert!(matches!(
+            resume(&file, 0, &restored, 2),
+            Err(CheckpointError::FormatErr)
+        ));
+        assert!(matches!(
+            resume(&file, 1, &restored, 2),
+            Err(CheckpointError::SettingsErr)
+        ));
+    }
+
+    #[test]
+    fn tst_settings_hash_stable() {
+        // the settings hash has to be the same in every run and build
+        assert_eq!(settings_hash(&42u64), 11286628774380615618);
+    }
+}
@@ -1,15 +1,22 @@
 use std::{
+    collections::HashMap,
     fmt::{self, Display},
+    hash::{Hash, Hasher},
     str::FromStr,
 };
 
-use jetty::{anti_kt_f, cambridge_aachen_f, kt_f, Cluster, PseudoJet};
+use jetty::{
+    anti_kt_f, cambridge_aachen_f, kt_f, ClusterHistory, ClusterStep, PseudoJet,
+};
+use noisy_float::prelude::*;
 use particle_id::{
-    sm_elementary_particles::{bottom, electron, gluon, muon, photon},
+    sm_elementary_particles::{bottom, charm, electron, gluon, muon, photon},
     ParticleID,
 };
 use thiserror::Error;
 
+use crate::ee_cluster::EeClusterHistory;
+
 /// Placeholder for an unknown jet algorithm
 #[derive(Debug, Clone, Error)]
 pub struct UnknownJetAlgorithm(String);
@@ -24,20 +31,39 @@ impl FromStr for JetAlgorithm {
     type Err = UnknownJetAlgorithm;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "anti_kt" | "antikt" | "anti-kt" => Ok(Self::AntiKt),
-            "kt" => Ok(Self::Kt),
-            "Cambridge/Aachen" | "Cambridge-Aachen" | "Cambridge_Aachen"
-            | "cambridge/aachen" | "cambridge-aachen" | "cambridge_aachen" => {
-                Ok(Self::CambridgeAachen)
+        let err = || UnknownJetAlgorithm(s.to_string());
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param.trim())),
+            None => (s, None),
+        };
+        match (name, param) {
+            ("anti_kt" | "antikt" | "anti-kt", None) => Ok(Self::AntiKt),
+            ("kt", None) => Ok(Self::Kt),
+            (
+                "Cambridge/Aachen" | "Cambridge-Aachen" | "Cambridge_Aachen"
+                | "cambridge/aachen" | "cambridge-aachen" | "cambridge_aachen",
+                None,
+            ) => Ok(Self::CambridgeAachen),
+            ("Durham" | "durham" | "ee_kt" | "ee-kt" | "eekt", Some(param)) => {
+                let mode = if let Some(njets) = param.strip_prefix("n=") {
+                    njets.parse().ok().map(ExclusiveMode::NJets)
+                } else if let Some(ycut) = param.strip_prefix("ycut=") {
+                    ycut.parse().ok().map(ExclusiveMode::YCut)
+                } else {
+                    None
+                };
+                mode.map(Self::Durham).ok_or_else(err)
+            }
+            ("ee_genkt" | "ee-genkt" | "eegenkt", Some(p)) => {
+                p.parse().map(Self::EeGenKt).map_err(|_| err())
             }
-            _ => Err(UnknownJetAlgorithm(s.to_string())),
+            _ => Err(err()),
         }
     }
 }
 
 /// Jet clustering algorithms
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum JetAlgorithm {
     /// The [anti-kt](https://arxiv.org/abs/0802.1189) algorithm
     AntiKt,
@@ -45,6 +71,58 @@ pub enum JetAlgorithm {
     CambridgeAachen,
     /// The [kt](https://arxiv.org/abs/hep-ph/9305266) algorithm
     Kt,
+    /// The e⁺e⁻ [Durham](https://doi.org/10.1016/0370-2693(91)90214-M) algorithm
+    ///
+    /// This algorithm always clusters exclusively and does not use a
+    /// jet radius.
+    Durham(ExclusiveMode),
+    /// The generalised e⁺e⁻ kt algorithm with the given exponent p
+    ///
+    /// p = 1 is the e⁺e⁻ variant of the kt algorithm, p = 0 of the
+    /// Cambridge/Aachen algorithm, and p = -1 of the anti-kt
+    /// algorithm.
+    EeGenKt(f64),
+}
+
+impl JetAlgorithm {
+    /// Whether this is an algorithm for lepton colliders
+    ///
+    /// Lepton collider algorithms use energies instead of transverse
+    /// momenta.
+    pub fn is_ee(&self) -> bool {
+        matches!(self, Self::Durham(_) | Self::EeGenKt(_))
+    }
+}
+
+impl Hash for JetAlgorithm {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Durham(mode) => mode.hash(state),
+            Self::EeGenKt(p) => p.to_bits().hash(state),
+            Self::AntiKt | Self::CambridgeAachen | Self::Kt => {}
+        }
+    }
+}
+
+/// Stopping criterion for exclusive jet clustering
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExclusiveMode {
+    /// Cluster until the given number of jets is left
+    NJets(usize),
+    /// Cluster until all y_ij = d_ij / E_vis² exceed the given value
+    ///
+    /// E_vis is the total energy of all clustered particles.
+    YCut(f64),
+}
+
+impl Hash for ExclusiveMode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Self::NJets(njets) => (0, *njets).hash(state),
+            Self::YCut(ycut) => (1, ycut.to_bits()).hash(state),
+        }
+    }
 }
 
 /// Definition of a jet
@@ -55,7 +133,198 @@ pub struct JetDefinition {
     /// Jet radius parameter
     pub radius: f64,
     /// Minimum jet transverse momentum
+    ///
+    /// For lepton collider algorithms, this is the minimum jet energy
+    /// instead.
     pub min_pt: f64,
+    /// Optional cut on the jet (pseudo)rapidity
+    pub rapidity_cut: Option<RapidityCut>,
+}
+
+impl JetDefinition {
+    // whether a jet passes the transverse momentum (or energy) and
+    // rapidity cuts
+    pub(crate) fn accepts(&self, jet: &PseudoJet) -> bool {
+        let hard_enough = if self.algorithm.is_ee() {
+            jet.e() > self.min_pt
+        } else {
+            jet.pt2() > self.min_pt * self.min_pt
+        };
+        hard_enough
+            && self
+                .rapidity_cut
+                .map(|cut| cut.accepts(jet))
+                .unwrap_or(true)
+    }
+}
+
+impl Hash for JetDefinition {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.algorithm.hash(state);
+        self.radius.to_bits().hash(state);
+        self.min_pt.to_bits().hash(state);
+        self.rapidity_cut.hash(state);
+    }
+}
+
+/// Rapidity variable used in acceptance cuts
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RapidityKind {
+    /// Rapidity y
+    #[default]
+    Rapidity,
+    /// Pseudorapidity η
+    Pseudorapidity,
+}
+
+/// Acceptance cut on the absolute (pseudo)rapidity
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RapidityCut {
+    /// Whether to cut on the rapidity or the pseudorapidity
+    pub kind: RapidityKind,
+    /// Maximum absolute (pseudo)rapidity
+    pub max: f64,
+    /// Optional excluded range of the absolute (pseudo)rapidity
+    ///
+    /// This can be used to remove objects in the transition region
+    /// between the detector barrel and endcaps.
+    pub excluded: Option<[f64; 2]>,
+}
+
+impl RapidityCut {
+    /// Whether an object with the given momentum passes the cut
+    pub fn accepts(&self, p: &PseudoJet) -> bool {
+        let y = match self.kind {
+            RapidityKind::Rapidity => p.rap().raw(),
+            RapidityKind::Pseudorapidity => {
+                (p.pz() / p.pt2().sqrt()).raw().asinh()
+            }
+        }
+        .abs();
+        if let Some([min, max]) = self.excluded {
+            if min < y && y < max {
+                return false;
+            }
+        }
+        y < self.max
+    }
+}
+
+impl Hash for RapidityCut {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.max.to_bits().hash(state);
+        self.excluded.map(|r| r.map(f64::to_bits)).hash(state);
+    }
+}
+
+/// Definition of isolated photons
+#[derive(Debug, Copy, Clone)]
+pub struct PhotonDefinition {
+    /// Minimum photon transverse momentum
+    pub min_pt: f64,
+    /// Isolation criterion
+    pub isolation: PhotonIsolation,
+}
+
+impl Hash for PhotonDefinition {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.min_pt.to_bits().hash(state);
+        self.isolation.hash(state);
+    }
+}
+
+/// Photon isolation criteria
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PhotonIsolation {
+    /// Fixed cone isolation
+    ///
+    /// The summed transverse momentum of all other particles within
+    /// ΔR < `radius` of the photon must not exceed `epsilon` times
+    /// the photon transverse momentum.
+    Cone {
+        /// Radius of the isolation cone
+        radius: f64,
+        /// Maximum fraction of the photon transverse momentum in the cone
+        epsilon: f64,
+    },
+    /// [Frixione](https://arxiv.org/abs/hep-ph/9801442) isolation
+    ///
+    /// For all r < `radius`, the summed transverse momentum of all
+    /// other particles within ΔR < r of the photon must not exceed
+    ///
+    /// `epsilon` p_T ((1 - cos r) / (1 - cos radius))^`exponent`,
+    ///
+    /// where p_T is the photon transverse momentum.
+    Frixione {
+        /// Radius of the isolation cone
+        radius: f64,
+        /// Maximum fraction of the photon transverse momentum in the cone
+        epsilon: f64,
+        /// Exponent of the Frixione isolation function
+        exponent: f64,
+    },
+}
+
+impl PhotonIsolation {
+    /// Whether the photon with momentum `p` is isolated from the `other`
+    /// particles
+    pub fn is_isolated<'a>(
+        &self,
+        p: &PseudoJet,
+        other: impl IntoIterator<Item = &'a PseudoJet>,
+    ) -> bool {
+        let (radius, epsilon) = match *self {
+            Self::Cone { radius, epsilon } => (radius, epsilon),
+            Self::Frixione {
+                radius, epsilon, ..
+            } => (radius, epsilon),
+        };
+        let max_pt = epsilon * p.pt2().sqrt().raw();
+        let mut cone = Vec::from_iter(other.into_iter().filter_map(|q| {
+            let dr = p.delta_r(q).raw();
+            (dr < radius).then(|| (dr, q.pt2().sqrt().raw()))
+        }));
+        match *self {
+            Self::Cone { .. } => {
+                cone.iter().map(|(_, pt)| pt).sum::<f64>() <= max_pt
+            }
+            Self::Frixione { exponent, .. } => {
+                // the isolation function grows with the cone size, so
+                // it is enough to check the cone around each particle
+                cone.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+                let norm = 1. - radius.cos();
+                let mut pt_sum = 0.;
+                cone.into_iter().all(|(dr, pt)| {
+                    pt_sum += pt;
+                    let chi = ((1. - dr.cos()) / norm).powf(exponent);
+                    pt_sum <= max_pt * chi
+                })
+            }
+        }
+    }
+}
+
+impl Hash for PhotonIsolation {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Self::Cone { radius, epsilon } => {
+                0.hash(state);
+                radius.to_bits().hash(state);
+                epsilon.to_bits().hash(state);
+            }
+            Self::Frixione {
+                radius,
+                epsilon,
+                exponent,
+            } => {
+                1.hash(state);
+                radius.to_bits().hash(state);
+                epsilon.to_bits().hash(state);
+                exponent.to_bits().hash(state);
+            }
+        }
+    }
 }
 
 pub(crate) fn is_parton(id: ParticleID) -> bool {
@@ -76,20 +345,290 @@ pub(crate) fn is_photon(id: ParticleID) -> bool {
 
 pub(crate) const PID_JET: ParticleID = ParticleID::new(81);
 pub(crate) const PID_DRESSED_LEPTON: ParticleID = ParticleID::new(82);
+pub(crate) const PID_B_JET: ParticleID = ParticleID::new(83);
+pub(crate) const PID_C_JET: ParticleID = ParticleID::new(84);
+pub(crate) const PID_MISSING_PT: ParticleID = ParticleID::new(85);
+
+/// Placeholder for an unknown jet flavour
+#[derive(Debug, Clone, Error)]
+pub struct UnknownJetFlavour(String);
+
+impl Display for UnknownJetFlavour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown jet flavour: {}", self.0)
+    }
+}
+
+impl FromStr for JetFlavour {
+    type Err = UnknownJetFlavour;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "b" | "bottom" => Ok(Self::Bottom),
+            "c" | "charm" => Ok(Self::Charm),
+            _ => Err(UnknownJetFlavour(s.to_string())),
+        }
+    }
+}
+
+/// Heavy jet flavours
+///
+/// Flavours are ordered by priority: a jet containing both a bottom
+/// and a charm hadron is a b-jet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum JetFlavour {
+    /// c-jets
+    Charm,
+    /// b-jets
+    Bottom,
+}
+
+impl JetFlavour {
+    /// Pseudo particle id for jets of this flavour
+    pub fn pid(self) -> ParticleID {
+        match self {
+            Self::Charm => PID_C_JET,
+            Self::Bottom => PID_B_JET,
+        }
+    }
+
+    /// Flavour of a hadron or quark
+    ///
+    /// For hadrons, this is the heaviest valence quark. Particles
+    /// without bottom or charm content have no flavour.
+    pub fn of(id: ParticleID) -> Option<Self> {
+        let id = id.abs();
+        let heaviest = if id.is_quark() {
+            id.id()
+        } else if is_hadron(id) {
+            // the quark content is encoded in the last four digits
+            let digits = id.id() % 10000 / 10;
+            (digits / 100).max(digits / 10 % 10).max(digits % 10)
+        } else {
+            return None;
+        };
+        if heaviest == bottom.id() {
+            Some(Self::Bottom)
+        } else if heaviest == charm.id() {
+            Some(Self::Charm)
+        } else {
+            None
+        }
+    }
+}
+
+// scale factor for ghost momenta, small enough not to change the jets
+const GHOST_SCALE: f64 = 1e-18;
+
+/// Cluster the given `partons` into jets and label them by flavour
+///
+/// Flavours are assigned by ghost association: the `ghosts`, usually
+/// the heavy-flavour hadrons in the event, are rescaled to negligible
+/// momenta and clustered together with the `partons`. Each jet gets
+/// the highest flavour of the ghosts it contains, or no flavour if it
+/// contains none.
+pub fn cluster_flavoured(
+    partons: Vec<PseudoJet>,
+    ghosts: Vec<(PseudoJet, JetFlavour)>,
+    jet_def: &JetDefinition,
+) -> Vec<(PseudoJet, Option<JetFlavour>)> {
+    let mut flavours = HashMap::with_capacity(ghosts.len());
+    let mut all = partons;
+    for (ghost, flavour) in ghosts {
+        if ghost.pt2() <= 0. {
+            continue;
+        }
+        let scale = n64(GHOST_SCALE);
+        let ghost: PseudoJet = [
+            scale * ghost.e(),
+            scale * ghost.px(),
+            scale * ghost.py(),
+            scale * ghost.pz(),
+        ]
+        .into();
+        let entry = flavours.entry(ghost).or_insert(flavour);
+        *entry = flavour.max(*entry);
+        all.push(ghost);
+    }
+    let mut jets = Vec::new();
+    for step in cluster_history(all, jet_def) {
+        match step {
+            ClusterStep::Combine([p1, p2]) => {
+                let flavour = flavours.get(&p1).max(flavours.get(&p2)).copied();
+                if let Some(flavour) = flavour {
+                    flavours.insert(p1 + p2, flavour);
+                }
+            }
+            ClusterStep::Jet(jet) if jet_def.accepts(&jet) => {
+                jets.push((jet, flavours.get(&jet).copied()))
+            }
+            ClusterStep::Jet(_) => {}
+        }
+    }
+    jets
+}
 
 /// Cluster the given `partons` into jets
 pub fn cluster(
     partons: Vec<PseudoJet>,
     jet_def: &JetDefinition,
 ) -> Vec<PseudoJet> {
-    let minpt2 = jet_def.min_pt * jet_def.min_pt;
-    let cut = |jet: PseudoJet| jet.pt2() > minpt2;
+    cluster_history(partons, jet_def)
+        .filter_map(|step| match step {
+            ClusterStep::Jet(jet) if jet_def.accepts(&jet) => Some(jet),
+            _ => None,
+        })
+        .collect()
+}
+
+fn cluster_history<'a>(
+    partons: Vec<PseudoJet>,
+    jet_def: &JetDefinition,
+) -> Box<dyn Iterator<Item = ClusterStep> + 'a> {
     let r = jet_def.radius;
     match jet_def.algorithm {
-        JetAlgorithm::AntiKt => partons.cluster_if(anti_kt_f(r), cut),
-        JetAlgorithm::Kt => partons.cluster_if(kt_f(r), cut),
+        JetAlgorithm::AntiKt => {
+            Box::new(ClusterHistory::new(partons, anti_kt_f(r)))
+        }
+        JetAlgorithm::Kt => Box::new(ClusterHistory::new(partons, kt_f(r))),
         JetAlgorithm::CambridgeAachen => {
-            partons.cluster_if(cambridge_aachen_f(r), cut)
+            Box::new(ClusterHistory::new(partons, cambridge_aachen_f(r)))
+        }
+        JetAlgorithm::Durham(mode) => {
+            Box::new(EeClusterHistory::durham(partons, mode))
         }
+        JetAlgorithm::EeGenKt(p) => {
+            Box::new(EeClusterHistory::gen_kt(partons, r, p))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cmp::Reverse;
+
+    #[test]
+    fn tst_flavour() {
+        assert_eq!(JetFlavour::of(bottom), Some(JetFlavour::Bottom));
+        assert_eq!(
+            JetFlavour::of(ParticleID::new(-4)),
+            Some(JetFlavour::Charm)
+        );
+        assert_eq!(JetFlavour::of(gluon), None);
+        // B0, Λb, B_c, D+, J/ψ, π+, K0
+        let bottom_hadrons = [511, 5122, 541];
+        let charm_hadrons = [411, 443, -4122];
+        let light_hadrons = [211, 311, 2212];
+        for id in bottom_hadrons {
+            let flavour = JetFlavour::of(ParticleID::new(id));
+            assert_eq!(flavour, Some(JetFlavour::Bottom));
+        }
+        for id in charm_hadrons {
+            let flavour = JetFlavour::of(ParticleID::new(id));
+            assert_eq!(flavour, Some(JetFlavour::Charm));
+        }
+        for id in light_hadrons {
+            assert_eq!(JetFlavour::of(ParticleID::new(id)), None);
+        }
+    }
+
+    #[test]
+    fn tst_cluster_flavoured() {
+        let jet_def = JetDefinition {
+            algorithm: JetAlgorithm::AntiKt,
+            radius: 0.4,
+            min_pt: 20.,
+            rapidity_cut: None,
+        };
+        let p = |e, px, py, pz| PseudoJet::from([e, px, py, pz]);
+        let partons = vec![
+            p(50., 50., 0., 0.),
+            p(10., 9., 1., 0.),
+            p(40., -40., 0., 0.),
+            p(30., 0., 30., 0.),
+        ];
+        let ghosts = vec![
+            (p(20., 19., 1., 0.), JetFlavour::Charm),
+            (p(10., 10., 0., 0.), JetFlavour::Bottom),
+            (p(10., 0., 10., 0.), JetFlavour::Charm),
+            (p(10., 0., -10., 0.), JetFlavour::Bottom),
+        ];
+        let unflavoured = cluster(partons.clone(), &jet_def);
+        let mut jets = cluster_flavoured(partons, ghosts, &jet_def);
+        assert_eq!(jets.len(), unflavoured.len());
+        jets.sort_by_key(|(jet, _)| Reverse(jet.pt2()));
+        let flavours = Vec::from_iter(jets.iter().map(|(_, f)| *f));
+        assert_eq!(
+            flavours,
+            [Some(JetFlavour::Bottom), None, Some(JetFlavour::Charm)]
+        );
+        for (jet, _) in jets {
+            assert!(unflavoured
+                .iter()
+                .any(|j| (j.pt2() - jet.pt2()).abs() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn tst_rapidity_cut() {
+        // y ≈ 0.55, η ≈ 1.28
+        let p = PseudoJet::from([10., 0., 3., 5.]);
+        let mut cut = RapidityCut {
+            kind: RapidityKind::Rapidity,
+            max: 1.,
+            excluded: None,
+        };
+        assert!(cut.accepts(&p));
+        cut.excluded = Some([0.5, 0.6]);
+        assert!(!cut.accepts(&p));
+        cut.excluded = Some([1.2, 1.3]);
+        assert!(cut.accepts(&p));
+        cut.kind = RapidityKind::Pseudorapidity;
+        assert!(!cut.accepts(&p));
+        cut.max = 2.;
+        assert!(!cut.accepts(&p));
+        cut.excluded = None;
+        assert!(cut.accepts(&p));
+    }
+
+    #[test]
+    fn tst_photon_isolation() {
+        let gamma = PseudoJet::from([50., 50., 0., 0.]);
+        // ΔR ≈ 0.1 and ΔR ≈ 0.3
+        let near = PseudoJet::from([4., 4., 0.4, 0.]);
+        let far = PseudoJet::from([10., 9.5, 3., 0.]);
+        let outside = PseudoJet::from([100., 0., 100., 0.]);
+        let cone = PhotonIsolation::Cone {
+            radius: 0.4,
+            epsilon: 0.2,
+        };
+        assert!(cone.is_isolated(&gamma, [&far, &outside]));
+        assert!(!cone.is_isolated(&gamma, [&near, &far, &outside]));
+        let frixione = PhotonIsolation::Frixione {
+            radius: 0.4,
+            epsilon: 1.,
+            exponent: 1.,
+        };
+        // (1 - cos 0.3) / (1 - cos 0.4) ≈ 0.59
+        assert!(frixione.is_isolated(&gamma, [&far, &outside]));
+        // (1 - cos 0.1) / (1 - cos 0.4) ≈ 0.06
+        assert!(!frixione.is_isolated(&gamma, [&near, &outside]));
+    }
+
+    #[test]
+    fn tst_parse_algorithm() {
+        use ExclusiveMode::*;
+        use JetAlgorithm::*;
+
+        let parse = |s: &str| s.parse::<JetAlgorithm>().ok();
+        assert_eq!(parse("anti-kt"), Some(AntiKt));
+        assert_eq!(parse("durham:n=4"), Some(Durham(NJets(4))));
+        assert_eq!(parse("ee-kt:ycut=0.01"), Some(Durham(YCut(0.01))));
+        assert_eq!(parse("ee-genkt:-1"), Some(EeGenKt(-1.)));
+        assert_eq!(parse("durham"), None);
+        assert_eq!(parse("durham:n=0.5"), None);
+        assert_eq!(parse("kt:1"), None);
     }
 }
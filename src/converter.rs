@@ -1,16 +1,19 @@
 #[cfg(feature = "multiweight")]
 use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 
 use crate::cluster::{
-    cluster, is_hadron, is_light_lepton, is_parton, is_photon, JetDefinition,
-    PID_DRESSED_LEPTON, PID_JET,
+    cluster, cluster_flavoured, is_hadron, is_light_lepton, is_parton,
+    is_photon, JetDefinition, JetFlavour, PhotonDefinition, PID_DRESSED_LEPTON,
+    PID_JET, PID_MISSING_PT,
 };
 use crate::event::{Event, EventBuilder};
 use crate::traits::TryConvert;
 
-use avery::event::Status;
+use avery::event::{Particle, Status};
+use jetty::PseudoJet;
 use noisy_float::prelude::*;
-use particle_id::ParticleID;
+use particle_id::{sm_elementary_particles::photon, ParticleID};
 use thiserror::Error;
 
 /// Convert an input event into internal format with jet clustering
@@ -18,7 +21,12 @@ use thiserror::Error;
 pub struct ClusteringConverter {
     jet_def: JetDefinition,
     lepton_def: Option<JetDefinition>,
+    photon_def: Option<PhotonDefinition>,
+    jet_flavours: Vec<JetFlavour>,
     include_neutrinos: bool,
+    // invisible particles in addition to neutrinos,
+    // `None` if there is no missing transverse momentum
+    invisible: Option<Vec<ParticleID>>,
     #[cfg(feature = "multiweight")]
     weight_names: HashSet<String>,
 }
@@ -29,7 +37,10 @@ impl ClusteringConverter {
         Self {
             jet_def,
             lepton_def: None,
+            photon_def: None,
+            jet_flavours: Vec::new(),
             include_neutrinos: false,
+            invisible: None,
             #[cfg(feature = "multiweight")]
             weight_names: HashSet::new(),
         }
@@ -41,12 +52,63 @@ impl ClusteringConverter {
         self
     }
 
+    /// Keep isolated photons
+    ///
+    /// Without a photon definition, photons are either clustered
+    /// together with leptons or, if there is no lepton definition,
+    /// kept as they are. With a photon definition, each photon within
+    /// the radius of the lepton definition around a bare lepton is
+    /// instead added to the nearest lepton. Remaining photons passing
+    /// the transverse momentum cut and the isolation criterion are
+    /// kept as photons, whereas all other photons are clustered into
+    /// jets.
+    pub fn with_photon_def(mut self, photon_def: PhotonDefinition) -> Self {
+        self.photon_def = Some(photon_def);
+        self
+    }
+
+    /// Distinguish jets of the given flavours
+    ///
+    /// Jets are labelled by ghost association with the bottom and
+    /// charm hadrons in the event record, or the outgoing bottom and
+    /// charm quarks for events without hadrons. Jets with one of the
+    /// given flavours are assigned a separate pseudo particle id, all
+    /// other jets are treated as light jets.
+    pub fn with_jet_flavours(
+        mut self,
+        flavours: impl IntoIterator<Item = JetFlavour>,
+    ) -> Self {
+        self.jet_flavours = Vec::from_iter(flavours);
+        self.jet_flavours.sort_unstable();
+        self.jet_flavours.dedup();
+        self
+    }
+
     /// Whether to include neutrinos in final event record
     pub fn include_neutrinos(mut self, include: bool) -> Self {
         self.include_neutrinos = include;
         self
     }
 
+    /// Replace all invisible particles by their missing transverse momentum
+    ///
+    /// Invisible particles are neutrinos and particles with the given
+    /// ids or the corresponding antiparticle ids. Their momenta are
+    /// summed into a single massless pseudo particle without
+    /// longitudinal momentum. This takes precedence over
+    /// [include_neutrinos](Self::include_neutrinos).
+    pub fn with_missing_pt(
+        mut self,
+        invisible: impl IntoIterator<Item = ParticleID>,
+    ) -> Self {
+        let mut invisible =
+            Vec::from_iter(invisible.into_iter().map(|id| id.abs()));
+        invisible.sort_unstable();
+        invisible.dedup();
+        self.invisible = Some(invisible);
+        self
+    }
+
     /// Names of additional weights to include in the converted event
     ///
     /// By default, only the main weight is kept
@@ -56,9 +118,46 @@ impl ClusteringConverter {
         self
     }
 
+    fn is_invisible(&self, id: ParticleID) -> bool {
+        match self.invisible.as_ref() {
+            Some(invisible) => is_neutrino(id) || invisible.contains(&id.abs()),
+            None => false,
+        }
+    }
+
+    // flavour of a particle used as a ghost for flavour tagging
+    fn ghost_flavour(&self, particle: &Particle) -> Option<JetFlavour> {
+        if self.jet_flavours.is_empty() {
+            return None;
+        }
+        let id = particle.id?;
+        let outgoing = particle.status == Some(Status::Outgoing);
+        if is_hadron(id.abs()) || (outgoing && is_parton(id)) {
+            JetFlavour::of(id).filter(|f| self.jet_flavours.contains(f))
+        } else {
+            None
+        }
+    }
+
     fn is_clustered_to_lepton(&self, id: ParticleID) -> bool {
         self.lepton_def.is_some()
-            && (is_light_lepton(id.abs()) || is_photon(id))
+            && (is_light_lepton(id.abs())
+                || (is_photon(id) && self.photon_def.is_none()))
+    }
+}
+
+// Used to identify cached events, so all settings that influence the
+// conversion have to be included
+impl Hash for ClusteringConverter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.jet_def.hash(state);
+        self.lepton_def.hash(state);
+        self.photon_def.hash(state);
+        self.jet_flavours.hash(state);
+        self.include_neutrinos.hash(state);
+        self.invisible.hash(state);
+        #[cfg(feature = "multiweight")]
+        hash_weight_names(&self.weight_names, state);
     }
 }
 
@@ -71,21 +170,42 @@ impl TryConvert<avery::Event, Event> for ClusteringConverter {
     ) -> Result<Event, Self::Error> {
         let mut partons = Vec::new();
         let mut leptons = Vec::new();
+        let mut ghosts = Vec::new();
+        // candidates for isolated photons with their position in
+        // `visible`, which holds all particles relevant for isolation
+        let mut photons = Vec::new();
+        let mut visible = Vec::new();
+        let mut missing = [0.; 2];
         let mut builder = EventBuilder::new();
         #[cfg(feature = "multiweight")]
         builder.weights(extract_weights(&event, &self.weight_names)?);
         #[cfg(not(feature = "multiweight"))]
         builder.weights(n64(event.weights.first().unwrap().weight.unwrap()));
 
-        let outgoing = event
-            .particles
-            .into_iter()
-            .filter(|p| p.status == Some(Status::Outgoing));
-        for out in outgoing {
-            let id = out.id.unwrap();
-            let p = out.p.unwrap();
+        for particle in event.particles {
+            // unlike outgoing particles, intermediate ones may not have
+            // a momentum
+            let ghost = self.ghost_flavour(&particle).zip(particle.p);
+            if let Some((flavour, p)) = ghost {
+                ghosts.push((p.into(), flavour));
+            }
+            if particle.status != Some(Status::Outgoing) {
+                continue;
+            }
+            let id = particle.id.unwrap();
+            let p = particle.p.unwrap();
+            if self.is_invisible(id) {
+                missing[0] += p[1];
+                missing[1] += p[2];
+                continue;
+            }
+            if self.photon_def.is_some() && !is_neutrino(id) {
+                visible.push(p.into());
+            }
             if is_parton(id) || is_hadron(id.abs()) {
                 partons.push(p.into());
+            } else if self.photon_def.is_some() && is_photon(id) {
+                photons.push((visible.len() - 1, p.into()));
             } else if self.is_clustered_to_lepton(id) {
                 leptons.push(p.into());
             } else if self.include_neutrinos || !is_neutrino(id) {
@@ -93,13 +213,53 @@ impl TryConvert<avery::Event, Event> for ClusteringConverter {
                 builder.add_outgoing(id, p.into());
             }
         }
-        let jets = cluster(partons, &self.jet_def);
-        for jet in jets {
-            let p = [jet.e(), jet.px(), jet.py(), jet.pz()];
-            builder.add_outgoing(PID_JET, p.into());
+        let [px, py] = missing;
+        let pt = px.hypot(py);
+        if pt > 0. {
+            let p = [n64(pt), n64(px), n64(py), n64(0.)];
+            builder.add_outgoing(PID_MISSING_PT, p.into());
+        }
+        if let Some(photon_def) = self.photon_def.as_ref() {
+            if let Some(lepton_def) = self.lepton_def.as_ref() {
+                photons = dress(&mut leptons, photons, lepton_def.radius);
+            }
+            let min_pt2 = photon_def.min_pt * photon_def.min_pt;
+            for (idx, p) in photons {
+                let other = visible
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, q)| (i != idx).then_some(q));
+                if p.pt2() > min_pt2
+                    && photon_def.isolation.is_isolated(&p, other)
+                {
+                    let p = [p.e(), p.px(), p.py(), p.pz()];
+                    builder.add_outgoing(photon, p.into());
+                } else {
+                    partons.push(p);
+                }
+            }
+        }
+        if self.jet_flavours.is_empty() {
+            let jets = cluster(partons, &self.jet_def);
+            for jet in jets {
+                let p = [jet.e(), jet.px(), jet.py(), jet.pz()];
+                builder.add_outgoing(PID_JET, p.into());
+            }
+        } else {
+            let jets = cluster_flavoured(partons, ghosts, &self.jet_def);
+            for (jet, flavour) in jets {
+                let p = [jet.e(), jet.px(), jet.py(), jet.pz()];
+                let id = flavour.map(JetFlavour::pid).unwrap_or(PID_JET);
+                builder.add_outgoing(id, p.into());
+            }
         }
         if let Some(lepton_def) = self.lepton_def.as_ref() {
-            let leptons = cluster(leptons, lepton_def);
+            let leptons = if self.photon_def.is_some() {
+                leptons.retain(|l| lepton_def.accepts(l));
+                leptons
+            } else {
+                cluster(leptons, lepton_def)
+            };
             for lepton in leptons {
                 let p = [lepton.e(), lepton.px(), lepton.py(), lepton.pz()];
                 builder.add_outgoing(PID_DRESSED_LEPTON, p.into());
@@ -109,6 +269,30 @@ impl TryConvert<avery::Event, Event> for ClusteringConverter {
     }
 }
 
+// add each photon within ΔR < `radius` of a lepton to the nearest
+// lepton and return the remaining photons
+fn dress(
+    leptons: &mut [PseudoJet],
+    photons: Vec<(usize, PseudoJet)>,
+    radius: f64,
+) -> Vec<(usize, PseudoJet)> {
+    // distances are always measured to the bare leptons
+    let bare = leptons.to_vec();
+    let mut remaining = Vec::new();
+    for (idx, p) in photons {
+        let nearest = bare
+            .iter()
+            .map(|l| p.delta_r(l))
+            .enumerate()
+            .min_by_key(|(_, dr)| *dr);
+        match nearest {
+            Some((i, dr)) if dr < radius => leptons[i] += p,
+            _ => remaining.push((idx, p)),
+        }
+    }
+    remaining
+}
+
 fn is_neutrino(id: ParticleID) -> bool {
     id.abs().is_neutrino()
 }
@@ -120,6 +304,21 @@ pub struct Converter {
     weight_names: HashSet<String>,
 }
 
+impl Hash for Converter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        "Converter".hash(state);
+        #[cfg(feature = "multiweight")]
+        hash_weight_names(&self.weight_names, state);
+    }
+}
+
+#[cfg(feature = "multiweight")]
+fn hash_weight_names<H: Hasher>(names: &HashSet<String>, state: &mut H) {
+    let mut names = Vec::from_iter(names);
+    names.sort_unstable();
+    names.hash(state);
+}
+
 impl Converter {
     /// Constructor
     pub fn new() -> Self {
@@ -45,15 +45,18 @@
 //!
 use std::convert::From;
 use std::iter::Iterator;
+use std::time::Instant;
 
 use log::{info, trace};
-use noisy_float::prelude::*;
 use rayon::prelude::*;
 use thiserror::Error;
 
 use crate::event::Event;
+use crate::event_cache::{CacheError, EventCache};
 use crate::progress_bar::ProgressBar;
+use crate::report::{RunReport, SampleSummary, Timings};
 use crate::traits::*;
+use crate::validation::{fill_histograms, HistogramSpec, ValidationReport};
 
 /// Build a new [Cres] object
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
@@ -79,6 +82,8 @@ impl<R, C, S, U, W> CresBuilder<R, C, S, U, W> {
             resampler: self.resampler,
             unweighter: self.unweighter,
             writer: self.writer,
+            cache: None,
+            validation: Vec::new(),
         }
     }
 }
@@ -96,13 +101,43 @@ impl<R, C, S, U, W> From<Cres<R, C, S, U, W>> for CresBuilder<R, C, S, U, W> {
 }
 
 /// Main cell resampler
-#[derive(Copy, Clone, PartialEq, Eq, Ord, PartialOrd, Hash, Debug, Default)]
+#[derive(Clone, PartialEq, Eq, Ord, PartialOrd, Hash, Debug, Default)]
 pub struct Cres<R, C, S, U, W> {
     reader: R,
     converter: C,
     resampler: S,
     unweighter: U,
     writer: W,
+    cache: Option<EventCache>,
+    validation: Vec<HistogramSpec>,
+}
+
+impl<R, C, S, U, W> Cres<R, C, S, U, W> {
+    /// Cache converted events on disk
+    ///
+    /// If the cache contains events, they are used instead of
+    /// reading and converting the input. In this case, the converter
+    /// is not called at all. Otherwise, the converted events are
+    /// written to the cache.
+    ///
+    /// Converters that move the particle momenta out of the events,
+    /// like [StoringConverter](crate::event_store::StoringConverter),
+    /// cannot be combined with a cache. Reading events then fails with
+    /// [CacheError::MomentaErr].
+    pub fn with_cache(mut self, cache: EventCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Compare histograms before and after resampling
+    ///
+    /// The histograms are filled with the events before resampling
+    /// and with the final events after unweighting. The comparison
+    /// is included in the [RunReport].
+    pub fn with_validation(mut self, histograms: Vec<HistogramSpec>) -> Self {
+        self.validation = histograms;
+        self
+    }
 }
 
 impl<R, C, S, U, W> From<CresBuilder<R, C, S, U, W>> for Cres<R, C, S, U, W> {
@@ -135,8 +170,24 @@ pub enum CresError<E1, E2, E3, E4, E5, E6> {
     /// Encountered event with invalid id
     #[error("Encountered event with non-zero id {0}")]
     IdErr(usize),
+    /// Error reading from or writing to the event cache
+    #[error("Event cache error")]
+    CacheErr(#[source] CacheError),
 }
 
+/// Result of the steps of [Cres::run]
+type CresResult<T, E, Ev, R, C, S, U, W> = Result<
+    T,
+    CresError<
+        E,
+        <R as Rewind>::Error,
+        <C as TryConvert<Ev, Event>>::Error,
+        <S as Resample>::Error,
+        <U as Unweight>::Error,
+        <W as Write<R>>::Error,
+    >,
+>;
+
 impl<R, C, S, U, W, E, Ev> Cres<R, C, S, U, W>
 where
     R: Iterator<Item = Result<Ev, E>> + Rewind,
@@ -154,22 +205,97 @@ where
     /// 3. Apply cell resampling
     /// 4. Unweight
     /// 5. Write out events
-    pub fn run(
+    ///
+    /// On success, a summary of the run is returned.
+    pub fn run(&mut self) -> CresResult<RunReport, E, Ev, R, C, S, U, W> {
+        use CresError::*;
+
+        let mut timings = Timings::default();
+        let start = Instant::now();
+        let events = self.read_events()?;
+        let initial = SampleSummary::new(&events);
+        let initial_histograms = fill_histograms(&self.validation, &events);
+        timings.read = start.elapsed();
+
+        let start = Instant::now();
+        let events = self.resampler.resample(events).map_err(ResamplingErr)?;
+        timings.resample = start.elapsed();
+
+        let start = Instant::now();
+        let mut events =
+            self.unweighter.unweight(events).map_err(UnweightErr)?;
+        events.par_sort_unstable();
+        timings.unweight = start.elapsed();
+
+        let resampled = SampleSummary::new(&events);
+        let validation = (!self.validation.is_empty()).then(|| {
+            let resampled_histograms =
+                fill_histograms(&self.validation, &events);
+            ValidationReport::new(
+                &self.validation,
+                &initial_histograms,
+                &resampled_histograms,
+            )
+        });
+        info!(
+            "Final sum of weights: {:.3e} ± {:.3e}",
+            resampled.sum_weights, resampled.sum_weights_err
+        );
+        info!(
+            "Final negative weight fraction: {:.3}",
+            resampled.neg_weight_fraction
+        );
+
+        let start = Instant::now();
+        self.reader.rewind().map_err(RewindErr)?;
+        let reader = &mut self.reader;
+        self.writer.write(reader, &events).map_err(WriteErr)?;
+        timings.write = start.elapsed();
+
+        Ok(RunReport {
+            initial,
+            resampled,
+            cells: self.resampler.cell_summary(),
+            validation,
+            timings,
+        })
+    }
+
+    /// Read in events and convert them into the internal format
+    ///
+    /// These are the first two steps of [Cres::run]. If a cache is
+    /// set, the events are taken from the cache if possible.
+    pub fn read_events(
         &mut self,
-    ) -> Result<
-        (),
-        CresError<
-            E,
-            <R as Rewind>::Error,
-            C::Error,
-            S::Error,
-            U::Error,
-            W::Error,
-        >,
-    > {
+    ) -> CresResult<Vec<Event>, E, Ev, R, C, S, U, W> {
         use CresError::*;
 
+        if self.cache.is_some() && !self.converter.keeps_momenta() {
+            return Err(CacheErr(CacheError::MomentaErr));
+        }
         self.reader.rewind().map_err(RewindErr)?;
+        let cached = match &self.cache {
+            Some(cache) => cache.load().map_err(CacheErr)?,
+            None => None,
+        };
+        let events = match cached {
+            Some(events) => events,
+            None => {
+                let events = self.read_and_convert()?;
+                if let Some(cache) = &self.cache {
+                    cache.store(&events).map_err(CacheErr)?;
+                }
+                events
+            }
+        };
+        info!("Read {} events", events.len());
+        Ok(events)
+    }
+
+    fn read_and_convert(
+        &mut self,
+    ) -> CresResult<Vec<Event>, E, Ev, R, C, S, U, W> {
+        use CresError::*;
 
         let converter = &mut self.converter;
         let expected_nevents = self.reader.size_hint().0;
@@ -188,6 +314,7 @@ where
             .collect();
         event_progress.finish();
         let mut events = events?;
+        self.converter.finish().map_err(ConversionErr)?;
 
         for (id, ev) in events.iter_mut().enumerate() {
             if ev.id != 0 {
@@ -196,33 +323,6 @@ where
             ev.id = id;
             trace!("{ev:#?}");
         }
-        info!("Read {} events", events.len());
-
-        let events = self.resampler.resample(events).map_err(ResamplingErr)?;
-
-        let mut events =
-            self.unweighter.unweight(events).map_err(UnweightErr)?;
-        events.par_sort_unstable();
-
-        let sum_wt: N64 = events.par_iter().map(|e| e.weight()).sum();
-        let sum_neg_wt: N64 = events
-            .par_iter()
-            .map(|e| e.weight())
-            .filter(|&w| w < 0.)
-            .sum();
-        let sum_wtsqr: N64 =
-            events.par_iter().map(|e| e.weight() * e.weight()).sum();
-        info!(
-            "Final sum of weights: {sum_wt:.3e} ± {:.3e}",
-            sum_wtsqr.sqrt()
-        );
-        info!(
-            "Final negative weight fraction: {:.3}",
-            -sum_neg_wt / (sum_wt - sum_neg_wt * 2.)
-        );
-
-        self.reader.rewind().map_err(RewindErr)?;
-        let reader = &mut self.reader;
-        self.writer.write(reader, &events).map_err(WriteErr)
+        Ok(events)
     }
 }
@@ -1,19 +1,42 @@
+use crate::assignment;
 use crate::event::Event;
 use crate::four_vector::FourVector;
+use crate::observable::{Observable, OutgoingMomenta};
+use crate::transport;
 
 use std::cmp::Ordering;
-use std::fmt::{self, Display};
-use std::ops::{Index, IndexMut};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
 
-use itertools::Itertools;
 use noisy_float::prelude::*;
-use pathfinding::prelude::{kuhn_munkres_min, Weights};
+use particle_id::ParticleID;
 use permutohedron::LexicalPermutation;
 
 /// A metric (distance function) in the space of all events
 pub trait Distance<E = Event> {
     /// Compute the distance between two events
     fn distance(&self, ev1: &E, ev2: &E) -> N64;
+
+    /// Compute the distance between two events if it does not exceed
+    /// `max_dist`
+    ///
+    /// Returns `None` if the distance is larger than `max_dist`.
+    /// Implementations can use this to skip expensive parts of the
+    /// calculation. The default implementation always computes the
+    /// full distance.
+    fn distance_within(&self, ev1: &E, ev2: &E, max_dist: N64) -> Option<N64> {
+        let dist = self.distance(ev1, ev2);
+        (dist <= max_dist).then_some(dist)
+    }
+
+    /// Cheap lower bound on the distance between two events
+    ///
+    /// Nearest neighbour searches use this to skip the computation of
+    /// distances that are certainly too large. The default
+    /// implementation returns zero.
+    fn lower_bound(&self, _ev1: &E, _ev2: &E) -> N64 {
+        n64(0.)
+    }
 }
 
 impl<D, E> Distance<E> for &D
@@ -23,146 +46,696 @@ where
     fn distance(&self, ev1: &E, ev2: &E) -> N64 {
         (*self).distance(ev1, ev2)
     }
+
+    fn distance_within(&self, ev1: &E, ev2: &E, max_dist: N64) -> Option<N64> {
+        (*self).distance_within(ev1, ev2, max_dist)
+    }
+
+    fn lower_bound(&self, ev1: &E, ev2: &E) -> N64 {
+        (*self).lower_bound(ev1, ev2)
+    }
 }
 
 /// The distance function defined in [arXiv:2109.07851](https://arxiv.org/abs/2109.07851)
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+///
+/// Optionally, the contributions from different particle types can
+/// be weighted differently, see [TypeWeights].
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct EuclWithScaledPt {
     pt_weight: N64,
+    unmatched_penalty: N64,
+    type_weights: BTreeMap<ParticleID, TypeWeights>,
+}
+
+impl Default for EuclWithScaledPt {
+    fn default() -> Self {
+        Self::new(n64(0.))
+    }
 }
 
 impl Distance for EuclWithScaledPt {
     fn distance(&self, ev1: &Event, ev2: &Event) -> N64 {
-        let mut dist = n64(0.);
-        let out1 = ev1.outgoing();
-        let out2 = ev2.outgoing();
-        let mut idx1 = 0;
-        let mut idx2 = 0;
-        while idx1 < out1.len() && idx2 < out2.len() {
-            let (t1, p1) = &out1[idx1];
-            let (t2, p2) = &out2[idx2];
+        self.distance_by_pid(
+            ev1.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+            ev2.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+        )
+    }
+
+    fn distance_within(
+        &self,
+        ev1: &Event,
+        ev2: &Event,
+        max_dist: N64,
+    ) -> Option<N64> {
+        self.distance_by_pid_within(
+            ev1.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+            ev2.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+            max_dist,
+        )
+    }
+
+    fn lower_bound(&self, ev1: &Event, ev2: &Event) -> N64 {
+        self.lower_bound_by_pid(
+            ev1.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+            ev2.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+        )
+    }
+}
+
+impl EuclWithScaledPt {
+    /// Distance function with the given parameter τ = `pt_weight`
+    ///
+    /// See [arXiv:2109.07851](https://arxiv.org/abs/2109.07851) for a
+    /// definition of τ
+    pub fn new(pt_weight: N64) -> Self {
+        EuclWithScaledPt {
+            pt_weight,
+            unmatched_penalty: n64(1.),
+            type_weights: BTreeMap::new(),
+        }
+    }
+
+    /// Multiply the contribution of particles without partner in
+    /// the other event by `penalty`
+    ///
+    /// This only affects particle types without explicit
+    /// [TypeWeights]. The default penalty is 1. The penalty has to be
+    /// non-negative. For any penalty other than 1, the distance can
+    /// violate the triangle inequality and the [TreeSearch] may miss
+    /// nearest neighbours, so [NaiveNeighbourSearch] should be used
+    /// instead.
+    ///
+    /// [TreeSearch]: crate::neighbour_search::TreeSearch
+    /// [NaiveNeighbourSearch]: crate::neighbour_search::NaiveNeighbourSearch
+    pub fn with_unmatched_penalty(mut self, penalty: N64) -> Self {
+        self.unmatched_penalty = penalty;
+        self
+    }
+
+    /// Use the given weights for particles with id `pid`
+    pub fn with_type_weights(
+        mut self,
+        pid: ParticleID,
+        weights: TypeWeights,
+    ) -> Self {
+        self.type_weights.insert(pid, weights);
+        self
+    }
+
+    /// The weights for particles with id `pid`
+    pub fn type_weights(&self, pid: ParticleID) -> TypeWeights {
+        self.type_weights.get(&pid).copied().unwrap_or(TypeWeights {
+            scale: n64(1.),
+            pt_weight: self.pt_weight,
+            unmatched_penalty: self.unmatched_penalty,
+        })
+    }
+}
+
+impl ParticleDistance for EuclWithScaledPt {
+    fn particle_distance(
+        &self,
+        pid: ParticleID,
+        p: &FourVector,
+        q: &FourVector,
+    ) -> N64 {
+        let weights = self.type_weights(pid);
+        weights.scale * pt_dist(p, q, weights.pt_weight)
+    }
+
+    fn unpaired_distance(&self, pid: ParticleID, p: &FourVector) -> N64 {
+        let weights = self.type_weights(pid);
+        weights.scale
+            * weights.unmatched_penalty
+            * pt_norm(p, weights.pt_weight)
+    }
+
+    // the distance between two particles is the Euclidean distance
+    // between the four-vectors (p_x, p_y, p_z, τ p_⟂)
+    fn particle_norm(&self, pid: ParticleID, p: &FourVector) -> N64 {
+        let weights = self.type_weights(pid);
+        let factor = std::cmp::min(n64(1.), weights.unmatched_penalty);
+        weights.scale * factor * pt_norm(p, weights.pt_weight)
+    }
+}
+
+/// Weights for the particles of a given type in [EuclWithScaledPt]
+///
+/// The distance between two particles p and q of this type is
+/// ```text
+/// scale * √[(p - q)² + (pt_weight (p_⟂ - q_⟂))²],
+/// ```
+/// where (p - q)² is the squared Euclidean norm of the difference of
+/// the spatial momenta. A particle without partner contributes
+/// ```text
+/// scale * unmatched_penalty * √[p² + (pt_weight p_⟂)²].
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct TypeWeights {
+    /// Overall factor for the contribution of particles of this type
+    ///
+    /// Has to be non-negative.
+    pub scale: N64,
+    /// Weight τ of the transverse momentum difference
+    pub pt_weight: N64,
+    /// Additional factor for particles without partner
+    ///
+    /// Has to be non-negative. As for
+    /// [EuclWithScaledPt::with_unmatched_penalty], values other than 1
+    /// can violate the triangle inequality.
+    pub unmatched_penalty: N64,
+}
+
+fn pt_norm(p: &FourVector, pt_weight: N64) -> N64 {
+    pt_norm_sq(p, pt_weight).sqrt()
+}
+
+fn pt_norm_sq(p: &FourVector, pt_weight: N64) -> N64 {
+    let pt = pt_weight * p.pt();
+    p.spatial_norm_sq() + pt * pt
+}
+
+fn pt_dist(p: &FourVector, q: &FourVector, pt_weight: N64) -> N64 {
+    pt_dist_sq(p, q, pt_weight).sqrt()
+}
+
+fn pt_dist_sq(p: &FourVector, q: &FourVector, pt_weight: N64) -> N64 {
+    let dpt = pt_weight * (p.pt() - q.pt());
+    (*p - *q).spatial_norm_sq() + dpt * dpt
+}
+
+/// Distance in transverse momentum, rapidity, and azimuthal angle
+///
+/// The distance between two particles is
+/// ```text
+/// min(√[(w_pt Δpt)² + (w_y Δy)² + (w_φ Δφ)²], w_pt (pt_1 + pt_2)),
+/// ```
+/// where Δφ takes into account the periodicity of the azimuthal
+/// angle. Unlike [EuclWithScaledPt], this is invariant under boosts
+/// along the beam axis. A particle without partner in the other event
+/// contributes `w_pt pt`, so pairing two particles never costs more
+/// than leaving both without partner. As for [EuclWithScaledPt], only particles of
+/// the same type are paired and the event distance is the sum of
+/// particle distances for the optimal pairing.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct PtRapidityPhi {
+    pt_weight: N64,
+    rapidity_weight: N64,
+    phi_weight: N64,
+}
+
+impl PtRapidityPhi {
+    /// Distance function with the given weights for the transverse
+    /// momentum, the rapidity, and the azimuthal angle
+    ///
+    /// The weight for the transverse momentum is dimensionless, the
+    /// other weights have the dimension of a momentum.
+    pub fn new(pt_weight: N64, rapidity_weight: N64, phi_weight: N64) -> Self {
+        Self {
+            pt_weight,
+            rapidity_weight,
+            phi_weight,
+        }
+    }
+}
+
+impl Distance for PtRapidityPhi {
+    fn distance(&self, ev1: &Event, ev2: &Event) -> N64 {
+        self.distance_by_pid(
+            ev1.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+            ev2.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+        )
+    }
+
+    fn distance_within(
+        &self,
+        ev1: &Event,
+        ev2: &Event,
+        max_dist: N64,
+    ) -> Option<N64> {
+        self.distance_by_pid_within(
+            ev1.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+            ev2.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+            max_dist,
+        )
+    }
+
+    fn lower_bound(&self, ev1: &Event, ev2: &Event) -> N64 {
+        self.lower_bound_by_pid(
+            ev1.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+            ev2.outgoing().iter().map(|(t, p)| (*t, p.as_ref())),
+        )
+    }
+}
+
+impl ParticleDistance for PtRapidityPhi {
+    fn particle_distance(
+        &self,
+        _pid: ParticleID,
+        p: &FourVector,
+        q: &FourVector,
+    ) -> N64 {
+        let dpt = self.pt_weight * (p.pt() - q.pt());
+        let dy = self.rapidity_weight * (rapidity(p) - rapidity(q));
+        let dphi = (phi(p) - phi(q)).abs();
+        let dphi = if dphi > PI { n64(2. * PI) - dphi } else { dphi };
+        let dphi = self.phi_weight * dphi;
+        let dist = (dpt * dpt + dy * dy + dphi * dphi).sqrt();
+        // never more expensive than leaving both particles unpaired,
+        // otherwise the triangle inequality is violated
+        let unpaired = self.pt_weight * (p.pt() + q.pt());
+        std::cmp::min(dist, unpaired)
+    }
+
+    fn unpaired_distance(&self, _pid: ParticleID, p: &FourVector) -> N64 {
+        self.pt_weight * p.pt()
+    }
+
+    fn particle_norm(&self, _pid: ParticleID, p: &FourVector) -> N64 {
+        self.pt_weight * p.pt()
+    }
+}
+
+// rapidity, limited to a finite value for massless particles along
+// the beam axis
+fn rapidity(p: &FourVector) -> N64 {
+    const MAX_RAPIDITY: f64 = 100.;
+    let e = f64::from(p[0]);
+    let pz = f64::from(p[3]);
+    if pz == 0. {
+        return n64(0.);
+    }
+    let mt = (e * e - pz * pz).max(0.).sqrt();
+    n64((pz / mt).asinh().clamp(-MAX_RAPIDITY, MAX_RAPIDITY))
+}
+
+fn phi(p: &FourVector) -> N64 {
+    p[2].atan2(p[1])
+}
+
+/// The energy mover's distance
+///
+/// The [energy mover's distance](https://arxiv.org/abs/1902.02346) is
+/// the minimum cost of rearranging the transverse momenta of the
+/// particles in one event into those of the other event. Moving a
+/// transverse momentum `f` between two particles costs
+/// ```text
+/// f (θ / R)^β,
+/// ```
+/// where θ = √(Δy² + Δφ²) is the distance between the particles in
+/// the rapidity-azimuth plane. The difference between the total
+/// transverse momenta of the two events is added to the distance.
+/// Unlike for [EuclWithScaledPt] and [PtRapidityPhi], particles of
+/// all types are compared with each other and a particle can be
+/// matched to several particles in the other event.
+///
+/// For β = 1, this is a metric if R is at least half the largest
+/// distance θ between any two particles. The cost of computing the
+/// distance grows roughly with the third power of the number of
+/// particles.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct EnergyMoversDistance {
+    radius: N64,
+    beta: N64,
+}
+
+impl EnergyMoversDistance {
+    /// Energy mover's distance with radius parameter `radius` and β = 1
+    ///
+    /// The radius has to be positive.
+    pub fn new(radius: N64) -> Self {
+        Self {
+            radius,
+            beta: n64(1.),
+        }
+    }
+
+    /// Set the angular exponent β
+    ///
+    /// The exponent has to be positive.
+    pub fn with_beta(mut self, beta: N64) -> Self {
+        self.beta = beta;
+        self
+    }
+
+    /// Distance between two sets of outgoing momenta
+    pub(crate) fn emd<'a, 'b>(
+        &self,
+        out1: impl IntoIterator<Item = &'a FourVector>,
+        out2: impl IntoIterator<Item = &'b FourVector>,
+    ) -> N64 {
+        let coords = |p: &FourVector| -> [f64; 3] {
+            [p.pt().into(), rapidity(p).into(), phi(p).into()]
+        };
+        let p1: Vec<_> = out1.into_iter().map(coords).collect();
+        let p2: Vec<_> = out2.into_iter().map(coords).collect();
+        let mut supply: Vec<_> = p1.iter().map(|p| p[0]).collect();
+        let mut demand: Vec<_> = p2.iter().map(|p| p[0]).collect();
+        // balance the total transverse momenta with an extra particle
+        // that costs one per unit of transverse momentum
+        let pt1: f64 = supply.iter().sum();
+        let pt2: f64 = demand.iter().sum();
+        if pt1 < pt2 {
+            supply.push(pt2 - pt1);
+        } else if pt2 < pt1 {
+            demand.push(pt1 - pt2);
+        }
+        let cost = |i: usize, j: usize| match (p1.get(i), p2.get(j)) {
+            (Some(p), Some(q)) => self.ground_distance(p, q),
+            _ => 1.,
+        };
+        n64(transport::min_cost(&supply, &demand, cost))
+    }
+
+    /// Lower bound on the distance between two sets of outgoing momenta
+    pub(crate) fn emd_lower_bound<'a, 'b>(
+        &self,
+        out1: impl IntoIterator<Item = &'a FourVector>,
+        out2: impl IntoIterator<Item = &'b FourVector>,
+    ) -> N64 {
+        let pt1: N64 = out1.into_iter().map(|p| p.pt()).sum();
+        let pt2: N64 = out2.into_iter().map(|p| p.pt()).sum();
+        (pt1 - pt2).abs()
+    }
+
+    // cost per unit transverse momentum between particles with
+    // coordinates (pt, y, φ)
+    fn ground_distance(&self, p: &[f64; 3], q: &[f64; 3]) -> f64 {
+        let dy = p[1] - q[1];
+        let dphi = (p[2] - q[2]).abs();
+        let dphi = if dphi > PI { 2. * PI - dphi } else { dphi };
+        let theta = (dy * dy + dphi * dphi).sqrt() / f64::from(self.radius);
+        if self.beta == 1. {
+            theta
+        } else {
+            theta.powf(self.beta.into())
+        }
+    }
+}
+
+impl Distance for EnergyMoversDistance {
+    fn distance(&self, ev1: &Event, ev2: &Event) -> N64 {
+        self.emd(all_outgoing(ev1), all_outgoing(ev2))
+    }
+
+    fn lower_bound(&self, ev1: &Event, ev2: &Event) -> N64 {
+        self.emd_lower_bound(all_outgoing(ev1), all_outgoing(ev2))
+    }
+}
+
+fn all_outgoing(ev: &Event) -> impl Iterator<Item = &FourVector> {
+    ev.outgoing().iter().flat_map(|(_, p)| p.iter())
+}
+
+/// Distance between the values of a list of observables
+///
+/// Each event is mapped to the vector of the weighted observable
+/// values. The distance is then the L1 or L2 norm of the difference
+/// of these vectors. An observable that cannot be computed for an
+/// event, e.g. the transverse momentum of the second jet in a
+/// one-jet event, is taken to be zero.
+///
+/// # Example
+///
+/// ```
+/// use cres::distance::{Norm, ObservableDistance};
+/// use noisy_float::prelude::*;
+///
+/// let ht = "ht(jet)".parse().unwrap();
+/// let mjj = "m(jet,1,jet,2)".parse().unwrap();
+/// let distance = ObservableDistance::new(Norm::L2)
+///     .with_observable(ht, n64(1.))
+///     .with_observable(mjj, n64(0.5));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObservableDistance {
+    observables: Vec<(Observable, N64)>,
+    norm: Norm,
+}
+
+/// Norm of a vector
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Norm {
+    /// Sum of the absolute values of the components
+    L1,
+    /// Euclidean norm
+    #[default]
+    L2,
+}
+
+impl ObservableDistance {
+    /// Distance without any observables, using the given `norm`
+    pub fn new(norm: Norm) -> Self {
+        Self {
+            observables: Vec::new(),
+            norm,
+        }
+    }
+
+    /// Add an observable with the given weight
+    pub fn with_observable(
+        mut self,
+        observable: Observable,
+        weight: N64,
+    ) -> Self {
+        self.observables.push((observable, weight));
+        self
+    }
+
+    /// The observables with their weights
+    pub fn observables(&self) -> &[(Observable, N64)] {
+        &self.observables
+    }
+}
+
+impl<E: OutgoingMomenta> Distance<E> for ObservableDistance {
+    fn distance(&self, ev1: &E, ev2: &E) -> N64 {
+        let diffs = self.observables.iter().map(|(observable, weight)| {
+            let x1 = observable.value(ev1).unwrap_or(n64(0.));
+            let x2 = observable.value(ev2).unwrap_or(n64(0.));
+            *weight * (x1 - x2).abs()
+        });
+        match self.norm {
+            Norm::L1 => diffs.sum(),
+            Norm::L2 => diffs.map(|d| d * d).sum::<N64>().sqrt(),
+        }
+    }
+}
+
+/// Distance between two particles
+///
+/// This is used to find the optimal pairing between the outgoing
+/// particles of two events. Only particles with the same id are
+/// paired. The event distance is the sum over the distances of all
+/// pairs and the distances of all particles without partner.
+pub(crate) trait ParticleDistance {
+    /// Distance between two particles with id `pid`
+    fn particle_distance(
+        &self,
+        pid: ParticleID,
+        p: &FourVector,
+        q: &FourVector,
+    ) -> N64;
+
+    /// Contribution of a particle with id `pid` without partner in
+    /// the other event
+    fn unpaired_distance(&self, pid: ParticleID, p: &FourVector) -> N64;
+
+    /// Norm used for the [lower bound](Distance::lower_bound) on
+    /// the distance
+    ///
+    /// The norm has to satisfy
+    /// `|norm(p) - norm(q)| <= particle_distance(p, q)` and
+    /// `norm(p) <= unpaired_distance(p)`. The default is zero.
+    fn particle_norm(&self, _pid: ParticleID, _p: &FourVector) -> N64 {
+        n64(0.)
+    }
+
+    /// Lower bound on the distance between two sets of outgoing momenta
+    ///
+    /// For each particle type, the distance is at least the difference
+    /// between the summed [norms](ParticleDistance::particle_norm) in
+    /// the two events.
+    fn lower_bound_by_pid<'a, 'b>(
+        &self,
+        out1: impl IntoIterator<Item = (ParticleID, &'a [FourVector])>,
+        out2: impl IntoIterator<Item = (ParticleID, &'b [FourVector])>,
+    ) -> N64 {
+        let norm_sum = |pid, p: &[FourVector]| -> N64 {
+            p.iter().map(|p| self.particle_norm(pid, p)).sum()
+        };
+        let mut bound = n64(0.);
+        let mut out1 = out1.into_iter().peekable();
+        let mut out2 = out2.into_iter().peekable();
+        while let (Some((t1, p1)), Some((t2, p2))) = (out1.peek(), out2.peek())
+        {
             match t1.cmp(t2) {
                 Ordering::Greater => {
-                    dist += self.pt_norm(p1);
-                    idx1 += 1;
+                    bound += norm_sum(*t1, p1);
+                    out1.next();
                 }
                 Ordering::Less => {
-                    dist += self.pt_norm(p2);
-                    idx2 += 1;
+                    bound += norm_sum(*t2, p2);
+                    out2.next();
                 }
                 Ordering::Equal => {
-                    dist += self.set_distance(p1, p2);
-                    idx1 += 1;
-                    idx2 += 1;
+                    bound += (norm_sum(*t1, p1) - norm_sum(*t2, p2)).abs();
+                    out1.next();
+                    out2.next();
                 }
             }
         }
-
-        // consume remainders
-        debug_assert!(idx1 >= out1.len() || idx2 >= out2.len());
-        if idx1 < out1.len() {
-            dist += out1[idx1..]
-                .iter()
-                .map(|(_t, p)| self.pt_norm(p))
-                .sum::<N64>();
-        } else if idx2 < out2.len() {
-            dist += out2[idx2..]
-                .iter()
-                .map(|(_t, p)| self.pt_norm(p))
-                .sum::<N64>();
-        }
-        dist
+        bound += out1.map(|(t, p)| norm_sum(t, p)).sum::<N64>();
+        bound += out2.map(|(t, p)| norm_sum(t, p)).sum::<N64>();
+        bound
     }
-}
 
-impl EuclWithScaledPt {
-    /// Distance function with the given parameter τ = `pt_weight`
+    /// Distance between two sets of outgoing momenta
     ///
-    /// See [arXiv:2109.07851](https://arxiv.org/abs/2109.07851) for a
-    /// definition of τ
-    pub fn new(pt_weight: N64) -> Self {
-        EuclWithScaledPt { pt_weight }
+    /// The momenta have to be grouped by particle id, in the same order
+    /// as [Event::outgoing].
+    fn distance_by_pid<'a, 'b>(
+        &self,
+        out1: impl IntoIterator<Item = (ParticleID, &'a [FourVector])>,
+        out2: impl IntoIterator<Item = (ParticleID, &'b [FourVector])>,
+    ) -> N64 {
+        self.distance_by_pid_within(out1, out2, N64::infinity())
+            .unwrap()
     }
 
-    fn pt_norm(&self, p: &[FourVector]) -> N64 {
-        p.iter().map(|p| pt_norm(p, self.pt_weight)).sum()
+    /// Distance between two sets of outgoing momenta if it does not
+    /// exceed `max_dist`
+    ///
+    /// See [distance_by_pid](ParticleDistance::distance_by_pid) and
+    /// [Distance::distance_within].
+    fn distance_by_pid_within<'a, 'b>(
+        &self,
+        out1: impl IntoIterator<Item = (ParticleID, &'a [FourVector])>,
+        out2: impl IntoIterator<Item = (ParticleID, &'b [FourVector])>,
+        max_dist: N64,
+    ) -> Option<N64> {
+        let mut dist = n64(0.);
+        let mut out1 = out1.into_iter().peekable();
+        let mut out2 = out2.into_iter().peekable();
+        while let (Some((t1, p1)), Some((t2, p2))) = (out1.peek(), out2.peek())
+        {
+            match t1.cmp(t2) {
+                Ordering::Greater => {
+                    dist += self.unpaired_sum(*t1, p1);
+                    out1.next();
+                }
+                Ordering::Less => {
+                    dist += self.unpaired_sum(*t2, p2);
+                    out2.next();
+                }
+                Ordering::Equal => {
+                    dist +=
+                        self.min_paired_distance(*t1, p1, p2, max_dist - dist)?;
+                    out1.next();
+                    out2.next();
+                }
+            }
+            if dist > max_dist {
+                return None;
+            }
+        }
+
+        // consume remainders
+        dist += out1.map(|(t, p)| self.unpaired_sum(t, p)).sum::<N64>();
+        dist += out2.map(|(t, p)| self.unpaired_sum(t, p)).sum::<N64>();
+        (dist <= max_dist).then_some(dist)
     }
 
-    fn set_distance(&self, p1: &[FourVector], p2: &[FourVector]) -> N64 {
-        self.min_paired_distance(p1, p2)
+    fn unpaired_sum(&self, pid: ParticleID, p: &[FourVector]) -> N64 {
+        p.iter().map(|p| self.unpaired_distance(pid, p)).sum()
     }
 
-    fn min_paired_distance(&self, p1: &[FourVector], p2: &[FourVector]) -> N64 {
+    /// Minimum distance over all pairings, or `None` if it certainly
+    /// exceeds `max_dist`
+    fn min_paired_distance(
+        &self,
+        pid: ParticleID,
+        p1: &[FourVector],
+        p2: &[FourVector],
+        max_dist: N64,
+    ) -> Option<N64> {
         if p1.len() > p2.len() {
-            return self.min_paired_distance(p2, p1);
+            return self.min_paired_distance(pid, p2, p1, max_dist);
         }
         debug_assert!(p1.len() <= p2.len());
-        // copy and pad with zeros
-        let zero = FourVector::new();
-        let mut p1 = p1.to_vec();
-        p1.resize_with(p2.len(), || zero);
+        // pad with missing particles
+        let mut p1: Vec<_> = p1.iter().map(Some).collect();
+        p1.resize(p2.len(), None);
         p1.sort_unstable();
 
         // TODO: find optimum value (either 3 or 4)
         const MAX_PART_NAIVE: usize = 3;
         match p1.len() {
-            0 => n64(0.),
-            1 => pt_dist(&p1[0], &p2[0], self.pt_weight),
-            2..=MAX_PART_NAIVE => self.min_paired_distance_naive(&mut p1, p2),
-            _ => self.min_paired_distance_hungarian(&p1, p2),
+            0 => Some(n64(0.)),
+            1 => Some(self.maybe_paired_distance(pid, p1[0], &p2[0])),
+            2..=MAX_PART_NAIVE => {
+                Some(self.min_paired_distance_naive(pid, &mut p1, p2))
+            }
+            _ => self.min_paired_distance_assignment(pid, &p1, p2, max_dist),
         }
     }
 
     fn min_paired_distance_naive(
         &self,
-        p1: &mut [FourVector],
+        pid: ParticleID,
+        p1: &mut [Option<&FourVector>],
         p2: &[FourVector],
     ) -> N64 {
-        let mut min_dist = self.paired_distance(p1, p2);
+        let mut min_dist = self.paired_distance(pid, p1, p2);
         while p1.next_permutation() {
-            min_dist = std::cmp::min(min_dist, self.paired_distance(p1, p2));
+            let dist = self.paired_distance(pid, p1, p2);
+            min_dist = std::cmp::min(min_dist, dist);
         }
         min_dist
     }
 
-    fn min_paired_distance_hungarian(
+    fn min_paired_distance_assignment(
         &self,
-        p1: &[FourVector],
+        pid: ParticleID,
+        p1: &[Option<&FourVector>],
         p2: &[FourVector],
-    ) -> N64 {
-        let weights = SquareMatrix::from_iter(
-            p1.iter()
-                .cartesian_product(p2.iter())
-                .map(|(p, q)| pt_dist(p, q, self.pt_weight)),
-        );
-        kuhn_munkres_min(&weights).0
+        max_dist: N64,
+    ) -> Option<N64> {
+        assignment::with_scratch(|scratch| {
+            scratch.set_costs(p1.len(), |i, j| {
+                self.maybe_paired_distance(pid, p1[i], &p2[j]).into()
+            });
+            if max_dist < scratch.lower_bound() {
+                return None;
+            }
+            Some(n64(scratch.solve()))
+        })
     }
 
-    fn paired_distance(&self, p1: &[FourVector], p2: &[FourVector]) -> N64 {
+    fn paired_distance(
+        &self,
+        pid: ParticleID,
+        p1: &[Option<&FourVector>],
+        p2: &[FourVector],
+    ) -> N64 {
         debug_assert!(p1.len() == p2.len());
         p1.iter()
             .zip(p2.iter())
-            .map(|(p1, p2)| pt_dist(p1, p2, self.pt_weight))
+            .map(|(p1, p2)| self.maybe_paired_distance(pid, *p1, p2))
             .sum()
     }
-}
-
-fn pt_norm(p: &FourVector, pt_weight: N64) -> N64 {
-    pt_norm_sq(p, pt_weight).sqrt()
-}
-
-fn pt_norm_sq(p: &FourVector, pt_weight: N64) -> N64 {
-    let pt = pt_weight * p.pt();
-    p.spatial_norm_sq() + pt * pt
-}
-
-fn pt_dist(p: &FourVector, q: &FourVector, pt_weight: N64) -> N64 {
-    pt_dist_sq(p, q, pt_weight).sqrt()
-}
 
-fn pt_dist_sq(p: &FourVector, q: &FourVector, pt_weight: N64) -> N64 {
-    let dpt = pt_weight * (p.pt() - q.pt());
-    (*p - *q).spatial_norm_sq() + dpt * dpt
+    fn maybe_paired_distance(
+        &self,
+        pid: ParticleID,
+        p: Option<&FourVector>,
+        q: &FourVector,
+    ) -> N64 {
+        match p {
+            Some(p) => self.particle_distance(pid, p, q),
+            None => self.unpaired_distance(pid, q),
+        }
+    }
 }
 
 /// Wrapper around distances storing also the events
@@ -182,72 +755,221 @@ impl<'a, 'b, D: Distance> Distance<usize> for DistWrapper<'a, 'b, D> {
     fn distance(&self, e1: &usize, e2: &usize) -> N64 {
         self.ev_dist.distance(&self.events[*e1], &self.events[*e2])
     }
-}
-
-#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
-struct SquareMatrix {
-    entries: Vec<N64>,
-    rows: usize,
-}
 
-impl Index<(usize, usize)> for SquareMatrix {
-    type Output = N64;
+    fn distance_within(
+        &self,
+        e1: &usize,
+        e2: &usize,
+        max_dist: N64,
+    ) -> Option<N64> {
+        self.ev_dist.distance_within(
+            &self.events[*e1],
+            &self.events[*e2],
+            max_dist,
+        )
+    }
 
-    fn index(&self, index: (usize, usize)) -> &Self::Output {
-        let (row, col) = index;
-        &self.entries[row * self.rows + col]
+    fn lower_bound(&self, e1: &usize, e2: &usize) -> N64 {
+        self.ev_dist
+            .lower_bound(&self.events[*e1], &self.events[*e2])
     }
 }
 
-impl IndexMut<(usize, usize)> for SquareMatrix {
-    fn index_mut(&mut self, index: (usize, usize)) -> &mut Self::Output {
-        let (row, col) = index;
-        &mut self.entries[row * self.rows + col]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::event::test_event;
+    use itertools::Itertools;
+
+    fn event(momenta: &[[f64; 4]]) -> Event {
+        event_with_pid(81, momenta)
     }
-}
 
-impl Weights<N64> for SquareMatrix {
-    fn rows(&self) -> usize {
-        self.rows
+    fn event_with_pid(pid: i32, momenta: &[[f64; 4]]) -> Event {
+        let outgoing: Vec<_> = momenta.iter().map(|&p| (pid, p)).collect();
+        test_event(0, 1., &outgoing)
     }
 
-    fn columns(&self) -> usize {
-        self.rows()
+    // massless momentum with the given pt, rapidity, and azimuthal angle
+    fn momentum(pt: f64, y: f64, phi: f64) -> [f64; 4] {
+        let [e, pz] = [pt * y.cosh(), pt * y.sinh()];
+        [e, pt * phi.cos(), pt * phi.sin(), pz]
     }
 
-    fn at(&self, row: usize, col: usize) -> N64 {
-        self[(row, col)]
+    #[test]
+    fn tst_pt_rapidity_phi() {
+        let dist = PtRapidityPhi::new(n64(1.), n64(10.), n64(100.));
+
+        let ev1 = event(&[momentum(50., 0.5, PI - 0.1)]);
+        let ev2 = event(&[momentum(40., 1.5, -PI + 0.1)]);
+        let expected = (10f64.powi(2) + 10f64.powi(2) + 20f64.powi(2)).sqrt();
+        assert!((dist.distance(&ev1, &ev2) - expected).abs() < 1e-9);
+
+        // invariance under longitudinal boosts
+        let ev1 = event(&[momentum(50., 0.5, 1.), momentum(30., -1., 2.)]);
+        let ev2 = event(&[momentum(45., 0.7, 1.2), momentum(35., 0., 2.)]);
+        let ev1_boosted =
+            event(&[momentum(50., 2.5, 1.), momentum(30., 1., 2.)]);
+        let ev2_boosted =
+            event(&[momentum(45., 2.7, 1.2), momentum(35., 2., 2.)]);
+        let d = dist.distance(&ev1, &ev2);
+        let d_boosted = dist.distance(&ev1_boosted, &ev2_boosted);
+        assert!((d - d_boosted).abs() < 1e-9);
+
+        // unpaired particles
+        let ev2 = event(&[momentum(50., 0.5, 1.)]);
+        assert!((dist.distance(&ev1, &ev2) - 30.).abs() < 1e-9);
     }
 
-    fn neg(&self) -> Self {
-        let entries = self.entries.iter().map(|e| -e).collect();
-        Self {
-            entries,
-            rows: self.rows,
+    #[test]
+    fn tst_pt_rapidity_phi_search() {
+        use crate::neighbour_search::{
+            NaiveNeighbourSearch, NeighbourData, NeighbourSearch, TreeSearch,
+        };
+        use rand::{Rng, SeedableRng};
+        use rand_xoshiro::Xoshiro256Plus;
+
+        // the tree search relies on the triangle inequality
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let events: Vec<_> = (0..150)
+            .map(|_| {
+                let momenta: Vec<_> = (0..rng.gen_range(1..=4))
+                    .map(|_| {
+                        let [pt, y, phi]: [f64; 3] = rng.gen();
+                        momentum(20. + 100. * pt, 5. * y - 2.5, 6. * phi)
+                    })
+                    .collect();
+                event(&momenta)
+            })
+            .collect();
+        let dist = PtRapidityPhi::new(n64(1.), n64(50.), n64(50.));
+        let dist = DistWrapper::new(&dist, &events);
+        let inf = N64::infinity();
+        let tree = TreeSearch::new_with_dist(events.len(), &dist, inf);
+        let naive =
+            NaiveNeighbourSearch::new_with_dist(events.len(), &dist, inf);
+        for n in 0..events.len() {
+            let from_tree = tree.nearest_in(&n, &dist).take(3);
+            let from_naive = naive.nearest_in(&n, &dist).take(3);
+            for ((_, d1), (_, d2)) in from_tree.zip(from_naive) {
+                assert!((d1 - d2).abs() < d1 * 1e-9);
+            }
         }
     }
-}
 
-impl Display for SquareMatrix {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for row in 0..self.rows {
-            write!(f, "(")?;
-            for col in 0..self.rows {
-                write!(f, " {:^6.2} ", self[(row, col)])?;
+    #[test]
+    fn tst_type_weights() {
+        let ev1 = event_with_pid(82, &[[13., 5., 12., 0.], [5., 0., 3., 4.]]);
+        let ev2 = event_with_pid(82, &[[13., 5., 12., 0.]]);
+        let dist = EuclWithScaledPt::new(n64(0.));
+        assert_eq!(dist.distance(&ev1, &ev2), 5.);
+
+        let dist = dist.with_unmatched_penalty(n64(2.));
+        assert_eq!(dist.distance(&ev1, &ev2), 10.);
+
+        let weights = TypeWeights {
+            scale: n64(3.),
+            pt_weight: n64(0.),
+            unmatched_penalty: n64(1.),
+        };
+        let dist = dist.with_type_weights(ParticleID::new(82), weights);
+        assert_eq!(dist.distance(&ev1, &ev2), 15.);
+        let ev1 = event(&[[13., 5., 12., 0.], [5., 0., 3., 4.]]);
+        let ev2 = event(&[[13., 5., 12., 0.]]);
+        assert_eq!(dist.distance(&ev1, &ev2), 10.);
+    }
+
+    #[test]
+    fn tst_penalty_triangle_inequality() {
+        // only a penalty of 1 gives a metric
+        let a = event(&[[10., 10., 0., 0.]]);
+        let b = event(&[]);
+        let q = event(&[[10., -10., 0., 0.]]);
+        let dist = EuclWithScaledPt::new(n64(0.))
+            .with_unmatched_penalty(n64(0.7));
+        let d_aq = dist.distance(&a, &q);
+        let d_ab = dist.distance(&a, &b);
+        let d_bq = dist.distance(&b, &q);
+        assert_eq!(d_aq, 20.);
+        assert!((d_ab + d_bq - 14.).abs() < 1e-9);
+        assert!(d_aq > d_ab + d_bq);
+    }
+
+    #[test]
+    fn tst_bounds() {
+        use rand::{Rng, SeedableRng};
+        use rand_xoshiro::Xoshiro256Plus;
+
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let mut random_event = |n| {
+            let momenta: Vec<_> = (0..n)
+                .map(|_| {
+                    let [pt, y, phi]: [f64; 3] = rng.gen();
+                    momentum(100. * pt, 4. * y - 2., 6. * phi)
+                })
+                .collect();
+            event(&momenta)
+        };
+        let events: Vec<_> = (1..8).map(&mut random_event).collect();
+        for penalty in [0.5, 2.] {
+            let dist = EuclWithScaledPt::new(n64(1.))
+                .with_unmatched_penalty(n64(penalty));
+            for (ev1, ev2) in events.iter().cartesian_product(&events) {
+                let d = dist.distance(ev1, ev2);
+                assert!(dist.lower_bound(ev1, ev2) <= d);
+                assert_eq!(dist.distance_within(ev1, ev2, d), Some(d));
+                if d > 0. {
+                    let max_dist = d * 0.99;
+                    assert_eq!(dist.distance_within(ev1, ev2, max_dist), None);
+                }
             }
-            writeln!(f, ")")?;
         }
-        Ok(())
     }
-}
-impl FromIterator<N64> for SquareMatrix {
-    fn from_iter<T: IntoIterator<Item = N64>>(iter: T) -> Self {
-        let entries = Vec::from_iter(iter);
-        let rows = (entries.len() as f64).sqrt();
-        assert_eq!(rows.fract(), 0.);
-        Self {
-            entries,
-            rows: rows as usize,
-        }
+
+    #[test]
+    fn tst_energy_movers_distance() {
+        let dist = EnergyMoversDistance::new(n64(2.));
+
+        let ev1 = event(&[momentum(50., 0.5, 1.), momentum(30., -1., 2.)]);
+        assert_eq!(dist.distance(&ev1, &ev1), 0.);
+
+        // move one particle
+        let ev2 = event(&[momentum(50., 0.5, 1.), momentum(30., -1., 3.)]);
+        assert!((dist.distance(&ev1, &ev2) - 15.).abs() < 1e-9);
+
+        // split one particle, with an extra unit of transverse momentum
+        let ev2 = event(&[
+            momentum(50., 0.5, 1.),
+            momentum(20., -1., 2.),
+            momentum(11., 1., 2.),
+        ]);
+        let expected = 10. + 1.;
+        assert!((dist.distance(&ev1, &ev2) - expected).abs() < 1e-9);
+        assert!((dist.distance(&ev2, &ev1) - expected).abs() < 1e-9);
+        assert_eq!(dist.lower_bound(&ev1, &ev2), 1.);
+
+        // particles of different types are compared with each other
+        let ev2 = test_event(
+            0,
+            1.,
+            &[(82, momentum(50., 0.5, 1.)), (81, momentum(30., -1., 2.))],
+        );
+        assert!(dist.distance(&ev1, &ev2).abs() < 1e-9);
+
+        let dist = dist.with_beta(n64(2.));
+        let ev2 = event(&[momentum(50., 0.5, 1.), momentum(30., -1., 3.)]);
+        assert!((dist.distance(&ev1, &ev2) - 7.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tst_observables() {
+        let ev1 = event(&[momentum(50., 1., 0.), momentum(30., -1., 2.)]);
+        let ev2 = event(&[momentum(40., 0.5, 1.)]);
+        let dist = ObservableDistance::new(Norm::L1)
+            .with_observable("ht(jet)".parse().unwrap(), n64(1.))
+            .with_observable("dy(jet,1,jet,2)".parse().unwrap(), n64(10.))
+            .with_observable("n(jet)".parse().unwrap(), n64(5.));
+        assert!((dist.distance(&ev1, &ev2) - 65.).abs() < 1e-9);
     }
 }
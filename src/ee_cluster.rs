@@ -0,0 +1,302 @@
+//! Jet clustering for lepton colliders
+//!
+//! The e⁺e⁻ algorithms use energies and angles between momenta instead
+//! of transverse momenta and rapidity-azimuth distances. To avoid
+//! recomputing all pairwise distances in each step, we cache the
+//! nearest angular neighbour of each pseudojet. The smallest distance
+//! min(w_i, w_j) (1 - cos θ_ij) is always found between some pseudojet
+//! and its nearest angular neighbour.
+use std::f64::consts::PI;
+
+use jetty::{ClusterStep, PseudoJet};
+
+use crate::cluster::ExclusiveMode;
+
+// marks a pseudojet without any neighbours
+const NO_NEIGHBOUR: (usize, f64) = (usize::MAX, f64::INFINITY);
+
+/// Clustering history for e⁺e⁻ jet algorithms
+#[derive(Clone, Debug)]
+pub(crate) struct EeClusterHistory {
+    pseudojets: Vec<PseudoJet>,
+    // energies raised to the power 2p
+    weights: Vec<f64>,
+    // nearest angular neighbour and its distance 1 - cos θ
+    nn: Vec<(usize, f64)>,
+    exponent: f64,
+    // prefactor of the distance between pseudojets
+    norm: f64,
+    // whether pseudojets with a small enough energy become jets
+    inclusive: bool,
+    stop: Option<ExclusiveMode>,
+    e_vis2: f64,
+    stopped: bool,
+}
+
+impl EeClusterHistory {
+    /// Exclusive clustering with the Durham algorithm
+    pub(crate) fn durham(partons: Vec<PseudoJet>, mode: ExclusiveMode) -> Self {
+        Self::new(partons, 1., 2., Some(mode))
+    }
+
+    /// Inclusive clustering with the generalised e⁺e⁻ kt algorithm
+    pub(crate) fn gen_kt(partons: Vec<PseudoJet>, radius: f64, p: f64) -> Self {
+        // same convention as FastJet for radii beyond π
+        let norm = if radius < PI {
+            1. / (1. - radius.cos())
+        } else {
+            1. / (3. + radius.cos())
+        };
+        Self::new(partons, p, norm, None)
+    }
+
+    fn new(
+        partons: Vec<PseudoJet>,
+        p: f64,
+        norm: f64,
+        stop: Option<ExclusiveMode>,
+    ) -> Self {
+        let e_vis: f64 = partons.iter().map(|p| p.e().raw()).sum();
+        let exponent = 2. * p;
+        let weights =
+            partons.iter().map(|q| q.e().raw().powf(exponent)).collect();
+        let mut res = Self {
+            nn: vec![NO_NEIGHBOUR; partons.len()],
+            pseudojets: partons,
+            weights,
+            exponent,
+            norm,
+            inclusive: stop.is_none(),
+            stop,
+            e_vis2: e_vis * e_vis,
+            stopped: false,
+        };
+        for i in 0..res.pseudojets.len() {
+            res.update_nn(i);
+        }
+        res
+    }
+
+    fn update_nn(&mut self, i: usize) {
+        let p = &self.pseudojets[i];
+        self.nn[i] = self
+            .pseudojets
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(j, q)| (j, one_minus_cos(p, q)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or(NO_NEIGHBOUR);
+    }
+
+    // smallest distance between pseudojets and the corresponding pair
+    fn min_pair(&self) -> Option<(f64, usize, usize)> {
+        self.nn
+            .iter()
+            .zip(&self.weights)
+            .enumerate()
+            .filter(|(_, ((j, _), _))| *j != usize::MAX)
+            .map(|(i, (&(j, angle), w))| (self.norm * w * angle, i, j))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    fn remove(&mut self, k: usize) -> PseudoJet {
+        let last = self.pseudojets.len() - 1;
+        let res = self.pseudojets.swap_remove(k);
+        self.weights.swap_remove(k);
+        self.nn.swap_remove(k);
+        for i in 0..self.pseudojets.len() {
+            if self.nn[i].0 == k {
+                self.update_nn(i);
+            } else if self.nn[i].0 == last {
+                self.nn[i].0 = k;
+            }
+        }
+        res
+    }
+
+    fn combine(&mut self, i: usize, j: usize) -> [PseudoJet; 2] {
+        let (i, j) = (i.min(j), i.max(j));
+        let res = [self.pseudojets[i], self.pseudojets[j]];
+        let merged = res[0] + res[1];
+        self.pseudojets[i] = merged;
+        self.weights[i] = merged.e().raw().powf(self.exponent);
+        // `i < j`, so `i` is not moved
+        self.remove(j);
+        for k in 0..self.pseudojets.len() {
+            if k == i {
+                continue;
+            }
+            if self.nn[k].0 == i {
+                self.update_nn(k);
+            } else {
+                let angle = one_minus_cos(&self.pseudojets[k], &merged);
+                if angle < self.nn[k].1 {
+                    self.nn[k] = (i, angle);
+                }
+            }
+        }
+        self.update_nn(i);
+        res
+    }
+}
+
+impl Iterator for EeClusterHistory {
+    type Item = ClusterStep;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(ExclusiveMode::NJets(njets)) = self.stop {
+            self.stopped |= self.pseudojets.len() <= njets;
+        }
+        if self.stopped {
+            return self.pseudojets.pop().map(ClusterStep::Jet);
+        }
+        let Some((dist, i, j)) = self.min_pair() else {
+            // at most one pseudojet left
+            return self.pseudojets.pop().map(ClusterStep::Jet);
+        };
+        if self.inclusive {
+            let beam = self
+                .weights
+                .iter()
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(b.1));
+            if let Some((k, &beam_dist)) = beam {
+                if beam_dist <= dist {
+                    return Some(ClusterStep::Jet(self.remove(k)));
+                }
+            }
+        }
+        if let Some(ExclusiveMode::YCut(ycut)) = self.stop {
+            if dist > ycut * self.e_vis2 {
+                self.stopped = true;
+                return self.pseudojets.pop().map(ClusterStep::Jet);
+            }
+        }
+        Some(ClusterStep::Combine(self.combine(i, j)))
+    }
+}
+
+// 1 - cos θ for the angle θ between the spatial momenta
+fn one_minus_cos(p: &PseudoJet, q: &PseudoJet) -> f64 {
+    let p = [p.px().raw(), p.py().raw(), p.pz().raw()];
+    let q = [q.px().raw(), q.py().raw(), q.pz().raw()];
+    let dot: f64 = p.iter().zip(&q).map(|(p, q)| p * q).sum();
+    let norm2 = |p: &[f64; 3]| p.iter().map(|p| p * p).sum::<f64>();
+    let norm = (norm2(&p) * norm2(&q)).sqrt();
+    if norm > 0. {
+        1. - dot / norm
+    } else {
+        1.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256Plus;
+
+    // cluster by recomputing all distances in each step
+    fn brute_force(
+        mut pseudojets: Vec<PseudoJet>,
+        p: f64,
+        norm: f64,
+        stop: Option<ExclusiveMode>,
+    ) -> Vec<PseudoJet> {
+        let e_vis: f64 = pseudojets.iter().map(|p| p.e().raw()).sum();
+        let w = |q: &PseudoJet| q.e().raw().powf(2. * p);
+        let mut jets = Vec::new();
+        loop {
+            if let Some(ExclusiveMode::NJets(n)) = stop {
+                if pseudojets.len() <= n {
+                    break;
+                }
+            }
+            let mut min = (f64::INFINITY, 0, 0);
+            for i in 0..pseudojets.len() {
+                if stop.is_none() && w(&pseudojets[i]) < min.0 {
+                    min = (w(&pseudojets[i]), i, i);
+                }
+                for j in 0..i {
+                    let (pi, pj) = (&pseudojets[i], &pseudojets[j]);
+                    let d = norm * w(pi).min(w(pj)) * one_minus_cos(pi, pj);
+                    if d < min.0 {
+                        min = (d, i, j);
+                    }
+                }
+            }
+            let (d, i, j) = min;
+            if d == f64::INFINITY {
+                break;
+            }
+            if let Some(ExclusiveMode::YCut(ycut)) = stop {
+                if d > ycut * e_vis * e_vis {
+                    break;
+                }
+            }
+            let pi = pseudojets.swap_remove(i);
+            if i == j {
+                jets.push(pi);
+            } else {
+                pseudojets[j] += pi;
+            }
+        }
+        jets.append(&mut pseudojets);
+        jets
+    }
+
+    fn sorted_energies(jets: impl IntoIterator<Item = PseudoJet>) -> Vec<f64> {
+        let mut energies =
+            Vec::from_iter(jets.into_iter().map(|p| p.e().raw()));
+        energies.sort_by(f64::total_cmp);
+        energies
+    }
+
+    #[test]
+    fn tst_ee_cluster() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        for n in [1, 2, 5, 20] {
+            let partons = Vec::from_iter((0..n).map(|_| {
+                let p = [(); 3].map(|_| rng.gen_range(-50. ..50.));
+                let e = p.iter().map(|p| p * p).sum::<f64>().sqrt();
+                PseudoJet::from([e, p[0], p[1], p[2]])
+            }));
+            let modes = [
+                ExclusiveMode::NJets(1),
+                ExclusiveMode::NJets(3),
+                ExclusiveMode::YCut(0.01),
+            ];
+            for mode in modes {
+                let jets = EeClusterHistory::durham(partons.clone(), mode)
+                    .filter_map(|step| match step {
+                        ClusterStep::Jet(jet) => Some(jet),
+                        _ => None,
+                    });
+                let expected = brute_force(partons.clone(), 1., 2., Some(mode));
+                let jets = sorted_energies(jets);
+                assert_eq!(jets.len(), expected.len());
+                for (e, expected) in jets.iter().zip(sorted_energies(expected))
+                {
+                    assert!((e - expected).abs() < 1e-10 * expected);
+                }
+            }
+            for p in [-1., 0., 1.] {
+                let jets = EeClusterHistory::gen_kt(partons.clone(), 0.7, p)
+                    .filter_map(|step| match step {
+                        ClusterStep::Jet(jet) => Some(jet),
+                        _ => None,
+                    });
+                let norm = 1. / (1. - 0.7f64.cos());
+                let expected = brute_force(partons.clone(), p, norm, None);
+                let jets = sorted_energies(jets);
+                assert_eq!(jets.len(), expected.len());
+                for (e, expected) in jets.iter().zip(sorted_energies(expected))
+                {
+                    assert!((e - expected).abs() < 1e-10 * expected);
+                }
+            }
+        }
+    }
+}
@@ -78,6 +78,29 @@ impl EventBuilder {
     }
 }
 
+/// Event with the given id, central weight, and outgoing particles
+///
+/// With the `multiweight` feature, the event has a second weight that
+/// is twice the central weight.
+#[cfg(test)]
+pub(crate) fn test_event(
+    id: usize,
+    weight: f64,
+    outgoing: &[(i32, [f64; 4])],
+) -> Event {
+    let mut ev = EventBuilder::with_capacity(outgoing.len());
+    #[cfg(feature = "multiweight")]
+    ev.weights(vec![n64(weight), n64(2. * weight)]);
+    #[cfg(not(feature = "multiweight"))]
+    ev.weights(n64(weight));
+    for (pid, p) in outgoing {
+        ev.add_outgoing(ParticleID::new(*pid), p.map(n64).into());
+    }
+    let mut ev = ev.build();
+    ev.id = id;
+    ev
+}
+
 impl From<EventBuilder> for Event {
     fn from(b: EventBuilder) -> Self {
         b.build()
@@ -162,6 +185,11 @@ impl Event {
         self.outgoing_by_pid
     }
 
+    /// Remove and return the outgoing particle momenta
+    pub(crate) fn take_outgoing(&mut self) -> Box<[(ParticleID, MomentumSet)]> {
+        std::mem::take(&mut self.outgoing_by_pid)
+    }
+
     /// Number of weights
     pub fn n_weights(&self) -> usize {
         #[cfg(feature = "multiweight")]
@@ -0,0 +1,282 @@
+//! On-disk cache of converted events
+//!
+//! Reading and converting events, in particular jet clustering, can
+//! take a significant fraction of the total run time. An
+//! [EventCache] saves the converted events to a file, so that later
+//! runs with the same input and the same conversion settings can
+//! skip this step. See
+//! [Cres::with_cache](crate::cres::Cres::with_cache) for how to
+//! enable the cache.
+//!
+//! The cache file starts with the 8 bytes `CRESEVCH`, followed by
+//! the format version, the cache key, the number of events, and the
+//! number of weights per event. For each event, we then store the id,
+//! the weights, the number of distinct particle ids, and for each
+//! particle id the id itself, the number of momenta, and the
+//! momenta. All numbers are stored in little-endian byte order.
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use log::{debug, info};
+use noisy_float::prelude::*;
+use particle_id::ParticleID;
+use thiserror::Error;
+
+use crate::checkpoint::stable_hasher;
+use crate::event::{Event, EventBuilder};
+use crate::VERSION;
+
+const MAGIC: &[u8; 8] = b"CRESEVCH";
+const FORMAT_VERSION: u64 = 1;
+
+/// Error reading from or writing to an event cache
+#[derive(Debug, Error)]
+pub enum CacheError {
+    /// I/O error
+    #[error("I/O error")]
+    IoErr(#[from] io::Error),
+    /// Invalid cache content
+    #[error("Invalid event cache: {0}")]
+    FormatErr(String),
+    /// Converted events without particle momenta
+    #[error("Cannot cache events without particle momenta")]
+    MomentaErr,
+}
+
+/// On-disk cache of converted events
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventCache {
+    file: PathBuf,
+    key: u64,
+}
+
+impl EventCache {
+    /// Cache for the given key in the directory `dir`
+    ///
+    /// The key should uniquely identify the input events and the
+    /// conversion settings. It can be computed with [cache_key].
+    pub fn new<P: AsRef<Path>>(dir: P, key: u64) -> Self {
+        let file = dir.as_ref().join(format!("{key:016x}.cres-cache"));
+        Self { file, key }
+    }
+
+    /// The file containing the cached events
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// Load the cached events
+    ///
+    /// Returns `None` if there is no cache file or if it was written
+    /// for a different key or with a different format version.
+    pub fn load(&self) -> Result<Option<Vec<Event>>, CacheError> {
+        use CacheError::FormatErr;
+
+        let file = match File::open(&self.file) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                debug!("No event cache found in {:?}", self.file);
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let mut input = BufReader::new(file);
+        let mut magic = [0u8; MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(FormatErr(format!("{:?} is no cache file", self.file)));
+        }
+        let version = read_u64(&mut input)?;
+        if version != FORMAT_VERSION {
+            info!("Ignoring event cache with outdated format {version}");
+            return Ok(None);
+        }
+        let key = read_u64(&mut input)?;
+        if key != self.key {
+            info!("Ignoring event cache with different key {key:016x}");
+            return Ok(None);
+        }
+        info!("Reading events from cache {:?}", self.file);
+        let nevents = read_u64(&mut input)? as usize;
+        let nweights = read_u64(&mut input)? as usize;
+        let mut events = Vec::with_capacity(nevents);
+        for _ in 0..nevents {
+            let id = read_u64(&mut input)? as usize;
+            let mut event = EventBuilder::new();
+            let weights = (0..nweights)
+                .map(|_| read_n64(&mut input))
+                .collect::<Result<Vec<_>, _>>()?;
+            #[cfg(feature = "multiweight")]
+            event.weights(weights);
+            #[cfg(not(feature = "multiweight"))]
+            {
+                if weights.len() != 1 {
+                    return Err(FormatErr(format!(
+                        "Expected one weight, found {}",
+                        weights.len()
+                    )));
+                }
+                event.weights(weights[0]);
+            }
+            let ntypes = read_u32(&mut input)?;
+            for _ in 0..ntypes {
+                let pid = ParticleID::new(read_u32(&mut input)? as i32);
+                let nmomenta = read_u32(&mut input)?;
+                for _ in 0..nmomenta {
+                    let mut p = [n64(0.); 4];
+                    for p in &mut p {
+                        *p = read_n64(&mut input)?;
+                    }
+                    event.add_outgoing(pid, p.into());
+                }
+            }
+            let mut event = event.build();
+            event.id = id;
+            events.push(event);
+        }
+        Ok(Some(events))
+    }
+
+    /// Save events to the cache
+    pub fn store(&self, events: &[Event]) -> Result<(), CacheError> {
+        info!("Writing events to cache {:?}", self.file);
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // write to temporary file first to avoid incomplete cache files
+        let mut tmp = self.file.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(MAGIC)?;
+        let nweights = events.first().map(|e| e.n_weights()).unwrap_or(1);
+        let header = [FORMAT_VERSION, self.key, events.len() as u64];
+        for entry in header {
+            out.write_all(&entry.to_le_bytes())?;
+        }
+        out.write_all(&(nweights as u64).to_le_bytes())?;
+        for event in events {
+            out.write_all(&(event.id() as u64).to_le_bytes())?;
+            let weights = event.weights.read();
+            #[cfg(feature = "multiweight")]
+            {
+                if weights.len() != nweights {
+                    return Err(CacheError::FormatErr(format!(
+                        "Event {} has {} weights, expected {nweights}",
+                        event.id(),
+                        weights.len()
+                    )));
+                }
+                for wt in weights.iter() {
+                    write_n64(&mut out, *wt)?;
+                }
+            }
+            #[cfg(not(feature = "multiweight"))]
+            write_n64(&mut out, *weights)?;
+            let outgoing = event.outgoing();
+            out.write_all(&(outgoing.len() as u32).to_le_bytes())?;
+            for (pid, momenta) in outgoing {
+                out.write_all(&pid.id().to_le_bytes())?;
+                out.write_all(&(momenta.len() as u32).to_le_bytes())?;
+                for p in momenta.iter() {
+                    for i in 0..4 {
+                        write_n64(&mut out, p[i])?;
+                    }
+                }
+            }
+        }
+        out.into_inner().map_err(|err| err.into_error())?;
+        fs::rename(&tmp, &self.file)?;
+        Ok(())
+    }
+}
+
+/// Compute a cache key from input files and conversion settings
+///
+/// The key depends on the cres version, the file names, sizes, and
+/// modification times, and the hash of `settings`. Like
+/// [settings_hash](crate::checkpoint::settings_hash), it is computed
+/// with SipHash-1-3 with fixed keys, so that the same input gives the
+/// same key in every run.
+pub fn cache_key<I, P, S>(files: I, settings: &S) -> Result<u64, io::Error>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+    S: Hash + ?Sized,
+{
+    let mut hasher = stable_hasher();
+    VERSION.hash(&mut hasher);
+    for file in files {
+        let file = file.as_ref();
+        let metadata = fs::metadata(file)?;
+        fs::canonicalize(file)?.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        if let Ok(modified) = metadata.modified() {
+            modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .hash(&mut hasher);
+        }
+    }
+    settings.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32, io::Error> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64, io::Error> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_n64(r: &mut impl Read) -> Result<N64, CacheError> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    let x = f64::from_le_bytes(buf);
+    N64::try_new(x).ok_or_else(|| CacheError::FormatErr(format!("{x}")))
+}
+
+fn write_n64(w: &mut impl Write, x: N64) -> Result<(), io::Error> {
+    w.write_all(&f64::from(x).to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::event::test_event;
+    use particle_id::sm_elementary_particles::{electron, photon};
+
+    #[test]
+    fn tst_cache_roundtrip() {
+        let out = [
+            (photon.id(), [3., 0., 3., 0.]),
+            (electron.id(), [2., 0., 0., 2.]),
+            (photon.id(), [1., 1., 0., 0.]),
+        ];
+        let events = vec![test_event(7, -1.5, &out), test_event(8, 1., &[])];
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = EventCache::new(dir.path(), 1);
+        assert!(cache.load().unwrap().is_none());
+        cache.store(&events).unwrap();
+        let cached = cache.load().unwrap().unwrap();
+        assert_eq!(cached, events);
+        assert_eq!(cached[0].id(), 7);
+        assert_eq!(cached[0].weight(), -1.5);
+        #[cfg(feature = "multiweight")]
+        assert_eq!(cached[0].n_weights(), 2);
+
+        std::fs::copy(cache.file(), EventCache::new(dir.path(), 2).file())
+            .unwrap();
+        assert!(EventCache::new(dir.path(), 2).load().unwrap().is_none());
+    }
+}
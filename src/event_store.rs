@@ -0,0 +1,588 @@
+//! Out-of-core storage of event momenta
+//!
+//! For very large samples the particle momenta of all events may not
+//! fit into memory. An [EventStore] keeps them in temporary files
+//! instead, which are memory-mapped once all events have been
+//! read. Only the event ids and weights are kept in memory.
+//!
+//! The usual setup is
+//!
+//! 1. Wrap the converter in a [StoringConverter]. This moves the
+//!    momenta of each converted event into the store.
+//! 2. Wrap the distance function in a [StoredDistance]. This looks up
+//!    the momenta of the events in the store.
+//!
+//! All other parts of the resampling, i.e. the
+//! [Resampler](crate::resampler::Resampler), the nearest-neighbour
+//! search, and the construction of [Cell](crate::cell::Cell)s, work
+//! as usual.
+//!
+//! # Example
+//!
+//! ```no_run
+//!# fn store_doc() -> Result<(), Box<dyn std::error::Error>> {
+//! use std::sync::Arc;
+//!
+//! use cres::distance::EuclWithScaledPt;
+//! use cres::event_store::{EventStore, StoredDistance, StoringConverter};
+//! use noisy_float::prelude::*;
+//!
+//! let store = Arc::new(EventStore::new_in("/scratch")?);
+//! let converter = StoringConverter::new(
+//!     cres::converter::Converter::new(),
+//!     store.clone()
+//! );
+//! let distance = StoredDistance::new(store, EuclWithScaledPt::new(n64(0.)));
+//!# Ok(())
+//!# }
+//! ```
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use memmap2::Mmap;
+use noisy_float::prelude::*;
+use parking_lot::Mutex;
+use particle_id::ParticleID;
+use thiserror::Error;
+
+use crate::distance::{
+    Distance, EnergyMoversDistance, EuclWithScaledPt, ParticleDistance,
+    PtRapidityPhi,
+};
+use crate::event::Event;
+use crate::four_vector::FourVector;
+use crate::observable::OutgoingMomenta;
+use crate::traits::TryConvert;
+
+/// Error in the event store
+#[derive(Debug, Error)]
+pub enum StoreError {
+    /// I/O error
+    #[error("I/O error in event store")]
+    IoErr(#[from] io::Error),
+    /// Event with a deviating number of weights
+    #[error("Event has {1} weights, expected {0}")]
+    NWeightsErr(usize, usize),
+    /// Tried to add an event after finishing the store
+    #[error("Event store has already been finished")]
+    FinishedErr,
+}
+
+/// On-disk storage of particle momenta and weights
+///
+/// The layout is fixed. Momenta are stored as consecutive
+/// [FourVector]s. For each event and each particle id, we store the
+/// range of the corresponding momenta. Weights are stored as
+/// consecutive floating-point numbers, with the same number of
+/// weights for each event.
+///
+/// The id of an event is its position in the store, i.e. the `n`th
+/// event added to the store must have id `n`. This is the case when
+/// using [Cres](crate::cres::Cres) together with a [StoringConverter].
+#[derive(Debug)]
+pub struct EventStore {
+    writer: Mutex<Option<StoreWriter>>,
+    data: OnceLock<StoreData>,
+}
+
+impl EventStore {
+    /// Create a new store in the default directory for temporary files
+    pub fn new() -> Result<Self, StoreError> {
+        Self::new_in(std::env::temp_dir())
+    }
+
+    /// Create a new store in the given directory
+    ///
+    /// The files backing the store are removed automatically.
+    pub fn new_in<P: AsRef<Path>>(dir: P) -> Result<Self, StoreError> {
+        Ok(Self {
+            writer: Mutex::new(Some(StoreWriter::new_in(dir.as_ref())?)),
+            data: OnceLock::new(),
+        })
+    }
+
+    /// Append the momenta and weights of an event to the store
+    pub fn push(&self, event: &Event) -> Result<(), StoreError> {
+        match self.writer.lock().as_mut() {
+            Some(writer) => writer.push(event),
+            None => Err(StoreError::FinishedErr),
+        }
+    }
+
+    /// Finish adding events
+    ///
+    /// This has to be called before accessing any events. Calling
+    /// this function more than once has no effect.
+    pub fn finish(&self) -> Result<(), StoreError> {
+        if let Some(writer) = self.writer.lock().take() {
+            let data = writer.finish()?;
+            self.data
+                .set(data)
+                .expect("Event store data should not be initialised");
+        }
+        Ok(())
+    }
+
+    /// Number of events in the store
+    ///
+    /// This is zero before calling [finish](Self::finish).
+    pub fn len(&self) -> usize {
+        self.data.get().map(|d| d.nevents()).unwrap_or_default()
+    }
+
+    /// Check whether there are no events in the store
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Access the event with the given id
+    ///
+    /// # Panics
+    ///
+    /// Panics if [finish](Self::finish) has not been called yet or
+    /// if there is no event with the given id.
+    pub fn event(&self, id: usize) -> StoredEvent<'_> {
+        let data = self.data();
+        let [start, end] = data.events()[id];
+        StoredEvent {
+            types: &data.types()[start as usize..end as usize],
+            momenta: data.momenta(),
+        }
+    }
+
+    /// Access the original weights of the event with the given id
+    ///
+    /// # Panics
+    ///
+    /// Panics if [finish](Self::finish) has not been called yet or
+    /// if there is no event with the given id.
+    pub fn weights(&self, id: usize) -> &[N64] {
+        let data = self.data();
+        let start = id * data.nweights;
+        &data.weights()[start..start + data.nweights]
+    }
+
+    fn data(&self) -> &StoreData {
+        self.data
+            .get()
+            .expect("Accessing event store before calling `finish`")
+    }
+}
+
+/// A view into an event in an [EventStore]
+#[derive(Copy, Clone, Debug)]
+pub struct StoredEvent<'a> {
+    types: &'a [TypeRange],
+    momenta: &'a [FourVector],
+}
+
+impl<'a> StoredEvent<'a> {
+    /// Access the outgoing particle momenta grouped by particle id
+    ///
+    /// The order is the same as for [Event::outgoing].
+    pub fn outgoing(
+        &self,
+    ) -> impl Iterator<Item = (ParticleID, &'a [FourVector])> + 'a {
+        let momenta = self.momenta;
+        self.types.iter().map(move |t| {
+            (
+                ParticleID::new(t.pid as i32),
+                &momenta[t.start as usize..t.end as usize],
+            )
+        })
+    }
+
+    /// Access the outgoing particle momenta with the given particle id
+    pub fn outgoing_with_pid(&self, pid: ParticleID) -> &'a [FourVector] {
+        self.outgoing()
+            .find_map(|(t, p)| (t == pid).then_some(p))
+            .unwrap_or_default()
+    }
+
+    fn all_outgoing(&self) -> impl Iterator<Item = &'a FourVector> + 'a {
+        self.outgoing().flat_map(|(_, p)| p)
+    }
+}
+
+impl<'a> OutgoingMomenta for StoredEvent<'a> {
+    fn outgoing_with_pid(&self, pid: ParticleID) -> &[FourVector] {
+        StoredEvent::outgoing_with_pid(self, pid)
+    }
+}
+
+impl<'a> Distance<StoredEvent<'a>> for EuclWithScaledPt {
+    fn distance(&self, ev1: &StoredEvent<'a>, ev2: &StoredEvent<'a>) -> N64 {
+        self.distance_by_pid(ev1.outgoing(), ev2.outgoing())
+    }
+
+    fn distance_within(
+        &self,
+        ev1: &StoredEvent<'a>,
+        ev2: &StoredEvent<'a>,
+        max_dist: N64,
+    ) -> Option<N64> {
+        self.distance_by_pid_within(ev1.outgoing(), ev2.outgoing(), max_dist)
+    }
+
+    fn lower_bound(&self, ev1: &StoredEvent<'a>, ev2: &StoredEvent<'a>) -> N64 {
+        self.lower_bound_by_pid(ev1.outgoing(), ev2.outgoing())
+    }
+}
+
+impl<'a> Distance<StoredEvent<'a>> for PtRapidityPhi {
+    fn distance(&self, ev1: &StoredEvent<'a>, ev2: &StoredEvent<'a>) -> N64 {
+        self.distance_by_pid(ev1.outgoing(), ev2.outgoing())
+    }
+
+    fn distance_within(
+        &self,
+        ev1: &StoredEvent<'a>,
+        ev2: &StoredEvent<'a>,
+        max_dist: N64,
+    ) -> Option<N64> {
+        self.distance_by_pid_within(ev1.outgoing(), ev2.outgoing(), max_dist)
+    }
+
+    fn lower_bound(&self, ev1: &StoredEvent<'a>, ev2: &StoredEvent<'a>) -> N64 {
+        self.lower_bound_by_pid(ev1.outgoing(), ev2.outgoing())
+    }
+}
+
+impl<'a> Distance<StoredEvent<'a>> for EnergyMoversDistance {
+    fn distance(&self, ev1: &StoredEvent<'a>, ev2: &StoredEvent<'a>) -> N64 {
+        self.emd(ev1.all_outgoing(), ev2.all_outgoing())
+    }
+
+    fn lower_bound(&self, ev1: &StoredEvent<'a>, ev2: &StoredEvent<'a>) -> N64 {
+        self.emd_lower_bound(ev1.all_outgoing(), ev2.all_outgoing())
+    }
+}
+
+/// Distance between events with momenta kept in an [EventStore]
+///
+/// The momenta are looked up via the [id](Event::id) of the events.
+#[derive(Clone, Debug)]
+pub struct StoredDistance<D> {
+    store: Arc<EventStore>,
+    distance: D,
+}
+
+impl<D> StoredDistance<D> {
+    /// Use the distance function `distance` for events in `store`
+    pub fn new(store: Arc<EventStore>, distance: D) -> Self {
+        Self { store, distance }
+    }
+}
+
+impl<D> Distance for StoredDistance<D>
+where
+    D: for<'a> Distance<StoredEvent<'a>>,
+{
+    fn distance(&self, ev1: &Event, ev2: &Event) -> N64 {
+        let ev1 = self.store.event(ev1.id());
+        let ev2 = self.store.event(ev2.id());
+        self.distance.distance(&ev1, &ev2)
+    }
+
+    fn distance_within(
+        &self,
+        ev1: &Event,
+        ev2: &Event,
+        max_dist: N64,
+    ) -> Option<N64> {
+        let ev1 = self.store.event(ev1.id());
+        let ev2 = self.store.event(ev2.id());
+        self.distance.distance_within(&ev1, &ev2, max_dist)
+    }
+
+    fn lower_bound(&self, ev1: &Event, ev2: &Event) -> N64 {
+        let ev1 = self.store.event(ev1.id());
+        let ev2 = self.store.event(ev2.id());
+        self.distance.lower_bound(&ev1, &ev2)
+    }
+}
+
+/// Conversion error for a [StoringConverter]
+#[derive(Debug, Error)]
+pub enum StoringConversionError<E> {
+    /// Error in the underlying converter
+    #[error("Failed to convert event")]
+    ConversionErr(#[source] E),
+    /// Error storing the event
+    #[error("Failed to store event")]
+    StoreErr(#[from] StoreError),
+}
+
+/// Storage for the particle momenta of converted events
+///
+/// See [StoringConverter].
+pub trait MomentumStore {
+    /// Append the momenta of an event to the store
+    fn push(&self, event: &Event) -> Result<(), StoreError>;
+
+    /// Finish adding events
+    fn finish(&self) -> Result<(), StoreError>;
+}
+
+impl MomentumStore for EventStore {
+    fn push(&self, event: &Event) -> Result<(), StoreError> {
+        EventStore::push(self, event)
+    }
+
+    fn finish(&self) -> Result<(), StoreError> {
+        EventStore::finish(self)
+    }
+}
+
+/// Converter moving particle momenta into a [MomentumStore]
+///
+/// Events are first converted with the wrapped converter. The
+/// momenta are then moved into the store and the returned events
+/// only retain their weights. By default, the store is an
+/// [EventStore].
+#[derive(Clone, Debug)]
+pub struct StoringConverter<C, S = EventStore> {
+    converter: C,
+    store: Arc<S>,
+}
+
+impl<C, S> StoringConverter<C, S> {
+    /// Wrap the given converter
+    pub fn new(converter: C, store: Arc<S>) -> Self {
+        Self { converter, store }
+    }
+
+    /// Access the event store
+    pub fn store(&self) -> &Arc<S> {
+        &self.store
+    }
+}
+
+impl<C, S, Ev> TryConvert<Ev, Event> for StoringConverter<C, S>
+where
+    C: TryConvert<Ev, Event>,
+    S: MomentumStore,
+{
+    type Error = StoringConversionError<C::Error>;
+
+    fn try_convert(&mut self, ev: Ev) -> Result<Event, Self::Error> {
+        let mut ev = self
+            .converter
+            .try_convert(ev)
+            .map_err(StoringConversionError::ConversionErr)?;
+        self.store.push(&ev)?;
+        ev.take_outgoing();
+        Ok(ev)
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        self.converter
+            .finish()
+            .map_err(StoringConversionError::ConversionErr)?;
+        self.store.finish()?;
+        Ok(())
+    }
+
+    fn keeps_momenta(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct TypeRange {
+    pid: i64,
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug)]
+struct StoreWriter {
+    momenta: BufWriter<File>,
+    types: BufWriter<File>,
+    events: BufWriter<File>,
+    weights: BufWriter<File>,
+    nmomenta: u64,
+    ntypes: u64,
+    nweights: Option<usize>,
+}
+
+impl StoreWriter {
+    fn new_in(dir: &Path) -> Result<Self, io::Error> {
+        let tmp = || tempfile::tempfile_in(dir).map(BufWriter::new);
+        Ok(Self {
+            momenta: tmp()?,
+            types: tmp()?,
+            events: tmp()?,
+            weights: tmp()?,
+            nmomenta: 0,
+            ntypes: 0,
+            nweights: None,
+        })
+    }
+
+    fn push(&mut self, event: &Event) -> Result<(), StoreError> {
+        let types_start = self.ntypes;
+        for (pid, momenta) in event.outgoing() {
+            let start = self.nmomenta;
+            for p in momenta.iter() {
+                write_f64(&mut self.momenta, p.pt())?;
+                for i in 0..4 {
+                    write_f64(&mut self.momenta, p[i])?;
+                }
+            }
+            self.nmomenta += momenta.len() as u64;
+            let pid = pid.id() as i64;
+            self.types.write_all(&pid.to_ne_bytes())?;
+            self.types.write_all(&start.to_ne_bytes())?;
+            self.types.write_all(&self.nmomenta.to_ne_bytes())?;
+            self.ntypes += 1;
+        }
+        self.events.write_all(&types_start.to_ne_bytes())?;
+        self.events.write_all(&self.ntypes.to_ne_bytes())?;
+
+        #[cfg(feature = "multiweight")]
+        let weights = event.weights.read().to_vec();
+        #[cfg(not(feature = "multiweight"))]
+        let weights = vec![*event.weights.read()];
+        let nweights = *self.nweights.get_or_insert(weights.len());
+        if weights.len() != nweights {
+            return Err(StoreError::NWeightsErr(nweights, weights.len()));
+        }
+        for wt in weights {
+            write_f64(&mut self.weights, wt)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<StoreData, io::Error> {
+        Ok(StoreData {
+            momenta: map(self.momenta)?,
+            types: map(self.types)?,
+            events: map(self.events)?,
+            weights: map(self.weights)?,
+            nweights: self.nweights.unwrap_or_default(),
+        })
+    }
+}
+
+fn write_f64(w: &mut impl Write, x: N64) -> Result<(), io::Error> {
+    w.write_all(&f64::from(x).to_ne_bytes())
+}
+
+fn map(file: BufWriter<File>) -> Result<Option<Mmap>, io::Error> {
+    let file = file.into_inner().map_err(|err| err.into_error())?;
+    if file.metadata()?.len() == 0 {
+        // mapping empty files is not portable
+        return Ok(None);
+    }
+    // SAFETY: the file is an anonymous temporary file that nobody
+    //         else can modify
+    unsafe { Mmap::map(&file) }.map(Some)
+}
+
+#[derive(Debug)]
+struct StoreData {
+    momenta: Option<Mmap>,
+    types: Option<Mmap>,
+    events: Option<Mmap>,
+    weights: Option<Mmap>,
+    nweights: usize,
+}
+
+impl StoreData {
+    fn nevents(&self) -> usize {
+        self.events().len()
+    }
+
+    fn momenta(&self) -> &[FourVector] {
+        // SAFETY: `FourVector` has C layout consisting of five `N64`,
+        //         which are transparent wrappers around `f64`. All
+        //         numbers were written from valid `N64` values.
+        unsafe { cast_slice(&self.momenta) }
+    }
+
+    fn types(&self) -> &[TypeRange] {
+        // SAFETY: `TypeRange` has C layout and all bit patterns are valid
+        unsafe { cast_slice(&self.types) }
+    }
+
+    fn events(&self) -> &[[u64; 2]] {
+        // SAFETY: all bit patterns are valid
+        unsafe { cast_slice(&self.events) }
+    }
+
+    fn weights(&self) -> &[N64] {
+        // SAFETY: all numbers were written from valid `N64` values
+        unsafe { cast_slice(&self.weights) }
+    }
+}
+
+/// Reinterpret a memory map as a slice of `T`
+///
+/// # Safety
+///
+/// The mapped bytes have to be valid values of type `T`.
+unsafe fn cast_slice<T>(map: &Option<Mmap>) -> &[T] {
+    let Some(map) = map else {
+        return &[];
+    };
+    let size = std::mem::size_of::<T>();
+    assert_eq!(map.len() % size, 0);
+    assert_eq!(map.as_ptr().align_offset(std::mem::align_of::<T>()), 0);
+    std::slice::from_raw_parts(map.as_ptr() as *const T, map.len() / size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::event::test_event;
+
+    #[test]
+    fn tst_stored_distance() {
+        let events = [
+            test_event(
+                0,
+                -1.,
+                &[(1, [10., 1., 2., 3.]), (81, [20., 5., 6., 7.])],
+            ),
+            test_event(
+                1,
+                2.,
+                &[(81, [20., 4., 6., 7.]), (81, [9., 2., 1., 0.])],
+            ),
+            test_event(2, 3., &[]),
+        ];
+        let store = EventStore::new().unwrap();
+        for ev in &events {
+            store.push(ev).unwrap();
+        }
+        store.finish().unwrap();
+        assert_eq!(store.len(), events.len());
+        #[cfg(feature = "multiweight")]
+        {
+            assert_eq!(store.weights(0), [n64(-1.), n64(-2.)]);
+            assert_eq!(store.weights(2), [n64(3.), n64(6.)]);
+        }
+        #[cfg(not(feature = "multiweight"))]
+        {
+            assert_eq!(store.weights(0), [n64(-1.)]);
+            assert_eq!(store.weights(2), [n64(3.)]);
+        }
+
+        let eucl = EuclWithScaledPt::new(n64(0.5));
+        let stored = StoredDistance::new(Arc::new(store), eucl.clone());
+        let stripped: Vec<_> = events
+            .iter()
+            .map(|ev| test_event(ev.id, ev.weight().into(), &[]))
+            .collect();
+        for (ev1, s1) in events.iter().zip(&stripped) {
+            for (ev2, s2) in events.iter().zip(&stripped) {
+                assert_eq!(eucl.distance(ev1, ev2), stored.distance(s1, s2));
+            }
+        }
+    }
+}
@@ -5,6 +5,8 @@ use noisy_float::prelude::*;
 /// The zero component is the energy/time component. The remainder are
 /// the spatial components
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
+// fixed layout for the out-of-core event store
+#[repr(C)]
 pub struct FourVector {
     pt: N64,
     p: [N64; 4],
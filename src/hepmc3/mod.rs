@@ -0,0 +1,35 @@
+/// Reader for input in HepMC 3 ASCII format
+pub mod reader;
+/// Writer to HepMC 3 ASCII format
+pub mod writer;
+
+/// Read events from one or more inputs in HepMC 3 ASCII format
+pub use reader::FileReader;
+pub use writer::Writer;
+
+const HEPMC_OUTGOING: i32 = 1;
+const HEPMC_DECAYED: i32 = 2;
+const HEPMC_DOC: i32 = 3;
+const HEPMC_INCOMING: i32 = 4;
+
+fn status_from_i32(status: i32) -> avery::event::Status {
+    use avery::event::Status::*;
+    match status {
+        HEPMC_INCOMING => Incoming,
+        HEPMC_OUTGOING => Outgoing,
+        HEPMC_DECAYED => IntermediateResonance,
+        HEPMC_DOC => IntermediateDoc,
+        s => Unknown(s),
+    }
+}
+
+fn status_to_i32(status: avery::event::Status) -> i32 {
+    use avery::event::Status::*;
+    match status {
+        Incoming | IncomingBeam => HEPMC_INCOMING,
+        IntermediateResonance | IntermediateSpacelike => HEPMC_DECAYED,
+        IntermediateDoc => HEPMC_DOC,
+        Outgoing => HEPMC_OUTGOING,
+        Unknown(s) => s,
+    }
+}
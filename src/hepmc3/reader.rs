@@ -0,0 +1,649 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Seek};
+use std::num::{ParseFloatError, ParseIntError};
+use std::str::{FromStr, SplitAsciiWhitespace};
+
+use audec::auto_decompress;
+use avery::event::{
+    CrossSection, Particle, SampleInfo, Scales, Vertex, WeightInfo,
+};
+use particle_id::ParticleID;
+use petgraph::{graph::NodeIndex, prelude::DiGraph};
+use thiserror::Error;
+
+use super::status_from_i32;
+use crate::{
+    file::File,
+    reader::{EventReadError, RewindError},
+    traits::{Rewind, TryClone},
+};
+
+const BUF_SIZE: usize = 256;
+
+/// Reader for a single (potentially compressed) HepMC3 event file
+pub struct FileReader {
+    reader: Reader<Box<dyn BufRead>>,
+    source: File,
+}
+
+impl FileReader {
+    /// Construct a reader for the given (potentially compressed) HepMC3 event file
+    pub fn new(source: File) -> Result<Self, std::io::Error> {
+        let cloned_source = source.try_clone()?;
+        Ok(FileReader {
+            source,
+            reader: Reader::new(auto_decompress(BufReader::new(cloned_source))),
+        })
+    }
+}
+
+impl Rewind for FileReader {
+    type Error = RewindError;
+
+    fn rewind(&mut self) -> Result<(), Self::Error> {
+        use RewindError::*;
+        self.source.rewind()?;
+        let cloned_source = self.source.try_clone().map_err(CloneError)?;
+        self.reader =
+            Reader::new(auto_decompress(BufReader::new(cloned_source)));
+
+        Ok(())
+    }
+}
+
+impl Iterator for FileReader {
+    type Item = Result<avery::Event, EventReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.next().map(|ev| ev.map_err(|err| err.into()))
+    }
+}
+
+/// Reader for the HepMC3 ASCII format
+///
+/// Run information (weight names, tools, and run attributes) is
+/// stored in the [SampleInfo] of each event.
+#[derive(Debug)]
+pub struct Reader<T> {
+    stream: T,
+    line: String,
+    line_nr: usize,
+    weight_names: Vec<String>,
+    sample_info: SampleInfo,
+}
+
+impl<T: BufRead> Reader<T> {
+    /// Construct a new reader
+    pub fn new(stream: T) -> Self {
+        Self {
+            stream,
+            line: String::with_capacity(BUF_SIZE),
+            line_nr: 0,
+            weight_names: Vec::new(),
+            sample_info: SampleInfo::default(),
+        }
+    }
+
+    fn read_line(&mut self) -> Result<bool, io::Error> {
+        self.line.clear();
+        if self.stream.read_line(&mut self.line)? == 0 {
+            return Ok(false);
+        }
+        self.line_nr += 1;
+        Ok(true)
+    }
+
+    fn is_listing_marker(&self) -> bool {
+        self.line.starts_with("HepMC::")
+    }
+
+    fn parse_run_info_line(&mut self) -> Result<(), ParseError> {
+        let mut fields = Fields::new(&self.line[1..]);
+        match self.line.as_bytes()[0] {
+            b'W' => {
+                self.weight_names = fields.rest().map(String::from).collect()
+            }
+            b'T' => {
+                let tool = fields.remainder();
+                let mut tool = tool.split("\\|").map(|s| s.trim());
+                let name = tool.next().unwrap_or_default();
+                let version = tool.next().unwrap_or_default();
+                let generator = format!("{name} {version}");
+                self.sample_info
+                    .generators
+                    .push(generator.trim().to_owned());
+            }
+            b'A' => {
+                let name = fields.next_str()?;
+                let value = fields.remainder();
+                self.sample_info
+                    .attr
+                    .insert(name.to_owned(), value.to_owned());
+            }
+            _ => return Err(ParseError::BadPrefix),
+        }
+        Ok(())
+    }
+
+    fn parse_event(&mut self) -> Result<Option<avery::Event>, ParseError> {
+        // skip to the next event, collecting run information
+        loop {
+            match self.line.as_bytes().first() {
+                Some(b'E') => break,
+                Some(b'W' | b'T' | b'A') => self.parse_run_info_line()?,
+                _ if self.is_listing_marker() => {}
+                _ if self.line.trim().is_empty() => {}
+                _ => return Err(ParseError::BadPrefix),
+            }
+            if !self.read_line()? {
+                return Ok(None);
+            }
+        }
+
+        let mut event = RawEvent::parse(&self.line)?;
+        while self.read_line()? {
+            let line = &self.line;
+            match line.as_bytes().first() {
+                Some(b'E') => break,
+                Some(b'P') => event.parse_particle_line(line)?,
+                Some(b'V') => event.parse_vertex_line(line)?,
+                Some(b'A') => event.parse_attribute_line(line)?,
+                Some(b'W') => event.parse_weight_line(line)?,
+                Some(b'U') => event.parse_units_line(line)?,
+                _ if self.is_listing_marker() => break,
+                _ if line.trim().is_empty() => {}
+                _ => return Err(ParseError::BadPrefix),
+            }
+        }
+        event
+            .into_event(&self.weight_names, self.sample_info.clone())
+            .map(Some)
+    }
+}
+
+impl<T: BufRead> Iterator for Reader<T> {
+    type Item = Result<avery::Event, LineParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.parse_event()
+            .map_err(|err| LineParseError {
+                err,
+                line: self.line.clone(),
+                line_nr: self.line_nr,
+            })
+            .transpose()
+    }
+}
+
+/// Error parsing a line in a HepMC3 file
+#[derive(Debug, Error)]
+pub enum ParseError {
+    /// I/O error
+    #[error("I/O error")]
+    IoErr(#[from] io::Error),
+    /// Line starts with an unknown prefix
+    #[error("Unrecognised line prefix")]
+    BadPrefix,
+    /// Required entry is missing
+    #[error("Line ended prematurely")]
+    MissingEntry,
+    /// Error parsing an integer
+    #[error("Failed to parse integer")]
+    ParseIntErr(#[from] ParseIntError),
+    /// Error parsing a floating-point number
+    #[error("Failed to parse floating-point number")]
+    ParseFloatErr(#[from] ParseFloatError),
+    /// Unknown unit
+    #[error("Unknown unit `{0}`")]
+    UnitErr(String),
+    /// Reference to a particle that does not exist
+    #[error("Unknown particle {0}")]
+    ParticleErr(i32),
+    /// Reference to a vertex that does not exist
+    #[error("Unknown vertex {0}")]
+    VertexErr(i32),
+}
+
+/// Error parsing a line in a HepMC3 file, with the location
+#[derive(Debug, Error)]
+#[error("Failed to parse line {line_nr}: `{}`", line.trim_end())]
+pub struct LineParseError {
+    /// The underlying error
+    #[source]
+    pub err: ParseError,
+    /// The offending line
+    pub line: String,
+    /// The line number
+    pub line_nr: usize,
+}
+
+// whitespace-separated entries in a line
+struct Fields<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    fn new(line: &'a str) -> Self {
+        Self { rest: line }
+    }
+
+    fn next_str(&mut self) -> Result<&'a str, ParseError> {
+        let rest = self.rest.trim_start();
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return Err(ParseError::MissingEntry);
+        }
+        let (entry, rest) = rest.split_at(end);
+        self.rest = rest;
+        Ok(entry)
+    }
+
+    fn next<U>(&mut self) -> Result<U, ParseError>
+    where
+        U: FromStr,
+        ParseError: From<U::Err>,
+    {
+        Ok(self.next_str()?.parse()?)
+    }
+
+    fn rest(self) -> SplitAsciiWhitespace<'a> {
+        self.rest.split_ascii_whitespace()
+    }
+
+    // everything after the entries read so far
+    fn remainder(self) -> &'a str {
+        self.rest.trim()
+    }
+}
+
+#[derive(Debug, Default)]
+struct RawParticle {
+    id: i32,
+    production: i32,
+    pid: i32,
+    p: [f64; 4],
+    m: f64,
+    status: i32,
+}
+
+#[derive(Debug, Default)]
+struct RawVertex {
+    id: i32,
+    status: i32,
+    incoming: Vec<i32>,
+    position: [f64; 4],
+}
+
+#[derive(Debug)]
+struct RawEvent {
+    number: i32,
+    energy_factor: f64,
+    length_factor: f64,
+    weights: Vec<f64>,
+    attributes: Vec<(i32, String, String)>,
+    particles: Vec<RawParticle>,
+    vertices: Vec<RawVertex>,
+}
+
+impl RawEvent {
+    fn parse(line: &str) -> Result<Self, ParseError> {
+        let mut fields = Fields::new(&line[1..]);
+        let number = fields.next()?;
+        let nvertices = fields.next()?;
+        let nparticles = fields.next()?;
+        Ok(Self {
+            number,
+            energy_factor: 1.,
+            length_factor: 0.1,
+            weights: Vec::new(),
+            attributes: Vec::new(),
+            particles: Vec::with_capacity(nparticles),
+            vertices: Vec::with_capacity(nvertices),
+        })
+    }
+
+    fn parse_units_line(&mut self, line: &str) -> Result<(), ParseError> {
+        let mut fields = Fields::new(&line[1..]);
+        self.energy_factor = match fields.next_str()? {
+            "GEV" => 1.,
+            "MEV" => 1e-3,
+            unit => return Err(ParseError::UnitErr(unit.to_owned())),
+        };
+        // internally, lengths are in cm
+        self.length_factor = match fields.next_str()? {
+            "CM" => 1.,
+            "MM" => 0.1,
+            unit => return Err(ParseError::UnitErr(unit.to_owned())),
+        };
+        Ok(())
+    }
+
+    fn parse_weight_line(&mut self, line: &str) -> Result<(), ParseError> {
+        let weights: Result<Vec<_>, _> = line[1..]
+            .split_ascii_whitespace()
+            .map(f64::from_str)
+            .collect();
+        self.weights = weights?;
+        Ok(())
+    }
+
+    fn parse_attribute_line(&mut self, line: &str) -> Result<(), ParseError> {
+        let mut fields = Fields::new(&line[1..]);
+        let id = fields.next()?;
+        let name = fields.next_str()?.to_owned();
+        let value = fields.remainder().to_owned();
+        self.attributes.push((id, name, value));
+        Ok(())
+    }
+
+    fn parse_particle_line(&mut self, line: &str) -> Result<(), ParseError> {
+        let mut fields = Fields::new(&line[1..]);
+        let id = fields.next()?;
+        let production = fields.next()?;
+        let pid = fields.next()?;
+        let px = fields.next()?;
+        let py = fields.next()?;
+        let pz = fields.next()?;
+        let e = fields.next()?;
+        let m = fields.next()?;
+        let status = fields.next()?;
+        self.particles.push(RawParticle {
+            id,
+            production,
+            pid,
+            p: [e, px, py, pz],
+            m,
+            status,
+        });
+        Ok(())
+    }
+
+    fn parse_vertex_line(&mut self, line: &str) -> Result<(), ParseError> {
+        let mut fields = Fields::new(&line[1..]);
+        let id = fields.next()?;
+        let status = fields.next()?;
+        let rest = fields.remainder();
+        let (incoming, position) = match rest.split_once('@') {
+            Some((incoming, position)) => (incoming, Some(position)),
+            None => (rest, None),
+        };
+        let incoming: Result<Vec<_>, _> = incoming
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .map(i32::from_str)
+            .collect();
+        let mut vertex = RawVertex {
+            id,
+            status,
+            incoming: incoming?,
+            position: [0.; 4],
+        };
+        if let Some(position) = position {
+            let mut position = Fields::new(position);
+            for x in &mut vertex.position {
+                *x = position.next()?;
+            }
+        }
+        self.vertices.push(vertex);
+        Ok(())
+    }
+
+    fn into_event(
+        self,
+        weight_names: &[String],
+        mut sample_info: SampleInfo,
+    ) -> Result<avery::Event, ParseError> {
+        let efact = self.energy_factor;
+        let lfact = self.length_factor;
+
+        let mut topology = DiGraph::new();
+        let mut vertex_idx = HashMap::with_capacity(self.vertices.len());
+        for vx in &self.vertices {
+            let [x, y, z, t] = vx.position;
+            let node = topology.add_node(Vertex {
+                status: Some(vx.status),
+                x: Some(lfact * x),
+                y: Some(lfact * y),
+                z: Some(lfact * z),
+                t: Some(t),
+                weights: Vec::new(),
+            });
+            vertex_idx.insert(vx.id, node);
+        }
+        let particle_idx: HashMap<_, _> = self
+            .particles
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| (p.id, idx))
+            .collect();
+        let find_particle = |id| {
+            particle_idx
+                .get(&id)
+                .copied()
+                .ok_or(ParseError::ParticleErr(id))
+        };
+
+        let mut end_vertex: Vec<Option<NodeIndex>> =
+            vec![None; self.particles.len()];
+        for vx in &self.vertices {
+            for &id in &vx.incoming {
+                end_vertex[find_particle(id)?] = Some(vertex_idx[&vx.id]);
+            }
+        }
+        // A positive production vertex id refers to the single
+        // parent particle. In this case, the vertex is implicit.
+        let mut start_vertex = Vec::with_capacity(self.particles.len());
+        for p in &self.particles {
+            let start = match p.production {
+                0 => topology.add_node(Vertex::default()),
+                id if id < 0 => {
+                    *vertex_idx.get(&id).ok_or(ParseError::VertexErr(id))?
+                }
+                id => {
+                    let parent = find_particle(id)?;
+                    *end_vertex[parent].get_or_insert_with(|| {
+                        topology.add_node(Vertex::default())
+                    })
+                }
+            };
+            start_vertex.push(start);
+        }
+        for (idx, (start, end)) in
+            start_vertex.into_iter().zip(end_vertex).enumerate()
+        {
+            let end =
+                end.unwrap_or_else(|| topology.add_node(Vertex::default()));
+            topology.add_edge(start, end, idx);
+        }
+
+        let mut particles: Vec<_> = self
+            .particles
+            .into_iter()
+            .map(|p| Particle {
+                id: Some(ParticleID::new(p.pid)),
+                p: Some(p.p.map(|p| efact * p)),
+                m: Some(efact * p.m),
+                status: Some(status_from_i32(p.status)),
+                ..Default::default()
+            })
+            .collect();
+
+        let weights = self
+            .weights
+            .into_iter()
+            .enumerate()
+            .map(|(n, weight)| WeightInfo {
+                weight: Some(weight),
+                name: weight_names.get(n).cloned(),
+                ..Default::default()
+            })
+            .collect();
+
+        let mut event = avery::Event {
+            id: Some(self.number),
+            weights,
+            ..Default::default()
+        };
+        let mut scales = Scales::default();
+        for (id, name, value) in self.attributes {
+            if id > 0 {
+                let particle = &mut particles[find_particle(id)?];
+                parse_particle_attribute(particle, &name, &value)?;
+                continue;
+            } else if id < 0 {
+                // vertex attributes are not supported
+                continue;
+            }
+            let mut fields = Fields::new(&value);
+            match name.as_str() {
+                "GenCrossSection" => {
+                    let mean = fields.next()?;
+                    let err = fields.next()?;
+                    sample_info.cross_sections = vec![CrossSection {
+                        mean,
+                        err: Some(err),
+                    }];
+                }
+                "GenPdfInfo" => {
+                    // parton ids and momentum fractions are
+                    // reconstructed from the incoming particles
+                    for _ in 0..4 {
+                        fields.next_str()?;
+                    }
+                    scales.mu_f = Some(efact * fields.next::<f64>()?);
+                    for _ in 0..2 {
+                        fields.next_str()?;
+                    }
+                    for pdf in &mut sample_info.pdf {
+                        *pdf = Some(fields.next()?);
+                    }
+                }
+                "alphaQCD" => event.alpha_s = Some(fields.next()?),
+                "alphaQED" => event.alpha = Some(fields.next()?),
+                "event_scale" => {
+                    scales.mu_r = Some(efact * fields.next::<f64>()?)
+                }
+                "signal_process_id" => event.process_id = Some(fields.next()?),
+                "mpi" => event.mpi = Some(fields.next()?),
+                "random_states" => {
+                    let states: Result<_, _> =
+                        fields.rest().map(i32::from_str).collect();
+                    event.random_states = states?;
+                }
+                _ => {
+                    event.attr.insert(name, value);
+                }
+            }
+        }
+        event.sample_info = sample_info;
+        event.scales = scales;
+        event.particles = particles;
+        event.topology = topology;
+        Ok(event)
+    }
+}
+
+fn parse_particle_attribute(
+    particle: &mut Particle,
+    name: &str,
+    value: &str,
+) -> Result<(), ParseError> {
+    let mut fields = Fields::new(value);
+    match name {
+        "theta" => particle.theta = Some(fields.next()?),
+        "phi" => particle.phi = Some(fields.next()?),
+        name => {
+            if let Some(idx) = name.strip_prefix("flow") {
+                if let Ok(idx) = idx.parse() {
+                    particle.flows.insert(idx, fields.next()?);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use avery::event::Status;
+
+    use crate::hepmc3::Writer;
+    use crate::traits::WriteEvent;
+
+    const EVENT: &str = r"HepMC::Version 3.02.05
+HepMC::Asciiv3-START_EVENT_LISTING
+W Weight1 Weight2
+T Pythia8\|8.310\|Pythia8 event generator
+A NumberOfEvents 1
+E 7 2 6
+U MEV MM
+W 1.5e0 -2.5e0
+A 0 GenCrossSection 1.2e3 1.0e1 -1 -1
+A 0 alphaQCD 1.2e-1
+A 3 flow1 501
+P 1 0 2212 0.0 0.0 6.5e6 6.5e6 9.3827e2 4
+P 2 0 2212 0.0 0.0 -6.5e6 6.5e6 9.3827e2 4
+V -1 0 [1,2] @ 1.0 2.0 3.0 4.0
+P 3 -1 21 1.0e4 0.0 1.0e4 2.0e4 0.0 2
+P 4 -1 11 -1.0e4 0.0 -1.0e4 2.0e4 0.0 1
+P 5 3 21 5.0e3 0.0 5.0e3 1.0e4 0.0 1
+P 6 3 21 5.0e3 0.0 5.0e3 1.0e4 0.0 1
+HepMC::Asciiv3-END_EVENT_LISTING
+";
+
+    fn check(event: &avery::Event) {
+        assert_eq!(event.id, Some(7));
+        assert_eq!(event.weights.len(), 2);
+        assert_eq!(event.weights[1].weight, Some(-2.5));
+        assert_eq!(event.weights[1].name.as_deref(), Some("Weight2"));
+        assert_eq!(event.sample_info.cross_sections[0].mean, 1.2e3);
+        assert!(event.sample_info.generators[0].starts_with("Pythia8 8.310"));
+        assert_eq!(event.alpha_s, Some(0.12));
+        assert_eq!(event.particles.len(), 6);
+        assert_eq!(event.topology.edge_count(), 6);
+        let mut outgoing: Vec<_> = event
+            .particles
+            .iter()
+            .filter(|p| p.status == Some(Status::Outgoing))
+            .map(|p| p.p.unwrap())
+            .collect();
+        outgoing.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            outgoing,
+            [[10., 5., 0., 5.], [10., 5., 0., 5.], [20., -10., 0., -10.]]
+        );
+        let gluon = event
+            .particles
+            .iter()
+            .find(|p| p.status == Some(Status::IntermediateResonance))
+            .unwrap();
+        assert_eq!(gluon.flows.get(&1), Some(&501));
+    }
+
+    #[test]
+    fn tst_read_write() {
+        let mut reader = Reader::new(EVENT.as_bytes());
+        let event = reader.next().unwrap().unwrap();
+        assert!(reader.next().is_none());
+        check(&event);
+        assert_eq!(event.topology.node_count(), 7);
+
+        let mut out = Vec::new();
+        let mut writer = Writer::new(&mut out).unwrap();
+        writer.write(event).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = Reader::new(out.as_slice());
+        let event = reader.next().unwrap().unwrap();
+        assert!(reader.next().is_none());
+        check(&event);
+    }
+}
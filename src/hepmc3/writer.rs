@@ -0,0 +1,307 @@
+use std::fmt::Write as _;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use avery::event::Status;
+use petgraph::{
+    algo::toposort,
+    Direction::{Incoming, Outgoing},
+};
+
+use super::{status_to_i32, HEPMC_INCOMING};
+use crate::{
+    compression::{compress_writer, Compression},
+    file::File,
+    traits::WriteEvent,
+    GIT_BRANCH, GIT_REV, VERSION,
+};
+
+const HEADER: &str = "HepMC::Version 3.02.06
+HepMC::Asciiv3-START_EVENT_LISTING
+";
+
+const FOOTER: &str = "HepMC::Asciiv3-END_EVENT_LISTING\n\n";
+
+/// Write events in HepMC 3 ASCII format
+///
+/// Run information is taken from the first event.
+#[derive(Debug)]
+pub struct Writer<T: Write> {
+    stream: T,
+    nevents: usize,
+}
+
+impl Writer<Box<dyn Write>> {
+    /// Try to construct a new writer to the file with the given path
+    pub fn try_new(
+        filename: &Path,
+        compression: Option<Compression>,
+    ) -> Result<Self, std::io::Error> {
+        let outfile = File::create(filename)?;
+        let out = BufWriter::new(outfile);
+        let out = compress_writer(out, compression)?;
+        Self::new(out)
+    }
+}
+
+impl<T: Write> Writer<T> {
+    /// Construct a new writer, writing the HepMC3 header
+    pub fn new(mut stream: T) -> Result<Self, std::io::Error> {
+        stream.write_all(HEADER.as_bytes())?;
+        Ok(Self { stream, nevents: 0 })
+    }
+
+    fn write_run_info(&mut self, e: &avery::Event) -> std::io::Result<()> {
+        let mut run_info = String::new();
+        if e.weights.iter().any(|wt| wt.name.is_some()) {
+            run_info.push('W');
+            for (n, wt) in e.weights.iter().enumerate() {
+                match &wt.name {
+                    Some(name) => write!(run_info, " {name}"),
+                    None => write!(run_info, " {n}"),
+                }
+                .unwrap();
+            }
+            run_info.push('\n');
+        }
+        for generator in &e.sample_info.generators {
+            let (name, version) =
+                generator.split_once(' ').unwrap_or((generator, ""));
+            writeln!(run_info, "T {name}\\|{version}\\|").unwrap();
+        }
+        let description = match (GIT_REV, GIT_BRANCH) {
+            (Some(rev), Some(branch)) => format!("rev {rev} ({branch})"),
+            _ => String::new(),
+        };
+        writeln!(run_info, "T cres\\|{VERSION}\\|{description}").unwrap();
+        for (name, value) in &e.sample_info.attr {
+            writeln!(run_info, "A {name} {value}").unwrap();
+        }
+        self.stream.write_all(run_info.as_bytes())
+    }
+
+    fn write_event(&mut self, e: avery::Event) -> std::io::Result<()> {
+        let mut attributes = String::new();
+        let mut body = String::new();
+
+        let xs: f64 =
+            e.sample_info.cross_sections.iter().map(|xs| xs.mean).sum();
+        if !e.sample_info.cross_sections.is_empty() {
+            let err = e
+                .sample_info
+                .cross_sections
+                .iter()
+                .map(|xs| xs.err.unwrap_or_default().powi(2))
+                .sum::<f64>()
+                .sqrt();
+            writeln!(attributes, "A 0 GenCrossSection {xs:e} {err:e} -1 -1")
+                .unwrap();
+        }
+        if let [Some(pdf1), Some(pdf2)] = e.sample_info.pdf {
+            let (ids, x) = incoming_partons(&e);
+            let scale = e.scales.mu_f.unwrap_or_default();
+            writeln!(
+                attributes,
+                "A 0 GenPdfInfo {} {} {:e} {:e} {scale:e} 0 0 {pdf1} {pdf2}",
+                ids[0], ids[1], x[0], x[1]
+            )
+            .unwrap();
+        }
+        if let Some(alpha_s) = e.alpha_s {
+            writeln!(attributes, "A 0 alphaQCD {alpha_s:e}").unwrap();
+        }
+        if let Some(alpha) = e.alpha {
+            writeln!(attributes, "A 0 alphaQED {alpha:e}").unwrap();
+        }
+        if let Some(mu_r) = e.scales.mu_r {
+            writeln!(attributes, "A 0 event_scale {mu_r:e}").unwrap();
+        }
+        if let Some(id) = e.process_id {
+            writeln!(attributes, "A 0 signal_process_id {id}").unwrap();
+        }
+        if let Some(mpi) = e.mpi {
+            writeln!(attributes, "A 0 mpi {mpi}").unwrap();
+        }
+        if !e.random_states.is_empty() {
+            attributes.push_str("A 0 random_states");
+            for state in &e.random_states {
+                write!(attributes, " {state}").unwrap();
+            }
+            attributes.push('\n');
+        }
+        for (name, value) in &e.attr {
+            writeln!(attributes, "A 0 {name} {value}").unwrap();
+        }
+
+        // HepMC3 expects particles to be numbered consecutively and
+        // the incoming particles of each vertex to be listed before
+        // the vertex itself
+        let mut ids = vec![0; e.particles.len()];
+        let mut nparticles = 0;
+        let mut nvertices = 0;
+        let g = &e.topology;
+        if g.node_count() == 0 {
+            // no topology information, put everything into one vertex
+            let incoming = |p: &avery::event::Particle| {
+                p.status.map(status_to_i32) == Some(HEPMC_INCOMING)
+            };
+            let mut vertex = String::from("V -1 0 [");
+            let particles = e.particles.iter().zip(ids.iter_mut());
+            for (p, id) in particles.filter(|(p, _)| incoming(p)) {
+                nparticles += 1;
+                *id = nparticles;
+                write_particle(&mut body, nparticles, 0, p);
+                if nparticles > 1 {
+                    vertex.push(',');
+                }
+                write!(vertex, "{nparticles}").unwrap();
+            }
+            vertex.push_str("]\n");
+            body.push_str(&vertex);
+            nvertices = 1;
+            let particles = e.particles.iter().zip(ids.iter_mut());
+            for (p, id) in particles.filter(|(p, _)| !incoming(p)) {
+                nparticles += 1;
+                *id = nparticles;
+                write_particle(&mut body, nparticles, -1, p);
+            }
+        } else {
+            let nodes = toposort(g, None).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Event topology is not acyclic",
+                )
+            })?;
+            for node in nodes {
+                let mut outgoing: Vec<_> = g
+                    .edges_directed(node, Outgoing)
+                    .map(|e| *e.weight())
+                    .collect();
+                if outgoing.is_empty() {
+                    continue;
+                }
+                // edges are iterated in reverse order of insertion
+                outgoing.reverse();
+                let mut incoming: Vec<_> = g
+                    .edges_directed(node, Incoming)
+                    .map(|e| ids[*e.weight()])
+                    .collect();
+                let production = if incoming.is_empty() {
+                    0
+                } else {
+                    nvertices += 1;
+                    let vx = &g[node];
+                    incoming.reverse();
+                    write!(
+                        body,
+                        "V -{nvertices} {} [",
+                        vx.status.unwrap_or_default()
+                    )
+                    .unwrap();
+                    for (n, id) in incoming.iter().enumerate() {
+                        if n > 0 {
+                            body.push(',');
+                        }
+                        write!(body, "{id}").unwrap();
+                    }
+                    body.push(']');
+                    let position =
+                        [vx.x, vx.y, vx.z, vx.t].map(|x| x.unwrap_or_default());
+                    if position.iter().any(|&x| x != 0.) {
+                        let [x, y, z, t] = position;
+                        write!(body, " @ {x:e} {y:e} {z:e} {t:e}").unwrap();
+                    }
+                    body.push('\n');
+                    -nvertices
+                };
+                for idx in outgoing {
+                    nparticles += 1;
+                    ids[idx] = nparticles;
+                    let p = &e.particles[idx];
+                    write_particle(&mut body, nparticles, production, p);
+                }
+            }
+        }
+
+        for (idx, p) in e.particles.iter().enumerate() {
+            let id = ids[idx];
+            if let Some(theta) = p.theta {
+                writeln!(attributes, "A {id} theta {theta:e}").unwrap();
+            }
+            if let Some(phi) = p.phi {
+                writeln!(attributes, "A {id} phi {phi:e}").unwrap();
+            }
+            for (n, flow) in &p.flows {
+                writeln!(attributes, "A {id} flow{n} {flow}").unwrap();
+            }
+        }
+
+        let mut header = format!(
+            "E {} {nvertices} {nparticles}\nU GEV CM\n",
+            e.id.unwrap_or_default()
+        );
+        if !e.weights.is_empty() {
+            header.push('W');
+            for wt in &e.weights {
+                write!(header, " {:e}", wt.weight.unwrap_or_default()).unwrap();
+            }
+            header.push('\n');
+        }
+        self.stream.write_all(header.as_bytes())?;
+        self.stream.write_all(attributes.as_bytes())?;
+        self.stream.write_all(body.as_bytes())
+    }
+}
+
+fn write_particle(
+    out: &mut String,
+    id: i32,
+    production: i32,
+    p: &avery::event::Particle,
+) {
+    let [e, px, py, pz] = p.p.unwrap_or_default();
+    writeln!(
+        out,
+        "P {id} {production} {} {px:e} {py:e} {pz:e} {e:e} {:e} {}",
+        p.id.map(|id| id.id()).unwrap_or_default(),
+        p.m.unwrap_or_default(),
+        p.status.map(status_to_i32).unwrap_or_default(),
+    )
+    .unwrap();
+}
+
+// ids and momentum fractions of the incoming partons
+fn incoming_partons(e: &avery::Event) -> ([i32; 2], [f64; 2]) {
+    let mut ids = [0; 2];
+    let mut x = [0.; 2];
+    let incoming = e
+        .particles
+        .iter()
+        .filter(|p| p.status == Some(Status::Incoming));
+    for particle in incoming {
+        let Some(p) = particle.p else { continue };
+        let idx = if p[3] < 0. { 0 } else { 1 };
+        ids[idx] = particle.id.map(|id| id.id()).unwrap_or_default();
+        if let Some(energy) = e.sample_info.beam[idx].energy {
+            x[idx] = p[0] / energy;
+        }
+    }
+    (ids, x)
+}
+
+impl<T: Write> WriteEvent<avery::Event> for Writer<T> {
+    type Error = std::io::Error;
+
+    fn write(&mut self, e: avery::Event) -> Result<(), Self::Error> {
+        if self.nevents == 0 {
+            self.write_run_info(&e)?;
+        }
+        self.nevents += 1;
+        self.write_event(e)
+    }
+
+    fn finish(mut self) -> Result<(), Self::Error> {
+        self.stream.write_all(FOOTER.as_bytes())?;
+        self.stream.flush()
+    }
+}
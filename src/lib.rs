@@ -32,6 +32,8 @@ pub mod bisect;
 pub mod c_api;
 /// Definition of event cells
 pub mod cell;
+/// Saving and restoring the resampling progress
+pub mod checkpoint;
 /// Callbacks used upon cell construction and when writing out events
 pub mod cell_collector;
 /// Jet clustering helpers
@@ -45,17 +47,24 @@ pub mod cres;
 pub mod distance;
 /// Scattering event class
 pub mod event;
+pub mod event_cache;
+pub mod event_store;
 /// Thin wrapper around [std::fs::File]
 pub mod file;
+pub mod flat_store;
 /// Four-vector class
 pub mod four_vector;
 /// HepMC2 interface
 pub mod hepmc2;
+/// HepMC3 interface
+pub mod hepmc3;
 /// LesHouches Event File interface
 #[cfg(feature = "lhef")]
 pub mod lhef;
+pub mod metric_check;
 /// Nearest neighbour search algorithms
 pub mod neighbour_search;
+pub mod observable;
 /// ntuple interface
 #[cfg(feature = "ntuple")]
 pub mod ntuple;
@@ -65,10 +74,13 @@ pub mod prelude;
 pub mod progress_bar;
 /// Event readers
 pub mod reader;
+/// Summary of a resampling run
+pub mod report;
 /// Cell resampling
 pub mod resampler;
 /// Cell seed selection
 pub mod seeds;
+pub mod sidecar;
 /// STRIPPER XML interface
 #[cfg(feature = "stripper-xml")]
 pub mod stripper_xml;
@@ -76,9 +88,13 @@ pub mod stripper_xml;
 pub mod traits;
 /// Unweighting
 pub mod unweight;
+pub mod validation;
 /// Event writer
 pub mod writer;
 
+mod assignment;
+mod ee_cluster;
+mod transport;
 mod util;
 mod vptree;
 
@@ -114,6 +130,8 @@ pub const FEATURES: [&str; NFEATURES] = [
     "stripper-xml",
     #[cfg(feature = "capi")]
     "capi",
+    #[cfg(feature = "distance-plugin")]
+    "distance-plugin",
 ];
 
 const NFEATURES: usize = {
@@ -139,5 +157,9 @@ const NFEATURES: usize = {
     {
         nfeatures += 1;
     }
+    #[cfg(feature = "distance-plugin")]
+    {
+        nfeatures += 1;
+    }
     nfeatures
 };
@@ -0,0 +1,310 @@
+//! Checks whether a distance function is a metric
+//!
+//! The tree-based nearest neighbour search relies on the distance
+//! being a metric, i.e. on
+//!
+//! - identity: `d(a, a) = 0`,
+//! - non-negativity: `d(a, b) ≥ 0`,
+//! - symmetry: `d(a, b) = d(b, a)`,
+//! - the triangle inequality: `d(a, c) ≤ d(a, b) + d(b, c)`.
+//!
+//! Custom distances can violate these properties and then lead to
+//! cells that do not contain the actual nearest neighbours. A
+//! [MetricCheck] tests the properties for randomly chosen triples of
+//! events. Since only a sample of triples is tested, passing the check
+//! does not guarantee that the distance is a metric.
+use std::fmt;
+
+use log::{debug, warn};
+use noisy_float::prelude::*;
+use rand::{seq::index::sample, SeedableRng};
+use rand_xoshiro::Xoshiro256Plus;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{event::Event, traits::Distance};
+
+/// Maximum number of violations logged as warnings
+const MAX_WARNINGS: usize = 10;
+
+/// Check of the metric properties of a distance function
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MetricCheck {
+    /// Number of randomly chosen event triples
+    pub samples: usize,
+    /// Seed for choosing the triples
+    pub seed: u64,
+    /// Relative tolerance for symmetry and the triangle inequality
+    pub tolerance: f64,
+    /// Whether to fall back to a naive nearest neighbour search
+    ///
+    /// If set, the [DefaultResampler](crate::resampler::DefaultResampler)
+    /// uses a [NaiveNeighbourSearch](crate::neighbour_search::NaiveNeighbourSearch)
+    /// instead of the configured search whenever a violation is found.
+    pub naive_search_fallback: bool,
+}
+
+impl Default for MetricCheck {
+    fn default() -> Self {
+        Self {
+            samples: 1000,
+            seed: 0,
+            tolerance: 1e-10,
+            naive_search_fallback: false,
+        }
+    }
+}
+
+impl MetricCheck {
+    /// Test the metric properties of `distance` for random `events`
+    ///
+    /// At least three events are required to test the triangle
+    /// inequality. With fewer events, only the remaining properties
+    /// are tested.
+    pub fn check<D: Distance + Sync>(
+        &self,
+        distance: &D,
+        events: &[Event],
+    ) -> MetricReport {
+        debug!("Checking metric properties for {} samples", self.samples);
+        let size = std::cmp::min(events.len(), 3);
+        if size == 0 {
+            return MetricReport::default();
+        }
+        let mut rng = Xoshiro256Plus::seed_from_u64(self.seed);
+        let samples: Vec<_> = (0..self.samples)
+            .map(|_| sample(&mut rng, events.len(), size).into_vec())
+            .collect();
+        let violations = samples
+            .par_iter()
+            .flat_map_iter(|idx| {
+                let evs: Vec<_> = idx.iter().map(|&i| &events[i]).collect();
+                self.check_sample(distance, &evs)
+            })
+            .collect();
+        MetricReport {
+            samples: self.samples,
+            violations,
+        }
+    }
+
+    fn check_sample<D: Distance>(
+        &self,
+        distance: &D,
+        events: &[&Event],
+    ) -> Vec<Violation> {
+        use ViolationKind::*;
+
+        let d = |a: &Event, b: &Event| distance.distance(a, b);
+        let violation = |kind, evs: &[&Event], distances: Vec<N64>| Violation {
+            kind,
+            events: evs.iter().map(|e| e.id()).collect(),
+            distances: distances.into_iter().map(f64::from).collect(),
+        };
+
+        let mut res = Vec::new();
+        let a = events[0];
+        let d_aa = d(a, a);
+        if d_aa != 0. {
+            res.push(violation(Identity, &[a], vec![d_aa]));
+        }
+        let Some(&b) = events.get(1) else {
+            return res;
+        };
+        let d_ab = d(a, b);
+        let d_ba = d(b, a);
+        for (dist, evs) in [(d_ab, [a, b]), (d_ba, [b, a])] {
+            if dist < 0. {
+                res.push(violation(NonNegativity, &evs, vec![dist]));
+            }
+        }
+        if !self.approx_le(d_ab, d_ba) || !self.approx_le(d_ba, d_ab) {
+            res.push(violation(Symmetry, &[a, b], vec![d_ab, d_ba]));
+        }
+        let Some(&c) = events.get(2) else {
+            return res;
+        };
+        let d_bc = d(b, c);
+        let d_ac = d(a, c);
+        // check the triangle inequality with each event in the middle,
+        // reusing the computed distances
+        let triangles = [
+            ([a, b, c], [d_ab, d_bc, d_ac]),
+            ([b, a, c], [d_ab, d_ac, d_bc]),
+            ([a, c, b], [d_ac, d_bc, d_ab]),
+        ];
+        for (evs, [d1, d2, d3]) in triangles {
+            if !self.approx_le(d3, d1 + d2) {
+                res.push(violation(Triangle, &evs, vec![d1, d2, d3]));
+            }
+        }
+        res
+    }
+
+    // check `x <= y` up to the relative tolerance
+    fn approx_le(&self, x: N64, y: N64) -> bool {
+        if x <= y {
+            return true;
+        }
+        if !x.is_finite() {
+            return false;
+        }
+        x - y <= std::cmp::max(x.abs(), y.abs()) * self.tolerance
+    }
+}
+
+/// Result of a [MetricCheck]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MetricReport {
+    /// Number of tested event triples
+    pub samples: usize,
+    /// All found violations of metric properties
+    pub violations: Vec<Violation>,
+}
+
+impl MetricReport {
+    /// Whether no violations were found
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Log the found violations
+    ///
+    /// The first few violations are logged as warnings, the remaining
+    /// ones only at debug level.
+    pub fn log(&self) {
+        if self.passed() {
+            debug!("No metric violations found in {} samples", self.samples);
+            return;
+        }
+        warn!(
+            "Found {} violations of metric properties in {} samples",
+            self.violations.len(),
+            self.samples
+        );
+        for (n, violation) in self.violations.iter().enumerate() {
+            if n < MAX_WARNINGS {
+                warn!("{violation}");
+            } else {
+                debug!("{violation}");
+            }
+        }
+    }
+}
+
+/// Violation of a metric property
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Violation {
+    /// The violated property
+    pub kind: ViolationKind,
+    /// Ids of the offending events
+    pub events: Vec<usize>,
+    /// Computed distances
+    ///
+    /// For a violation of
+    /// - [identity](ViolationKind::Identity): `d(a, a)`
+    /// - [non-negativity](ViolationKind::NonNegativity): `d(a, b)`
+    /// - [symmetry](ViolationKind::Symmetry): `d(a, b)`, `d(b, a)`
+    /// - [the triangle inequality](ViolationKind::Triangle): `d(a, b)`,
+    ///   `d(b, c)`, `d(a, c)`
+    pub distances: Vec<f64>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ViolationKind::*;
+
+        let ev = &self.events;
+        let d = &self.distances;
+        match self.kind {
+            Identity => {
+                write!(f, "Identity violated: d({0}, {0}) = {1}", ev[0], d[0])
+            }
+            NonNegativity => write!(
+                f,
+                "Non-negativity violated: d({}, {}) = {}",
+                ev[0], ev[1], d[0]
+            ),
+            Symmetry => write!(
+                f,
+                "Symmetry violated: d({0}, {1}) = {2}, d({1}, {0}) = {3}",
+                ev[0], ev[1], d[0], d[1]
+            ),
+            Triangle => write!(
+                f,
+                "Triangle inequality violated: d({0}, {2}) = {5} > d({0}, {1}) + d({1}, {2}) = {3} + {4}",
+                ev[0], ev[1], ev[2], d[0], d[1], d[2]
+            ),
+        }
+    }
+}
+
+/// Property of a metric
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum ViolationKind {
+    /// `d(a, a) = 0`
+    Identity,
+    /// `d(a, b) ≥ 0`
+    NonNegativity,
+    /// `d(a, b) = d(b, a)`
+    Symmetry,
+    /// `d(a, c) ≤ d(a, b) + d(b, c)`
+    Triangle,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::event::test_event;
+    use particle_id::ParticleID;
+
+    const JET: ParticleID = ParticleID::new(81);
+
+    fn event(id: usize, pt: f64) -> Event {
+        test_event(id, 1., &[(JET.id(), [pt, pt, 0., 0.])])
+    }
+
+    struct Asymmetric;
+
+    impl Distance for Asymmetric {
+        fn distance(&self, ev1: &Event, ev2: &Event) -> N64 {
+            let pt = |ev: &Event| ev.outgoing_with_pid(JET)[0].pt();
+            n64(2.) * pt(ev1) - pt(ev2)
+        }
+    }
+
+    #[test]
+    fn tst_metric_check() {
+        let events: Vec<_> = (0..5).map(|n| event(n, n as f64)).collect();
+        let check = MetricCheck {
+            samples: 20,
+            ..Default::default()
+        };
+
+        let distance = crate::distance::EuclWithScaledPt::new(n64(0.));
+        assert!(check.check(&distance, &events).passed());
+
+        let report = check.check(&Asymmetric, &events);
+        assert!(!report.passed());
+        let kinds: Vec<_> = report.violations.iter().map(|v| v.kind).collect();
+        assert!(kinds.contains(&ViolationKind::Symmetry));
+        assert!(kinds.contains(&ViolationKind::Identity));
+        assert!(kinds.contains(&ViolationKind::NonNegativity));
+    }
+
+    #[test]
+    fn tst_triangle_orderings() {
+        let a = test_event(0, 1., &[(JET.id(), [10., 10., 0., 0.])]);
+        let b = test_event(1, 1., &[(JET.id(), [10., -10., 0., 0.])]);
+        let c = test_event(2, 1., &[]);
+        let distance = crate::distance::EuclWithScaledPt::new(n64(0.))
+            .with_unmatched_penalty(n64(0.7));
+        // only violated with `c` in the middle
+        let violations =
+            MetricCheck::default().check_sample(&distance, &[&a, &b, &c]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].kind, ViolationKind::Triangle);
+        assert_eq!(violations[0].events, [0, 2, 1]);
+    }
+}
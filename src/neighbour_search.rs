@@ -71,7 +71,12 @@ where
         let max_dist = self.max_dist;
         let mut dist = self.dist.clone();
         dist.par_iter_mut().for_each(|(id, dist)| {
-            *dist = d.distance(id, point);
+            *dist = if d.lower_bound(id, point) > max_dist {
+                N64::infinity()
+            } else {
+                d.distance_within(id, point, max_dist)
+                    .unwrap_or(N64::infinity())
+            };
         });
         NaiveNeighbourIter::new(dist, *point, max_dist)
     }
@@ -0,0 +1,252 @@
+//! Observables computed from events in the internal format
+//!
+//! Observables can be parsed from strings like `pt(jet,1)`. The
+//! supported observables are
+//!
+//! - `pt(CLASS,N)`: transverse momentum of the `N`th hardest particle
+//! - `y(CLASS,N)`: rapidity of the `N`th hardest particle
+//! - `m(CLASS,N,CLASS,M)`: invariant mass of two particles
+//! - `dy(CLASS,N,CLASS,M)`: absolute rapidity difference of two
+//!   particles
+//! - `ht(CLASS)`: scalar sum of the transverse momenta of all particles
+//! - `n(CLASS)`: number of particles
+//!
+//! `CLASS` is either `jet`, `bjet` or `cjet` for flavour-tagged jets,
+//! `lepton` for dressed leptons, `photon`, `met` for the missing
+//! transverse momentum, or a particle id following
+//! the [PDG Monte Carlo Particle Numbering Scheme](https://pdg.lbl.gov/2021/mcdata/mc_particle_id_contents.html).
+//! Particles of the same class are ordered by decreasing transverse
+//! momentum, starting with 1.
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use noisy_float::prelude::*;
+use particle_id::{sm_elementary_particles::photon, ParticleID};
+use thiserror::Error;
+
+use crate::{
+    cluster::{
+        PID_B_JET, PID_C_JET, PID_DRESSED_LEPTON, PID_JET, PID_MISSING_PT,
+    },
+    event::Event,
+    four_vector::FourVector,
+};
+
+/// A particle in an event, identified by its class and position
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Particle {
+    /// Particle class
+    pub class: ParticleID,
+    /// Position within the class, ordered by decreasing transverse
+    /// momentum and starting with 0
+    pub index: usize,
+}
+
+impl Particle {
+    fn momentum<E: OutgoingMomenta>(self, e: &E) -> Option<FourVector> {
+        e.outgoing_with_pid(self.class).get(self.index).copied()
+    }
+}
+
+/// Access to the outgoing particle momenta of an event
+///
+/// Observables can be computed for all types implementing this trait.
+pub trait OutgoingMomenta {
+    /// The outgoing particle momenta with the given particle id,
+    /// ordered by decreasing transverse momentum
+    fn outgoing_with_pid(&self, pid: ParticleID) -> &[FourVector];
+}
+
+impl OutgoingMomenta for Event {
+    fn outgoing_with_pid(&self, pid: ParticleID) -> &[FourVector] {
+        Event::outgoing_with_pid(self, pid)
+    }
+}
+
+/// An observable
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Observable {
+    /// Transverse momentum
+    Pt(Particle),
+    /// Rapidity
+    Rapidity(Particle),
+    /// Invariant mass of two particles
+    InvariantMass(Particle, Particle),
+    /// Absolute value of the rapidity difference between two particles
+    RapidityDifference(Particle, Particle),
+    /// Scalar sum of the transverse momenta of all particles of a class
+    HT(ParticleID),
+    /// Number of particles of a class
+    Multiplicity(ParticleID),
+}
+
+impl Observable {
+    /// Compute the value of the observable for the given event
+    ///
+    /// Returns `None` if the event does not contain the required
+    /// particles.
+    pub fn value<E: OutgoingMomenta>(&self, e: &E) -> Option<N64> {
+        use Observable::*;
+        match *self {
+            Pt(p) => p.momentum(e).map(|p| p.pt()),
+            Rapidity(p) => rapidity(&p.momentum(e)?),
+            InvariantMass(p, q) => {
+                let p = p.momentum(e)? + q.momentum(e)?;
+                let msq = p[0] * p[0] - p.spatial_norm_sq();
+                Some(msq.max(n64(0.)).sqrt())
+            }
+            RapidityDifference(p, q) => {
+                let y1 = rapidity(&p.momentum(e)?)?;
+                let y2 = rapidity(&q.momentum(e)?)?;
+                Some((y1 - y2).abs())
+            }
+            HT(class) => {
+                Some(e.outgoing_with_pid(class).iter().map(|p| p.pt()).sum())
+            }
+            Multiplicity(class) => {
+                Some(n64(e.outgoing_with_pid(class).len() as f64))
+            }
+        }
+    }
+}
+
+// undefined for massless particles along the beam axis
+fn rapidity(p: &FourVector) -> Option<N64> {
+    let e = p[0];
+    let pz = p[3];
+    (e > pz.abs()).then(|| ((e + pz) / (e - pz)).ln() / 2.)
+}
+
+/// Error parsing an [Observable]
+#[derive(Debug, Clone, Error)]
+pub enum ObservableParseError {
+    /// Not of the form `name(arguments)`
+    #[error("Failed to parse observable `{0}`: expected `name(arguments)`")]
+    SyntaxErr(String),
+    /// Unknown observable name
+    #[error("Unknown observable: {0}")]
+    UnknownObservable(String),
+    /// Wrong number of arguments
+    #[error("Observable `{0}` expects {1} arguments")]
+    NArgsErr(String, usize),
+    /// Unknown particle class
+    #[error("Unknown particle class: {0}")]
+    UnknownClass(String),
+    /// Invalid particle position
+    #[error("Invalid particle position `{0}`: expected a positive integer")]
+    IndexErr(String),
+}
+
+impl FromStr for Observable {
+    type Err = ObservableParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Observable::*;
+        use ObservableParseError::*;
+
+        let s = s.trim();
+        let Some((name, args)) =
+            s.strip_suffix(')').and_then(|s| s.split_once('('))
+        else {
+            return Err(SyntaxErr(s.to_owned()));
+        };
+        let name = name.trim();
+        let args: Vec<_> = args.split(',').map(|a| a.trim()).collect();
+        let nargs = match name {
+            "pt" | "y" => 2,
+            "m" | "dy" => 4,
+            "ht" | "n" => 1,
+            _ => return Err(UnknownObservable(name.to_owned())),
+        };
+        if args.len() != nargs {
+            return Err(NArgsErr(name.to_owned(), nargs));
+        }
+        let observable = match name {
+            "pt" => Pt(parse_particle(args[0], args[1])?),
+            "y" => Rapidity(parse_particle(args[0], args[1])?),
+            "m" => InvariantMass(
+                parse_particle(args[0], args[1])?,
+                parse_particle(args[2], args[3])?,
+            ),
+            "dy" => RapidityDifference(
+                parse_particle(args[0], args[1])?,
+                parse_particle(args[2], args[3])?,
+            ),
+            "ht" => HT(parse_class(args[0])?),
+            "n" => Multiplicity(parse_class(args[0])?),
+            _ => unreachable!(),
+        };
+        Ok(observable)
+    }
+}
+
+/// Parse a particle class
+///
+/// This is either `jet`, `bjet`, `cjet`, `lepton`, `photon`, `met`, or
+/// a particle id.
+pub fn parse_class(s: &str) -> Result<ParticleID, ObservableParseError> {
+    match s {
+        "jet" => Ok(PID_JET),
+        "bjet" => Ok(PID_B_JET),
+        "cjet" => Ok(PID_C_JET),
+        "lepton" => Ok(PID_DRESSED_LEPTON),
+        "photon" => Ok(photon),
+        "met" => Ok(PID_MISSING_PT),
+        _ => s
+            .parse()
+            .map(ParticleID::new)
+            .map_err(|_| ObservableParseError::UnknownClass(s.to_owned())),
+    }
+}
+
+fn parse_particle(
+    class: &str,
+    index: &str,
+) -> Result<Particle, ObservableParseError> {
+    let class = parse_class(class)?;
+    match index.parse::<usize>() {
+        Ok(index) if index > 0 => Ok(Particle {
+            class,
+            index: index - 1,
+        }),
+        _ => Err(ObservableParseError::IndexErr(index.to_owned())),
+    }
+}
+
+struct Class(ParticleID);
+
+impl Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            PID_JET => write!(f, "jet"),
+            PID_B_JET => write!(f, "bjet"),
+            PID_C_JET => write!(f, "cjet"),
+            PID_DRESSED_LEPTON => write!(f, "lepton"),
+            PID_MISSING_PT => write!(f, "met"),
+            pid if pid == photon => write!(f, "photon"),
+            pid => write!(f, "{}", pid.id()),
+        }
+    }
+}
+
+impl Display for Particle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", Class(self.class), self.index + 1)
+    }
+}
+
+impl Display for Observable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Observable::*;
+        match self {
+            Pt(p) => write!(f, "pt({p})"),
+            Rapidity(p) => write!(f, "y({p})"),
+            InvariantMass(p, q) => write!(f, "m({p},{q})"),
+            RapidityDifference(p, q) => write!(f, "dy({p},{q})"),
+            HT(class) => write!(f, "ht({})", Class(*class)),
+            Multiplicity(class) => write!(f, "n({})", Class(*class)),
+        }
+    }
+}
@@ -1,15 +1,20 @@
 use std::{
     collections::HashMap,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
 };
 
 use audec::auto_decompress;
 use hepmc2::reader::LineParseError;
 use log::debug;
+use tempfile::TempPath;
 use thiserror::Error;
 
-use crate::{file::File, traits::Rewind, util::trim_ascii_start};
+use crate::{
+    file::File,
+    traits::{Rewind, SourceIndex},
+    util::trim_ascii_start,
+};
 
 const ROOT_MAGIC_BYTES: [u8; 4] = [b'r', b'o', b'o', b't'];
 
@@ -18,13 +23,21 @@ const ROOT_MAGIC_BYTES: [u8; 4] = [b'r', b'o', b'o', b't'];
 /// The format is determined automatically. If you know the format
 /// beforehand, you can use
 /// e.g. [hepmc2::FileReader](crate::hepmc2::FileReader) instead.
-pub struct FileReader(Box<dyn EventFileReader>);
+///
+/// Streams that cannot be rewound, i.e. standard input (`-`) and
+/// named pipes, are first copied to a temporary file, which is
+/// removed again when the reader is dropped.
+pub struct FileReader {
+    path: PathBuf,
+    reader: Box<dyn EventFileReader>,
+    _spool: Option<TempPath>,
+}
 
 impl Rewind for FileReader {
     type Error = RewindError;
 
     fn rewind(&mut self) -> Result<(), Self::Error> {
-        self.0.rewind()
+        self.reader.rewind()
     }
 }
 
@@ -32,11 +45,17 @@ impl Iterator for FileReader {
     type Item = Result<avery::Event, EventReadError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next()
+        self.reader.next()
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.0.size_hint()
+        self.reader.size_hint()
+    }
+}
+
+impl SourceIndex for FileReader {
+    fn source_index(&self) -> usize {
+        0
     }
 }
 
@@ -50,56 +69,178 @@ impl FileReader {
     /// with channel-dependent scaling factors for STRIPPER XML events
     pub fn with_scaling<P: AsRef<Path>>(
         path: P,
-        _scaling: &HashMap<String, f64>, // only used in "stripper-xml" feature
+        scaling: &HashMap<String, f64>,
     ) -> Result<FileReader, CreateError> {
-        use crate::hepmc2::FileReader as HepMCReader;
-        let file = File::open(&path)?;
-        let mut r = auto_decompress(BufReader::new(file));
-        let bytes = match r.fill_buf() {
-            Ok(bytes) => bytes,
-            Err(_) => {
-                let file = File::open(&path)?;
-                let reader = HepMCReader::new(file)?;
-                return Ok(FileReader(Box::new(reader)));
-            }
+        Self::from_input(Input::new(path.as_ref())?, scaling)
+    }
+
+    fn from_input(
+        input: Input,
+        scaling: &HashMap<String, f64>,
+    ) -> Result<FileReader, CreateError> {
+        let reader = open(input.as_ref(), scaling)?;
+        let Input { path, spool } = input;
+        Ok(FileReader {
+            path,
+            reader,
+            _spool: spool,
+        })
+    }
+
+    /// The path of the file the events are read from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Check whether `path` refers to a stream that cannot be rewound
+///
+/// This is the case for `-`, denoting standard input, and for named
+/// pipes, character devices, and sockets.
+pub fn is_stream<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    if path == Path::new("-") {
+        return true;
+    }
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            let ft = metadata.file_type();
+            return ft.is_fifo() || ft.is_char_device() || ft.is_socket();
+        }
+    }
+    false
+}
+
+// An input file
+//
+// Streams are copied to a temporary spool file when the input is
+// created, so that they can be read several times.
+pub(crate) struct Input {
+    path: PathBuf,
+    spool: Option<TempPath>,
+}
+
+impl Input {
+    fn new(path: &Path) -> Result<Self, std::io::Error> {
+        let spool = if is_stream(path) {
+            Some(spool(path)?)
+        } else {
+            None
         };
-        if bytes.starts_with(&ROOT_MAGIC_BYTES) {
-            let path = path.as_ref().to_owned();
-            if !cfg!(feature = "ntuple") {
-                return Err(CreateError::RootUnsupported(path));
-            }
-            #[cfg(feature = "ntuple")]
-            {
-                debug!("Read {path:?} as ROOT ntuple");
-                let reader = crate::ntuple::Reader::new(path)?;
-                return Ok(FileReader(Box::new(reader)));
-            }
-        } else if trim_ascii_start(bytes).starts_with(b"<?xml") {
-            let path = path.as_ref();
-            #[cfg(not(feature = "stripper-xml"))]
-            return Err(CreateError::XMLUnsupported(path.to_owned()));
-            #[cfg(feature = "stripper-xml")]
-            {
-                debug!("Read {path:?} as STRIPPER XML file");
-                use crate::stripper_xml::FileReader as XMLReader;
-                let file = File::open(path)?;
-                let reader = XMLReader::new(file, _scaling)?;
-                return Ok(FileReader(Box::new(reader)));
-            }
+        Ok(Self {
+            path: path.to_owned(),
+            spool,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for Input {
+    fn as_ref(&self) -> &Path {
+        self.spool.as_deref().unwrap_or(&self.path)
+    }
+}
+
+// Copy a stream to a temporary file
+//
+// The input is decompressed and stored with fast zstd compression,
+// which is cheap to decompress again in the following passes. ROOT
+// files are stored as-is, since they are not read through a
+// decompressor. The spool file is created in the directory given by
+// the `TMPDIR` environment variable, falling back to the system
+// default.
+fn spool(path: &Path) -> Result<TempPath, std::io::Error> {
+    let input: Box<dyn Read> = if path == Path::new("-") {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(std::fs::File::open(path)?)
+    };
+    let mut input = auto_decompress(BufReader::new(input));
+    let dir = std::env::var_os("TMPDIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let mut spool = tempfile::Builder::new()
+        .prefix("cres-")
+        .suffix(".spool")
+        .tempfile_in(dir)?;
+    debug!("Copying {path:?} to {:?}", spool.path());
+    let mut out = BufWriter::new(spool.as_file_mut());
+    let mut out = if input.fill_buf()?.starts_with(&ROOT_MAGIC_BYTES) {
+        std::io::copy(&mut input, &mut out)?;
+        out
+    } else {
+        let mut encoder = zstd::Encoder::new(out, 1)?;
+        std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?
+    };
+    out.flush()?;
+    drop(out);
+    Ok(spool.into_temp_path())
+}
+
+fn open(
+    path: &Path,
+    _scaling: &HashMap<String, f64>, // only used in "stripper-xml" feature
+) -> Result<Box<dyn EventFileReader>, CreateError> {
+    use crate::hepmc2::FileReader as HepMCReader;
+    let file = File::open(path)?;
+    let mut r = auto_decompress(BufReader::new(file));
+    let bytes = match r.fill_buf() {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            let file = File::open(path)?;
+            let reader = HepMCReader::new(file)?;
+            return Ok(Box::new(reader));
+        }
+    };
+    if bytes.starts_with(&ROOT_MAGIC_BYTES) {
+        let path = path.to_owned();
+        if !cfg!(feature = "ntuple") {
+            return Err(CreateError::RootUnsupported(path));
+        }
+        #[cfg(feature = "ntuple")]
+        {
+            debug!("Read {path:?} as ROOT ntuple");
+            let reader = crate::ntuple::Reader::new(path)?;
+            return Ok(Box::new(reader));
         }
-        #[cfg(feature = "lhef")]
-        if bytes.starts_with(b"<LesHouchesEvents") {
-            use crate::lhef::FileReader as LHEFReader;
-            debug!("Read {:?} as LHEF file", path.as_ref());
+    } else if trim_ascii_start(bytes).starts_with(b"<?xml") {
+        #[cfg(not(feature = "stripper-xml"))]
+        return Err(CreateError::XMLUnsupported(path.to_owned()));
+        #[cfg(feature = "stripper-xml")]
+        {
+            debug!("Read {path:?} as STRIPPER XML file");
+            use crate::stripper_xml::FileReader as XMLReader;
             let file = File::open(path)?;
-            let reader = LHEFReader::new(file)?;
-            return Ok(FileReader(Box::new(reader)));
+            let reader = XMLReader::new(file, _scaling)?;
+            return Ok(Box::new(reader));
         }
-        debug!("Read {:?} as HepMC file", path.as_ref());
+    }
+    if trim_ascii_start(bytes).starts_with(b"HepMC::Version 3") {
+        use crate::hepmc3::FileReader as HepMC3Reader;
+        debug!("Read {path:?} as HepMC3 file");
         let file = File::open(path)?;
-        let reader = HepMCReader::new(file)?;
-        Ok(FileReader(Box::new(reader)))
+        let reader = HepMC3Reader::new(file)?;
+        return Ok(Box::new(reader));
     }
+    #[cfg(feature = "lhef")]
+    if bytes.starts_with(b"<LesHouchesEvents") {
+        use crate::lhef::FileReader as LHEFReader;
+        debug!("Read {path:?} as LHEF file");
+        let file = File::open(path)?;
+        let reader = LHEFReader::new(file)?;
+        return Ok(Box::new(reader));
+    }
+    debug!("Read {path:?} as HepMC file");
+    let file = File::open(path)?;
+    let reader = HepMCReader::new(file)?;
+    Ok(Box::new(reader))
 }
 
 /// Error creating an event reader
@@ -142,6 +283,9 @@ pub enum EventReadError {
     /// Error reading a HepMC event
     #[error("Error reading HepMC record")]
     HepMCError(#[from] LineParseError),
+    /// Error reading a HepMC3 event
+    #[error("Error reading HepMC3 record")]
+    HepMC3Error(#[from] crate::hepmc3::reader::LineParseError),
     #[cfg(feature = "ntuple")]
     /// Error reading a ROOT ntuple event
     #[error("Error reading ntuple event")]
@@ -185,6 +329,13 @@ impl<R: Rewind> Rewind for CombinedReader<R> {
     }
 }
 
+impl<R> SourceIndex for CombinedReader<R> {
+    /// Index of the reader the last event was read from
+    fn source_index(&self) -> usize {
+        self.current
+    }
+}
+
 impl<R: Iterator> Iterator for CombinedReader<R> {
     type Item = <R as Iterator>::Item;
 
@@ -222,38 +373,52 @@ impl CombinedReader<FileReader> {
         I: IntoIterator<Item = P>,
         P: AsRef<Path>,
     {
+        let inputs = files
+            .into_iter()
+            .map(|f| {
+                let f = f.as_ref();
+                Input::new(f).map_err(|err| {
+                    CreateError::FileError(
+                        f.to_path_buf(),
+                        Box::new(CreateError::from(err)),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
         #[cfg(feature = "stripper-xml")]
         {
-            let (files, scaling) =
-                crate::stripper_xml::reader::extract_scaling(files)?;
-            Self::from_files_with_scaling(files, &scaling)
+            let (inputs, scaling) =
+                crate::stripper_xml::reader::extract_scaling(inputs)?;
+            Self::from_inputs_with_scaling(inputs, &scaling)
         }
 
         #[cfg(not(feature = "stripper-xml"))]
-        return Self::from_files_with_scaling(files, &HashMap::new());
+        return Self::from_inputs_with_scaling(inputs, &HashMap::new());
     }
 
-    fn from_files_with_scaling<I, P>(
-        files: I,
+    fn from_inputs_with_scaling(
+        inputs: Vec<Input>,
         scaling: &HashMap<String, f64>,
-    ) -> Result<Self, CreateError>
-    where
-        I: IntoIterator<Item = P>,
-        P: AsRef<Path>,
-    {
-        let readers: Result<_, _> = files
+    ) -> Result<Self, CreateError> {
+        let readers: Result<_, _> = inputs
             .into_iter()
-            .map(|f| {
-                FileReader::with_scaling(f.as_ref(), scaling).map_err(|err| {
-                    CreateError::FileError(
-                        f.as_ref().to_path_buf(),
-                        Box::new(err),
-                    )
-                })
+            .map(|input| {
+                let path = input.path().to_owned();
+                FileReader::from_input(input, scaling)
+                    .map_err(|err| CreateError::FileError(path, Box::new(err)))
             })
             .collect();
         Ok(Self::new(readers?))
     }
+
+    /// Paths of the event files in the order they are read
+    ///
+    /// With the `stripper-xml` feature, files that only contain
+    /// normalisation information are not included.
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.readers.iter().map(|r| r.path())
+    }
 }
 
 /// Reader from an event file
@@ -270,3 +435,5 @@ impl EventFileReader for crate::ntuple::Reader {}
 impl EventFileReader for crate::lhef::FileReader {}
 
 impl EventFileReader for crate::hepmc2::FileReader {}
+
+impl EventFileReader for crate::hepmc3::FileReader {}
@@ -0,0 +1,306 @@
+use std::time::Duration;
+
+use noisy_float::prelude::*;
+use rayon::prelude::*;
+use serde::{Serialize, Serializer};
+
+use crate::event::Event;
+use crate::validation::ValidationReport;
+
+/// Summary of a [Cres](crate::cres::Cres) run
+///
+/// This is returned by [Cres::run](crate::cres::Cres::run) and can
+/// be serialised, e.g. to JSON.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RunReport {
+    /// Event sample before resampling
+    pub initial: SampleSummary,
+    /// Event sample after resampling and unweighting
+    #[serde(rename = "final")]
+    pub resampled: SampleSummary,
+    /// Statistics on the constructed cells
+    ///
+    /// This is `None` if the resampler does not provide any statistics.
+    pub cells: Option<CellSummary>,
+    /// Comparison of histograms before and after resampling
+    ///
+    /// This is `None` unless requested with
+    /// [Cres::with_validation](crate::cres::Cres::with_validation).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validation: Option<ValidationReport>,
+    /// Wall time spent in the individual steps
+    pub timings: Timings,
+}
+
+/// Summary of an event sample
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SampleSummary {
+    /// Number of events
+    pub nevents: usize,
+    /// Number of events with negative weight
+    pub nneg_weight: usize,
+    /// Sum of central event weights
+    pub sum_weights: f64,
+    /// Statistical error of the sum of central event weights
+    pub sum_weights_err: f64,
+    /// Fraction of the absolute sum of weights from negative weights
+    pub neg_weight_fraction: f64,
+}
+
+impl SampleSummary {
+    /// Summarise the central weights of the given events
+    pub fn new(events: &[Event]) -> Self {
+        let sum_wt: N64 = events.par_iter().map(|e| e.weight()).sum();
+        let sum_neg_wt: N64 = events
+            .par_iter()
+            .map(|e| e.weight())
+            .filter(|&w| w < 0.)
+            .sum();
+        let sum_wtsqr: N64 =
+            events.par_iter().map(|e| e.weight() * e.weight()).sum();
+        let nneg_weight = events.par_iter().filter(|e| e.weight() < 0.).count();
+        let sum_abs_wt = sum_wt - sum_neg_wt * 2.;
+        let neg_weight_fraction = if sum_abs_wt > 0. {
+            f64::from(-sum_neg_wt / sum_abs_wt)
+        } else {
+            0.
+        };
+        Self {
+            nevents: events.len(),
+            nneg_weight,
+            sum_weights: sum_wt.into(),
+            sum_weights_err: sum_wtsqr.sqrt().into(),
+            neg_weight_fraction,
+        }
+    }
+}
+
+/// Statistics on the cells constructed during resampling
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CellSummary {
+    /// Number of cells
+    pub ncells: usize,
+    /// Number of cells with a negative sum of weights
+    ///
+    /// This can only be non-zero if the cell size is limited.
+    pub nneg_weight: usize,
+    /// Distribution of the cell radii
+    pub radius: RadiusQuantiles,
+}
+
+/// Outcome of resampling with a given maximum cell size
+///
+/// See
+/// [Resampler::scan_max_cell_size](crate::resampler::Resampler::scan_max_cell_size).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CellSizeScanPoint {
+    /// Maximum cell size
+    pub max_cell_size: f64,
+    /// Fraction of the absolute sum of weights from negative weights
+    /// after resampling
+    pub neg_weight_fraction: f64,
+    /// Number of cells
+    pub ncells: usize,
+    /// Number of cells with a negative sum of weights
+    pub nneg_weight: usize,
+    /// Median cell radius
+    pub median_radius: f64,
+}
+
+/// Quantiles of the cell radius distribution
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RadiusQuantiles {
+    /// 10% quantile
+    pub q10: f64,
+    /// 25% quantile
+    pub q25: f64,
+    /// Median
+    pub median: f64,
+    /// 75% quantile
+    pub q75: f64,
+    /// 90% quantile
+    pub q90: f64,
+    /// Largest cell radius
+    pub max: f64,
+}
+
+impl RadiusQuantiles {
+    /// Compute quantiles from the given cell radii
+    pub fn new(radii: &mut [N64]) -> Self {
+        if radii.is_empty() {
+            return Self::default();
+        }
+        radii.par_sort_unstable();
+        let quantile = |q: f64| {
+            let idx = (q * radii.len() as f64) as usize;
+            f64::from(radii[std::cmp::min(idx, radii.len() - 1)])
+        };
+        Self {
+            q10: quantile(0.1),
+            q25: quantile(0.25),
+            median: quantile(0.5),
+            q75: quantile(0.75),
+            q90: quantile(0.9),
+            max: radii.last().copied().unwrap().into(),
+        }
+    }
+}
+
+/// Wall time spent in the individual steps
+///
+/// Times are serialised in seconds.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Timings {
+    /// Reading and converting events
+    #[serde(serialize_with = "as_secs")]
+    pub read: Duration,
+    /// Resampling
+    #[serde(serialize_with = "as_secs")]
+    pub resample: Duration,
+    /// Unweighting
+    #[serde(serialize_with = "as_secs")]
+    pub unweight: Duration,
+    /// Writing events
+    #[serde(serialize_with = "as_secs")]
+    pub write: Duration,
+}
+
+fn as_secs<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    use crate::cres::{CresBuilder, CresError};
+    use crate::event::test_event;
+    use crate::event_cache::{CacheError, EventCache};
+    use crate::event_store::{EventStore, StoringConverter};
+    use crate::resampler::DefaultResamplerBuilder;
+    use crate::traits::{Rewind, TryConvert, Write};
+    use crate::unweight::NO_UNWEIGHTING;
+
+    const EVENTS: [(f64, [f64; 4]); 3] = [
+        (1., [10., 10., 0., 0.]),
+        (-0.5, [11., 11., 0., 0.]),
+        (2., [100., 0., 100., 0.]),
+    ];
+
+    #[derive(Default)]
+    struct Reader(usize);
+
+    impl Iterator for Reader {
+        type Item = Result<Event, Infallible>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let (weight, p) = EVENTS.get(self.0)?;
+            self.0 += 1;
+            Some(Ok(test_event(0, *weight, &[(81, *p)])))
+        }
+    }
+
+    impl Rewind for Reader {
+        type Error = Infallible;
+
+        fn rewind(&mut self) -> Result<(), Self::Error> {
+            self.0 = 0;
+            Ok(())
+        }
+    }
+
+    struct Identity;
+
+    impl TryConvert<Event, Event> for Identity {
+        type Error = Infallible;
+
+        fn try_convert(&mut self, ev: Event) -> Result<Event, Self::Error> {
+            Ok(ev)
+        }
+    }
+
+    struct NoWriter;
+
+    impl<R> Write<R> for NoWriter {
+        type Error = Infallible;
+
+        fn write(
+            &mut self,
+            _r: &mut R,
+            _e: &[Event],
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tst_run_report() {
+        let mut cres = CresBuilder {
+            reader: Reader::default(),
+            converter: Identity,
+            resampler: DefaultResamplerBuilder::default().build(),
+            unweighter: NO_UNWEIGHTING,
+            writer: NoWriter,
+        }
+        .build();
+        let report = cres.run().unwrap();
+
+        assert_eq!(report.initial.nevents, 3);
+        assert_eq!(report.initial.nneg_weight, 1);
+        assert!((report.initial.sum_weights - 2.5).abs() < 1e-12);
+        assert!((report.initial.neg_weight_fraction - 0.5 / 3.5).abs() < 1e-12);
+        assert_eq!(report.resampled.nevents, 3);
+        assert_eq!(report.resampled.nneg_weight, 0);
+        assert!((report.resampled.sum_weights - 2.5).abs() < 1e-12);
+        assert_eq!(report.resampled.neg_weight_fraction, 0.);
+        let cells = report.cells.unwrap();
+        assert_eq!(cells.ncells, 1);
+        assert_eq!(cells.nneg_weight, 0);
+
+        let json = serde_json::to_value(&report).unwrap();
+        let keys = |v: &serde_json::Value| -> Vec<String> {
+            v.as_object().unwrap().keys().cloned().collect()
+        };
+        assert_eq!(keys(&json), ["cells", "final", "initial", "timings"]);
+        assert_eq!(
+            keys(&json["final"]),
+            [
+                "neg_weight_fraction",
+                "nevents",
+                "nneg_weight",
+                "sum_weights",
+                "sum_weights_err"
+            ]
+        );
+        assert_eq!(json["final"]["nevents"], 3);
+        assert_eq!(keys(&json["cells"]), ["ncells", "nneg_weight", "radius"]);
+        assert_eq!(
+            keys(&json["timings"]),
+            ["read", "resample", "unweight", "write"]
+        );
+        assert!(json["timings"]["read"].is_f64());
+    }
+
+    #[test]
+    fn tst_cache_with_storing_converter() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(EventStore::new().unwrap());
+        let mut cres = CresBuilder {
+            reader: Reader::default(),
+            converter: StoringConverter::new(Identity, store),
+            resampler: DefaultResamplerBuilder::default().build(),
+            unweighter: NO_UNWEIGHTING,
+            writer: NoWriter,
+        }
+        .build()
+        .with_cache(EventCache::new(dir.path(), 0));
+        assert!(matches!(
+            cres.read_events(),
+            Err(CresError::CacheErr(CacheError::MomentaErr))
+        ));
+        assert!(!EventCache::new(dir.path(), 0).file().exists());
+    }
+}
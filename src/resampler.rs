@@ -1,14 +1,23 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::default::Default;
 use std::marker::PhantomData;
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 
 use crate::cell::Cell;
 use crate::cell_collector::CellCollector;
+use crate::checkpoint::{self, CheckpointError, Checkpointing};
 use crate::distance::{Distance, EuclWithScaledPt, DistWrapper};
 use crate::event::Event;
-use crate::neighbour_search::TreeSearch;
+use crate::metric_check::{MetricCheck, MetricReport};
+use crate::neighbour_search::{NaiveNeighbourSearch, TreeSearch};
 use crate::progress_bar::{Progress, ProgressBar};
+use crate::report::{
+    CellSizeScanPoint, CellSummary, RadiusQuantiles, SampleSummary,
+};
 use crate::seeds::{StrategicSelector, Strategy};
 use crate::traits::{
     NeighbourData, NeighbourSearch, ObserveCell, Resample, SelectSeeds,
@@ -24,7 +33,17 @@ use thread_local::ThreadLocal;
 
 /// Error during resampling
 #[derive(Debug, Error)]
-pub enum ResamplingError {}
+pub enum ResamplingError {
+    /// Error writing a checkpoint
+    #[error("Failed to write checkpoint to {0:?}")]
+    CheckpointErr(PathBuf, #[source] CheckpointError),
+    /// Error resuming from a checkpoint
+    #[error("Failed to resume from checkpoint {0:?}")]
+    ResumeErr(PathBuf, #[source] CheckpointError),
+}
+
+// number of seeds processed between checks whether to write a checkpoint
+const CHECKPOINT_CHUNK_SIZE: usize = 1024;
 
 /// Main resampling class
 pub struct Resampler<D, N, O, S> {
@@ -33,6 +52,7 @@ pub struct Resampler<D, N, O, S> {
     neighbour_search: PhantomData<N>,
     observer: O,
     max_cell_size: Option<f64>,
+    checkpointing: Option<Checkpointing>,
 }
 
 impl<D, N, O, S> Resampler<D, N, O, S> {
@@ -75,9 +95,9 @@ where
         &mut self,
         events: Vec<Event>,
     ) -> Result<Vec<Event>, Self::Error> {
-        self.print_wt_sum(&events);
+        use ResamplingError::*;
 
-        let nneg_weight = events.iter().filter(|e| e.weight() < 0.).count();
+        self.print_wt_sum(&events);
 
         let max_cell_size = n64(self.max_cell_size.unwrap_or(f64::MAX));
 
@@ -88,21 +108,71 @@ where
             max_cell_size,
         );
 
+        let seeds: Vec<_> = self.seeds.select_seeds(&events).collect();
+        let mut nseeds_done = 0;
+        if let Some(checkpointing) = &self.checkpointing {
+            let file = &checkpointing.file;
+            if checkpointing.resume && file.exists() {
+                nseeds_done = checkpoint::resume(
+                    file,
+                    checkpointing.settings,
+                    &events,
+                    seeds.len(),
+                )
+                .map_err(|err| ResumeErr(file.clone(), err))?;
+            }
+        }
+
+        let nneg_weight = events.iter().filter(|e| e.weight() < 0.).count();
         info!("Resampling {nneg_weight} cells");
         let progress = ProgressBar::new(nneg_weight as u64, "events treated:");
-        let seeds = self.seeds.select_seeds(&events);
-        seeds.for_each(|seed| {
-            assert!(seed < events.len());
-            if events[seed].weight() > 0. {
-                return;
+        // when checkpointing, we have to process the seeds in chunks
+        let chunk_size = if self.checkpointing.is_some() {
+            CHECKPOINT_CHUNK_SIZE
+        } else {
+            std::cmp::max(seeds.len(), 1)
+        };
+        let mut last_checkpoint = Instant::now();
+        for chunk in seeds[nseeds_done..].chunks(chunk_size) {
+            chunk.par_iter().for_each(|&seed| {
+                assert!(seed < events.len());
+                if events[seed].weight() > 0. {
+                    return;
+                }
+                trace!("New cell around event {}", events[seed].id());
+                let mut cell =
+                    Cell::new(&events, seed, &self.distance, &neighbour_search);
+                cell.resample();
+                self.observer.observe_cell(&cell);
+                progress.inc(1);
+            });
+            nseeds_done += chunk.len();
+            if let Some(checkpointing) = &self.checkpointing {
+                if last_checkpoint.elapsed() >= checkpointing.interval {
+                    let file = &checkpointing.file;
+                    checkpoint::write(
+                        file,
+                        checkpointing.settings,
+                        &events,
+                        nseeds_done,
+                        seeds.len(),
+                    )
+                    .map_err(|err| CheckpointErr(file.clone(), err))?;
+                    last_checkpoint = Instant::now();
+                }
             }
-            trace!("New cell around event {}", events[seed].id());
-            let mut cell =
-                Cell::new(&events, seed, &self.distance, &neighbour_search);
-            cell.resample();
-            self.observer.observe_cell(&cell);
-            progress.inc(1);
-        });
+        }
+        if let Some(checkpointing) = &self.checkpointing {
+            let file = &checkpointing.file;
+            checkpoint::write(
+                file,
+                checkpointing.settings,
+                &events,
+                nseeds_done,
+                seeds.len(),
+            )
+            .map_err(|err| CheckpointErr(file.clone(), err))?;
+        }
         progress.finish();
         debug!("Combining cell observations");
         self.observer.finish();
@@ -110,6 +180,93 @@ where
         debug!("Resampling done");
         Ok(events)
     }
+
+    fn cell_summary(&self) -> Option<CellSummary> {
+        self.observer.cell_summary()
+    }
+}
+
+impl<D, N, O, S, T> Resampler<D, N, O, S>
+where
+    D: Distance + Send + Sync,
+    N: NeighbourData + Clone + Send + Sync,
+    for<'x, 'y, 'z> &'x N: NeighbourSearch<DistWrapper<'y, 'z, D>>,
+    for<'x, 'y, 'z> <&'x N as NeighbourSearch<DistWrapper<'y, 'z, D>>>::Iter:
+        Iterator<Item = (usize, N64)>,
+    S: SelectSeeds<ParallelIter = T> + Send + Sync,
+    T: ParallelIterator<Item = usize>,
+{
+    /// Resample separately with each of the given maximum cell sizes
+    ///
+    /// In contrast to calling [Resample::resample] repeatedly, the
+    /// nearest-neighbour search is only initialised once. The
+    /// original event weights are restored after each pass, so the
+    /// `events` are unchanged in the end. The maximum cell size set
+    /// in the builder, the observer, and checkpointing are ignored.
+    pub fn scan_max_cell_size(
+        &self,
+        events: &[Event],
+        max_cell_sizes: &[f64],
+    ) -> Vec<CellSizeScanPoint> {
+        self.print_wt_sum(events);
+
+        let largest = max_cell_sizes.iter().copied().fold(0., f64::max);
+        info!("Initialising nearest-neighbour search");
+        let neighbour_search = N::new_with_dist(
+            events.len(),
+            DistWrapper::new(&self.distance, events),
+            n64(largest),
+        );
+
+        let seeds: Vec<_> = self.seeds.select_seeds(events).collect();
+        let weights: Vec<_> =
+            events.iter().map(|e| e.weights.read().to_owned()).collect();
+        let nneg_weight = events.iter().filter(|e| e.weight() < 0.).count();
+        let distance = &self.distance;
+        max_cell_sizes
+            .iter()
+            .map(|&max_cell_size| {
+                info!("Resampling with maximum cell size {max_cell_size}");
+                let max_radius = n64(max_cell_size);
+                let progress =
+                    ProgressBar::new(nneg_weight as u64, "events treated:");
+                let cells: Vec<_> = seeds
+                    .par_iter()
+                    .filter_map(|&seed| {
+                        if events[seed].weight() > 0. {
+                            return None;
+                        }
+                        let mut cell = Cell::with_max_radius(
+                            events,
+                            seed,
+                            distance,
+                            &neighbour_search,
+                            max_radius,
+                        );
+                        cell.resample();
+                        progress.inc(1);
+                        Some((cell.radius(), cell.weight_sum() < 0.))
+                    })
+                    .collect();
+                progress.finish();
+
+                let nneg_weight = cells.iter().filter(|c| c.1).count();
+                let mut radii: Vec<_> =
+                    cells.into_iter().map(|c| c.0).collect();
+                let summary = SampleSummary::new(events);
+                for (event, wt) in events.iter().zip(&weights) {
+                    event.weights.write().clone_from(wt);
+                }
+                CellSizeScanPoint {
+                    max_cell_size,
+                    neg_weight_fraction: summary.neg_weight_fraction,
+                    ncells: radii.len(),
+                    nneg_weight,
+                    median_radius: RadiusQuantiles::new(&mut radii).median,
+                }
+            })
+            .collect()
+    }
 }
 
 /// Construct a `Resampler` object
@@ -119,6 +276,7 @@ pub struct ResamplerBuilder<D, O, S, N = TreeSearch> {
     neighbour_search: PhantomData<N>,
     observer: O,
     max_cell_size: Option<f64>,
+    checkpointing: Option<Checkpointing>,
 }
 
 impl<D, O, S, N> ResamplerBuilder<D, O, S, N> {
@@ -130,6 +288,7 @@ impl<D, O, S, N> ResamplerBuilder<D, O, S, N> {
             neighbour_search: PhantomData,
             observer: self.observer,
             max_cell_size: self.max_cell_size,
+            checkpointing: self.checkpointing,
         }
     }
 
@@ -145,6 +304,7 @@ impl<D, O, S, N> ResamplerBuilder<D, O, S, N> {
             neighbour_search: PhantomData,
             observer: self.observer,
             max_cell_size: self.max_cell_size,
+            checkpointing: self.checkpointing,
         }
     }
 
@@ -159,6 +319,7 @@ impl<D, O, S, N> ResamplerBuilder<D, O, S, N> {
             neighbour_search: PhantomData,
             observer: self.observer,
             max_cell_size: self.max_cell_size,
+            checkpointing: self.checkpointing,
         }
     }
 
@@ -173,6 +334,7 @@ impl<D, O, S, N> ResamplerBuilder<D, O, S, N> {
             neighbour_search: PhantomData,
             observer,
             max_cell_size: self.max_cell_size,
+            checkpointing: self.checkpointing,
         }
     }
 
@@ -190,6 +352,7 @@ impl<D, O, S, N> ResamplerBuilder<D, O, S, N> {
             neighbour_search: PhantomData,
             observer: self.observer,
             max_cell_size: self.max_cell_size,
+            checkpointing: self.checkpointing,
         }
     }
 
@@ -205,6 +368,19 @@ impl<D, O, S, N> ResamplerBuilder<D, O, S, N> {
             ..self
         }
     }
+
+    /// Periodically save the resampling progress
+    ///
+    /// The default is `None`, meaning no checkpoints are written.
+    pub fn checkpointing(
+        self,
+        checkpointing: Option<Checkpointing>,
+    ) -> ResamplerBuilder<D, O, S, N> {
+        ResamplerBuilder {
+            checkpointing,
+            ..self
+        }
+    }
 }
 
 impl Default
@@ -222,25 +398,31 @@ impl Default
             neighbour_search: PhantomData,
             observer: Default::default(),
             max_cell_size: Default::default(),
+            checkpointing: Default::default(),
         }
     }
 }
 
 /// Default implementation of cell resampling
-pub struct DefaultResampler<N = TreeSearch> {
-    ptweight: f64,
+pub struct DefaultResampler<N = TreeSearch, D = EuclWithScaledPt> {
+    distance: D,
     strategy: Strategy,
     max_cell_size: Option<f64>,
     cell_collector: Option<Rc<RefCell<CellCollector>>>,
+    cell_ids: Option<Rc<RefCell<HashMap<usize, usize>>>>,
+    checkpointing: Option<Checkpointing>,
+    metric_check: Option<MetricCheck>,
     neighbour_search: PhantomData<N>,
+    cell_summary: Option<CellSummary>,
+    metric_report: Option<MetricReport>,
 }
 
-impl<N> Resample for DefaultResampler<N>
+impl<N, D> Resample for DefaultResampler<N, D>
 where
+    D: Distance + Clone + Send + Sync,
     N: NeighbourData + Clone + Send + Sync,
-    for<'x, 'y, 'z> &'x N:
-        NeighbourSearch<DistWrapper<'y, 'z, EuclWithScaledPt>>,
-    for<'x, 'y, 'z> <&'x N as NeighbourSearch<DistWrapper<'y, 'z, EuclWithScaledPt>>>::Iter:
+    for<'x, 'y, 'z> &'x N: NeighbourSearch<DistWrapper<'y, 'z, D>>,
+    for<'x, 'y, 'z> <&'x N as NeighbourSearch<DistWrapper<'y, 'z, D>>>::Iter:
         Iterator<Item = (usize, N64)>,
 {
     type Error = ResamplingError;
@@ -249,69 +431,166 @@ where
         &mut self,
         events: Vec<Event>,
     ) -> Result<Vec<Event>, Self::Error> {
+        if let Some(check) = self.metric_check {
+            let report = check.check(&self.distance, &events);
+            report.log();
+            let fallback = check.naive_search_fallback && !report.passed();
+            self.metric_report = Some(report);
+            if fallback {
+                warn!("Falling back to naive nearest neighbour search");
+                return self.resample_with::<NaiveNeighbourSearch>(events);
+            }
+        }
+        self.resample_with::<N>(events)
+    }
+
+    fn cell_summary(&self) -> Option<CellSummary> {
+        self.cell_summary
+    }
+}
+
+impl<N, D> DefaultResampler<N, D>
+where
+    D: Distance + Clone + Send + Sync,
+{
+    fn resample_with<NN>(
+        &mut self,
+        events: Vec<Event>,
+    ) -> Result<Vec<Event>, ResamplingError>
+    where
+        NN: NeighbourData + Clone + Send + Sync,
+        for<'x, 'y, 'z> &'x NN: NeighbourSearch<DistWrapper<'y, 'z, D>>,
+        for<'x, 'y, 'z> <&'x NN as NeighbourSearch<DistWrapper<'y, 'z, D>>>::Iter:
+            Iterator<Item = (usize, N64)>,
+    {
         let observer_data = ObserverData {
             cell_collector: self
                 .cell_collector
                 .clone()
                 .map(|c| c.borrow().clone()),
+            cell_ids: self.cell_ids.as_ref().map(|_| HashMap::new()),
             ..Default::default()
         };
         let observer = Observer {
             central: observer_data,
-            threaded: Default::default(),
+            ..Default::default()
         };
 
         let mut resampler = ResamplerBuilder::default()
             .seeds(StrategicSelector::new(self.strategy))
-            .distance(EuclWithScaledPt::new(n64(self.ptweight)))
+            .distance(self.distance.clone())
             .max_cell_size(self.max_cell_size)
+            .checkpointing(self.checkpointing())
             .observer(observer)
-            .neighbour_search::<N>()
+            .neighbour_search::<NN>()
             .build();
         let events = crate::traits::Resample::resample(&mut resampler, events)?;
+        self.cell_summary = resampler.cell_summary();
 
+        let central = resampler.observer.central;
         if let Some(c) = self.cell_collector.as_mut() {
-            c.replace(resampler.observer.central.cell_collector.unwrap());
+            c.replace(central.cell_collector.unwrap());
+        }
+        if let Some(c) = self.cell_ids.as_mut() {
+            c.replace(central.cell_ids.unwrap());
         }
         Ok(events)
     }
 }
 
-impl<N> DefaultResampler<N> {
+impl<N, D> DefaultResampler<N, D>
+where
+    D: Distance + Clone + Send + Sync,
+    N: NeighbourData + Clone + Send + Sync,
+    for<'x, 'y, 'z> &'x N: NeighbourSearch<DistWrapper<'y, 'z, D>>,
+    for<'x, 'y, 'z> <&'x N as NeighbourSearch<DistWrapper<'y, 'z, D>>>::Iter:
+        Iterator<Item = (usize, N64)>,
+{
+    /// Resample separately with each of the given maximum cell sizes
+    ///
+    /// See [Resampler::scan_max_cell_size].
+    pub fn scan_max_cell_size(
+        &self,
+        events: &[Event],
+        max_cell_sizes: &[f64],
+    ) -> Vec<CellSizeScanPoint> {
+        ResamplerBuilder::default()
+            .seeds(StrategicSelector::new(self.strategy))
+            .distance(self.distance.clone())
+            .neighbour_search::<N>()
+            .build()
+            .scan_max_cell_size(events, max_cell_sizes)
+    }
+}
+
+impl<N, D> DefaultResampler<N, D> {
+    // include the seed strategy and the maximum cell size in the hash
+    // of the checkpointing settings
+    fn checkpointing(&self) -> Option<Checkpointing> {
+        let mut checkpointing = self.checkpointing.clone()?;
+        let max_cell_size = self.max_cell_size.map(f64::to_bits);
+        checkpointing.settings = checkpoint::settings_hash(&(
+            checkpointing.settings,
+            self.strategy,
+            max_cell_size,
+        ));
+        Some(checkpointing)
+    }
+
     /// Get the callback upon cell construction
     pub fn cell_collector(&self) -> Option<Rc<RefCell<CellCollector>>> {
         self.cell_collector.as_ref().cloned()
     }
+
+    /// Get the map from event ids to cell ids
+    pub fn cell_ids(&self) -> Option<Rc<RefCell<HashMap<usize, usize>>>> {
+        self.cell_ids.as_ref().cloned()
+    }
+
+    /// Get the result of the last check of the metric properties
+    ///
+    /// See [DefaultResamplerBuilder::metric_check].
+    pub fn metric_report(&self) -> Option<&MetricReport> {
+        self.metric_report.as_ref()
+    }
 }
 
 /// Build a [DefaultResampler]
-pub struct DefaultResamplerBuilder<N> {
-    ptweight: f64,
+pub struct DefaultResamplerBuilder<N, D = EuclWithScaledPt> {
+    distance: D,
     strategy: Strategy,
     max_cell_size: Option<f64>,
     cell_collector: Option<Rc<RefCell<CellCollector>>>,
+    cell_ids: Option<Rc<RefCell<HashMap<usize, usize>>>>,
+    checkpointing: Option<Checkpointing>,
+    metric_check: Option<MetricCheck>,
     neighbour_search: PhantomData<N>,
 }
 
 impl Default for DefaultResamplerBuilder<TreeSearch> {
     fn default() -> Self {
         Self {
-            ptweight: 0.,
+            distance: Default::default(),
             strategy: Strategy::default(),
             max_cell_size: None,
             cell_collector: None,
+            cell_ids: None,
+            checkpointing: None,
+            metric_check: None,
             neighbour_search: PhantomData,
         }
     }
 }
 
-impl<N> DefaultResamplerBuilder<N> {
+impl<N> DefaultResamplerBuilder<N, EuclWithScaledPt> {
     /// Set the τ factor in the distance measure
     pub fn ptweight(mut self, value: f64) -> Self {
-        self.ptweight = value;
+        self.distance = EuclWithScaledPt::new(n64(value));
         self
     }
+}
 
+impl<N, D> DefaultResamplerBuilder<N, D> {
     /// Set the strategy for selecting cell seeds
     pub fn strategy(mut self, value: Strategy) -> Self {
         self.strategy = value;
@@ -333,45 +612,97 @@ impl<N> DefaultResamplerBuilder<N> {
         self
     }
 
+    /// Record the cell id for each event that is part of a cell
+    ///
+    /// Cells are numbered consecutively, starting from zero. If an
+    /// event is part of several cells, one of them is recorded.
+    pub fn cell_ids(
+        mut self,
+        value: Option<Rc<RefCell<HashMap<usize, usize>>>>,
+    ) -> Self {
+        self.cell_ids = value;
+        self
+    }
+
+    /// Periodically save the resampling progress
+    pub fn checkpointing(mut self, value: Option<Checkpointing>) -> Self {
+        self.checkpointing = value;
+        self
+    }
+
+    /// Check the metric properties of the distance before resampling
+    ///
+    /// Violations are logged and the result can be retrieved with
+    /// [DefaultResampler::metric_report].
+    pub fn metric_check(mut self, value: Option<MetricCheck>) -> Self {
+        self.metric_check = value;
+        self
+    }
+
+    /// Set the distance function
+    ///
+    /// The default is [EuclWithScaledPt], with τ set by
+    /// [ptweight](DefaultResamplerBuilder::ptweight).
+    pub fn distance<DD>(self, distance: DD) -> DefaultResamplerBuilder<N, DD>
+    where
+        DD: Distance + Clone + Send + Sync,
+    {
+        DefaultResamplerBuilder {
+            distance,
+            strategy: self.strategy,
+            max_cell_size: self.max_cell_size,
+            cell_collector: self.cell_collector,
+            cell_ids: self.cell_ids,
+            checkpointing: self.checkpointing,
+            metric_check: self.metric_check,
+            neighbour_search: PhantomData,
+        }
+    }
+
     /// Set the nearest neighbour search algorithm
-    pub fn neighbour_search<NN>(self) -> DefaultResamplerBuilder<NN>
+    pub fn neighbour_search<NN>(self) -> DefaultResamplerBuilder<NN, D>
     where
+        D: Distance,
         NN: NeighbourData,
-        for<'x, 'y, 'z> &'x NN:
-            NeighbourSearch<DistWrapper<'y, 'z, EuclWithScaledPt>>,
-        for<'x, 'y, 'z> <&'x NN as NeighbourSearch<DistWrapper<'y, 'z, EuclWithScaledPt>>>::Iter:
+        for<'x, 'y, 'z> &'x NN: NeighbourSearch<DistWrapper<'y, 'z, D>>,
+        for<'x, 'y, 'z> <&'x NN as NeighbourSearch<DistWrapper<'y, 'z, D>>>::Iter:
             Iterator<Item = (usize, N64)>,
     {
         DefaultResamplerBuilder {
-            ptweight: self.ptweight,
+            distance: self.distance,
             strategy: self.strategy,
             max_cell_size: self.max_cell_size,
             cell_collector: self.cell_collector,
+            cell_ids: self.cell_ids,
+            checkpointing: self.checkpointing,
+            metric_check: self.metric_check,
             neighbour_search: PhantomData,
         }
     }
 
     /// Build a [DefaultResampler]
-    pub fn build(self) -> DefaultResampler<N> {
+    pub fn build(self) -> DefaultResampler<N, D> {
         DefaultResampler {
-            ptweight: self.ptweight,
+            distance: self.distance,
             strategy: self.strategy,
             max_cell_size: self.max_cell_size,
             cell_collector: self.cell_collector,
+            cell_ids: self.cell_ids,
+            checkpointing: self.checkpointing,
+            metric_check: self.metric_check,
             neighbour_search: PhantomData,
+            cell_summary: None,
+            metric_report: None,
         }
     }
 }
 
-fn median_radius(radii: &mut [N64]) -> N64 {
-    radii.sort_unstable();
-    radii[radii.len() / 2]
-}
-
 #[derive(Debug, Default)]
 struct Observer {
     central: ObserverData,
     threaded: ThreadLocal<RefCell<ObserverData>>,
+    ncells: AtomicUsize,
+    summary: Option<CellSummary>,
 }
 
 #[derive(Clone, Debug)]
@@ -379,6 +710,7 @@ struct ObserverData {
     cell_radii: Vec<N64>,
     rng: Xoshiro256Plus,
     cell_collector: Option<CellCollector>,
+    cell_ids: Option<HashMap<usize, usize>>,
     nneg: u64,
 }
 
@@ -388,6 +720,7 @@ impl std::default::Default for ObserverData {
             cell_radii: Vec::new(),
             rng: Xoshiro256Plus::seed_from_u64(0),
             cell_collector: None,
+            cell_ids: None,
             nneg: 0,
         }
     }
@@ -414,6 +747,10 @@ impl ObserveCell for Observer {
             c.collect(cell, &mut data.rng)
         }
         data.cell_collector = cell_collector;
+        if let Some(cell_ids) = &mut data.cell_ids {
+            let id = self.ncells.fetch_add(1, Ordering::Relaxed);
+            cell_ids.extend(cell.iter().map(|e| (e.id(), id)));
+        }
     }
 
     fn finish(&mut self) {
@@ -427,14 +764,21 @@ impl ObserveCell for Observer {
             if res.nneg > 0 {
                 warn!("{} cells had negative weight!", res.nneg);
             }
-            info!(
-                "Median radius: {:.3}",
-                median_radius(res.cell_radii.as_mut_slice())
-            );
+            let radius = RadiusQuantiles::new(res.cell_radii.as_mut_slice());
+            info!("Median radius: {:.3}", radius.median);
             res.cell_collector.as_ref().map(|c| c.dump_info());
+            self.summary = Some(CellSummary {
+                ncells: res.cell_radii.len(),
+                nneg_weight: res.nneg as usize,
+                radius,
+            });
             self.central = res;
         }
     }
+
+    fn cell_summary(&self) -> Option<CellSummary> {
+        self.summary
+    }
 }
 
 impl ObserverData {
@@ -448,6 +792,16 @@ impl ObserverData {
             (None, Some(c)) => Some(c),
             (None, None) => None,
         };
+        self.cell_ids = match (self.cell_ids, other.cell_ids) {
+            (Some(mut c1), Some(c2)) => {
+                for (event, cell) in c2 {
+                    let entry = c1.entry(event).or_insert(cell);
+                    *entry = std::cmp::max(*entry, cell);
+                }
+                Some(c1)
+            }
+            (c1, c2) => c1.or(c2),
+        };
         self
     }
 }
@@ -20,7 +20,7 @@ pub trait SelectSeeds {
 }
 
 /// Strategy for seeds selection
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum Strategy {
     /// Select events with the negative weight closest to zero first
     LeastNegative,
@@ -0,0 +1,680 @@
+//! Weights-only output
+//!
+//! Instead of writing out the full events with updated weights, a
+//! [SidecarWriter] only writes the new weights together with the
+//! index of each event in the original input. For each event, a
+//! sidecar record contains
+//!
+//! 1. The event index, counting from zero.
+//! 2. The original central weight.
+//! 3. The new central weight.
+//! 4. The new values of additional named weights (with the
+//!    `multiweight` feature).
+//! 5. Optionally, the id of a cell containing the event.
+//!
+//! Events discarded during unweighting are not listed.
+//!
+//! The sidecar can later be merged with the original input using the
+//! `cres-apply` executable, or with a [SidecarReader]. A
+//! [CheckedReader] ensures that the input matches the sidecar.
+//!
+//! # Formats
+//!
+//! - [SidecarFormat::Csv] writes a comma-separated table with a
+//!   header line `id,original_weight,weight,NAMES...,cell`, where
+//!   `NAMES...` are the names of the additional weights and the
+//!   `cell` column is only present if cell ids are written.
+//! - [SidecarFormat::Binary] writes the 8 bytes `CRESSIDE`, followed
+//!   by a header and the records. All numbers are in little-endian
+//!   byte order. The header consists of the format version (`u64`),
+//!   the number of additional weights (`u64`), the names of the
+//!   additional weights (each as the length in bytes as `u64` followed
+//!   by the UTF-8 encoded name), and a single byte that is 1 if cell
+//!   ids are written and 0 otherwise. Each record consists of the
+//!   event index (`u64`), the original weight (`f64`), the new
+//!   central weight (`f64`), the new additional weights (`f64`), and
+//!   the cell id (`u64`, only present if cell ids are written). A
+//!   cell id of `u64::MAX` means that the event does not belong to
+//!   any cell.
+#[cfg(feature = "multiweight")]
+use std::collections::HashSet;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use strum::{Display, EnumString};
+use thiserror::Error;
+use typed_builder::TypedBuilder;
+
+use crate::{
+    event::Event,
+    progress_bar::{Progress, ProgressBar},
+    traits::{SourceIndex, Write},
+    writer::EventWriteError,
+};
+
+const MAGIC: &[u8; 8] = b"CRESSIDE";
+const VERSION: u64 = 1;
+const NO_CELL: u64 = u64::MAX;
+
+/// Sidecar output format
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Display,
+    EnumString,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum SidecarFormat {
+    /// Comma-separated values
+    #[default]
+    Csv,
+    /// Compact binary format
+    Binary,
+}
+
+/// Writer for sidecar files with event weights
+///
+/// See the [module documentation](crate::sidecar) for details.
+#[derive(Debug, TypedBuilder)]
+pub struct SidecarWriter {
+    filename: PathBuf,
+    #[builder(default)]
+    format: SidecarFormat,
+    /// Map from event ids to cell ids
+    ///
+    /// This is usually shared with a
+    /// [DefaultResampler](crate::resampler::DefaultResampler).
+    #[builder(default)]
+    cell_ids: Option<Rc<RefCell<HashMap<usize, usize>>>>,
+    /// Names of the weights that have been resampled in addition to
+    /// the central weight
+    #[cfg(feature = "multiweight")]
+    #[builder(default)]
+    overwrite_weights: HashSet<String>,
+}
+
+impl SidecarWriter {
+    fn weight_names(&self, _event: &avery::Event) -> Vec<String> {
+        #[cfg(feature = "multiweight")]
+        return _event
+            .weights
+            .iter()
+            .filter_map(|wt| wt.name.as_ref())
+            .filter(|name| self.overwrite_weights.contains(*name))
+            .cloned()
+            .collect();
+        #[cfg(not(feature = "multiweight"))]
+        Vec::new()
+    }
+}
+
+impl<R, RE> Write<R> for SidecarWriter
+where
+    R: Iterator<Item = Result<avery::Event, RE>>,
+    RE: std::error::Error,
+{
+    type Error = EventWriteError<RE, io::Error>;
+
+    fn write(
+        &mut self,
+        r: &mut R,
+        events: &[Event],
+    ) -> Result<(), Self::Error> {
+        use EventWriteError::*;
+
+        let out = File::create(&self.filename).map_err(CreateErr)?;
+        let mut out = Some(BufWriter::new(out));
+        let cell_ids = self.cell_ids.as_ref().map(|c| c.borrow());
+        let has_cells = cell_ids.is_some();
+
+        let mut writer = None;
+        let mut reader_events = r.enumerate();
+        let progress = ProgressBar::new(events.len() as u64, "events written:");
+        for event in events {
+            let (mut read_id, mut read_event) = reader_events.next().unwrap();
+            while read_id < event.id() {
+                (read_id, read_event) = reader_events.next().unwrap();
+            }
+            let read_event = read_event.map_err(ReadErr)?;
+            if writer.is_none() {
+                let names = self.weight_names(&read_event);
+                let w = RecordWriter::new(
+                    out.take().unwrap(),
+                    self.format,
+                    &names,
+                    has_cells,
+                );
+                writer = Some(w.map_err(WriteErr)?);
+            }
+            let original_weight =
+                read_event.weights.first().and_then(|wt| wt.weight);
+            let Some(original_weight) = original_weight else {
+                let err = SidecarError::MissingWeightErr(read_id);
+                let err = io::Error::new(io::ErrorKind::InvalidData, err);
+                return Err(WriteErr(err));
+            };
+            #[cfg(feature = "multiweight")]
+            let weights =
+                event.weights.read().iter().map(|&w| w.into()).collect();
+            #[cfg(not(feature = "multiweight"))]
+            let weights = vec![event.weight().into()];
+            let cell =
+                cell_ids.as_ref().and_then(|c| c.get(&event.id()).copied());
+            let record = SidecarRecord {
+                id: event.id(),
+                original_weight,
+                weights,
+                cell,
+            };
+            writer.as_mut().unwrap().write(&record).map_err(WriteErr)?;
+            progress.inc(1);
+        }
+        let writer = match writer {
+            Some(writer) => writer,
+            None => {
+                let out = out.take().unwrap();
+                RecordWriter::new(out, self.format, &[], has_cells)
+                    .map_err(WriteErr)?
+            }
+        };
+        writer.finish().map_err(WriteErr)?;
+        progress.finish();
+        Ok(())
+    }
+}
+
+/// A single entry in a sidecar file
+#[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+pub struct SidecarRecord {
+    /// Index of the event in the original input, counting from zero
+    pub id: usize,
+    /// Original central event weight
+    pub original_weight: f64,
+    /// New event weights, starting with the central weight
+    pub weights: Vec<f64>,
+    /// Id of a cell containing the event
+    ///
+    /// If the event is part of several cells, this is one of them.
+    pub cell: Option<usize>,
+}
+
+enum RecordWriter<W: io::Write> {
+    Csv(Box<csv::Writer<W>>, bool),
+    Binary(W, bool),
+}
+
+impl<W: io::Write> RecordWriter<W> {
+    fn new(
+        mut out: W,
+        format: SidecarFormat,
+        names: &[String],
+        has_cells: bool,
+    ) -> Result<Self, io::Error> {
+        match format {
+            SidecarFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(out);
+                let mut header = vec!["id", "original_weight", "weight"];
+                header.extend(names.iter().map(|n| n.as_str()));
+                if has_cells {
+                    header.push("cell");
+                }
+                writer.write_record(header)?;
+                Ok(Self::Csv(Box::new(writer), has_cells))
+            }
+            SidecarFormat::Binary => {
+                out.write_all(MAGIC)?;
+                out.write_all(&VERSION.to_le_bytes())?;
+                out.write_all(&(names.len() as u64).to_le_bytes())?;
+                for name in names {
+                    out.write_all(&(name.len() as u64).to_le_bytes())?;
+                    out.write_all(name.as_bytes())?;
+                }
+                out.write_all(&[has_cells as u8])?;
+                Ok(Self::Binary(out, has_cells))
+            }
+        }
+    }
+
+    fn write(&mut self, record: &SidecarRecord) -> Result<(), io::Error> {
+        match self {
+            Self::Csv(writer, has_cells) => {
+                writer.write_field(record.id.to_string())?;
+                writer.write_field(record.original_weight.to_string())?;
+                for wt in &record.weights {
+                    writer.write_field(wt.to_string())?;
+                }
+                if *has_cells {
+                    let cell = record.cell.map(|c| c.to_string());
+                    writer.write_field(cell.unwrap_or_default())?;
+                }
+                writer.write_record(None::<&[u8]>)?;
+            }
+            Self::Binary(out, has_cells) => {
+                out.write_all(&(record.id as u64).to_le_bytes())?;
+                out.write_all(&record.original_weight.to_le_bytes())?;
+                for wt in &record.weights {
+                    out.write_all(&wt.to_le_bytes())?;
+                }
+                if *has_cells {
+                    let cell = record.cell.map(|c| c as u64).unwrap_or(NO_CELL);
+                    out.write_all(&cell.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), io::Error> {
+        match self {
+            Self::Csv(mut writer, _) => writer.flush(),
+            Self::Binary(mut out, _) => out.flush(),
+        }
+    }
+}
+
+/// Error reading a sidecar file
+#[derive(Debug, Error)]
+pub enum SidecarError {
+    /// I/O error
+    #[error("I/O error")]
+    IoErr(#[from] io::Error),
+    /// Error in CSV file
+    #[error("Failed to parse CSV record")]
+    CsvErr(#[from] csv::Error),
+    /// Invalid file content
+    #[error("Invalid sidecar file: {0}")]
+    FormatErr(String),
+    /// Input event without central weight
+    #[error("Event {0} in the input has no central weight")]
+    MissingWeightErr(usize),
+}
+
+/// Reader for sidecar files
+///
+/// The format is detected automatically.
+pub struct SidecarReader {
+    weight_names: Vec<String>,
+    has_cells: bool,
+    records: RecordReader,
+}
+
+enum RecordReader {
+    Csv(csv::StringRecordsIntoIter<BufReader<File>>),
+    Binary(BufReader<File>),
+}
+
+impl SidecarReader {
+    /// Open a sidecar file
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, SidecarError> {
+        let mut input = BufReader::new(File::open(path)?);
+        if input.fill_buf()?.starts_with(MAGIC) {
+            Self::new_binary(input)
+        } else {
+            Self::new_csv(input)
+        }
+    }
+
+    fn new_binary(mut input: BufReader<File>) -> Result<Self, SidecarError> {
+        use SidecarError::FormatErr;
+
+        let mut magic = [0u8; MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        let version = read_u64(&mut input)?;
+        if version != VERSION {
+            return Err(FormatErr(format!("unsupported version {version}")));
+        }
+        let nnames = read_u64(&mut input)?;
+        let mut weight_names = Vec::new();
+        for _ in 0..nnames {
+            let len = read_u64(&mut input)? as usize;
+            let mut name = vec![0u8; len];
+            input.read_exact(&mut name)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| FormatErr("weight name is not UTF-8".into()))?;
+            weight_names.push(name);
+        }
+        let mut has_cells = [0u8];
+        input.read_exact(&mut has_cells)?;
+        Ok(Self {
+            weight_names,
+            has_cells: has_cells[0] != 0,
+            records: RecordReader::Binary(input),
+        })
+    }
+
+    fn new_csv(input: BufReader<File>) -> Result<Self, SidecarError> {
+        let mut reader = csv::Reader::from_reader(input);
+        let header = reader.headers()?;
+        let expected = ["id", "original_weight", "weight"];
+        if header.len() < expected.len()
+            || header.iter().zip(expected).any(|(h, e)| h != e)
+        {
+            return Err(SidecarError::FormatErr(format!(
+                "unexpected CSV header {header:?}"
+            )));
+        }
+        let has_cells = header.iter().next_back() == Some("cell");
+        let nend = header.len() - has_cells as usize;
+        let weight_names = header
+            .iter()
+            .take(nend)
+            .skip(expected.len())
+            .map(|n| n.to_owned())
+            .collect();
+        Ok(Self {
+            weight_names,
+            has_cells,
+            records: RecordReader::Csv(reader.into_records()),
+        })
+    }
+
+    /// Names of the weights in addition to the central weight
+    pub fn weight_names(&self) -> &[String] {
+        &self.weight_names
+    }
+
+    /// Whether the sidecar contains cell ids
+    pub fn has_cells(&self) -> bool {
+        self.has_cells
+    }
+
+    fn next_binary(&mut self) -> Option<Result<SidecarRecord, SidecarError>> {
+        let RecordReader::Binary(input) = &mut self.records else {
+            unreachable!()
+        };
+        match input.fill_buf() {
+            Ok([]) => return None,
+            Err(err) => return Some(Err(err.into())),
+            _ => {}
+        }
+        let nweights = 1 + self.weight_names.len();
+        let has_cells = self.has_cells;
+        let mut read_record = || -> Result<SidecarRecord, SidecarError> {
+            let id = read_u64(input)? as usize;
+            let original_weight = read_f64(input)?;
+            let weights = (0..nweights)
+                .map(|_| read_f64(input))
+                .collect::<Result<_, _>>()?;
+            let cell = if has_cells {
+                Some(read_u64(input)?).filter(|&c| c != NO_CELL)
+            } else {
+                None
+            };
+            Ok(SidecarRecord {
+                id,
+                original_weight,
+                weights,
+                cell: cell.map(|c| c as usize),
+            })
+        };
+        Some(read_record())
+    }
+
+    fn next_csv(&mut self) -> Option<Result<SidecarRecord, SidecarError>> {
+        let RecordReader::Csv(records) = &mut self.records else {
+            unreachable!()
+        };
+        let record = match records.next()? {
+            Ok(record) => record,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let has_cells = self.has_cells;
+        let parse_record = || -> Result<SidecarRecord, SidecarError> {
+            let bad_entry = |entry: &str| {
+                SidecarError::FormatErr(format!("invalid entry '{entry}'"))
+            };
+            let mut entries = record.iter();
+            let mut next = || {
+                entries.next().ok_or_else(|| {
+                    SidecarError::FormatErr("missing entry".into())
+                })
+            };
+            let id = next()?;
+            let id = id.parse().map_err(|_| bad_entry(id))?;
+            let wt = next()?;
+            let original_weight = wt.parse().map_err(|_| bad_entry(wt))?;
+            let nweights = record.len() - 2 - has_cells as usize;
+            let mut weights = Vec::with_capacity(nweights);
+            for _ in 0..nweights {
+                let wt = next()?;
+                weights.push(wt.parse().map_err(|_| bad_entry(wt))?);
+            }
+            let cell = if has_cells {
+                let cell = next()?;
+                if cell.is_empty() {
+                    None
+                } else {
+                    Some(cell.parse().map_err(|_| bad_entry(cell))?)
+                }
+            } else {
+                None
+            };
+            Ok(SidecarRecord {
+                id,
+                original_weight,
+                weights,
+                cell,
+            })
+        };
+        Some(parse_record())
+    }
+}
+
+impl Iterator for SidecarReader {
+    type Item = Result<SidecarRecord, SidecarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.records {
+            RecordReader::Csv(_) => self.next_csv(),
+            RecordReader::Binary(_) => self.next_binary(),
+        }
+    }
+}
+
+/// Reader checking the original input against sidecar records
+///
+/// Wraps a reader for the input events the sidecar was created
+/// from. Each input event listed in the sidecar has to have the
+/// recorded original central weight, otherwise the reader returns a
+/// [SidecarMatchError]. This catches sidecars that are applied to
+/// the wrong input files, or to input files in the wrong order.
+pub struct CheckedReader<R> {
+    reader: R,
+    records: std::iter::Peekable<std::vec::IntoIter<(usize, f64)>>,
+    read_id: usize,
+}
+
+impl<R> CheckedReader<R> {
+    /// Check the events from `reader` against the `records`
+    ///
+    /// The records have to be ordered by event index.
+    pub fn new(reader: R, records: &[SidecarRecord]) -> Self {
+        let records: Vec<_> =
+            records.iter().map(|r| (r.id, r.original_weight)).collect();
+        Self {
+            reader,
+            records: records.into_iter().peekable(),
+            read_id: 0,
+        }
+    }
+}
+
+impl<R, RE> Iterator for CheckedReader<R>
+where
+    R: Iterator<Item = Result<avery::Event, RE>>,
+{
+    type Item = Result<avery::Event, SidecarMatchError<RE>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(event) = self.reader.next() else {
+            let (id, _) = self.records.next()?;
+            return Some(Err(SidecarMatchError::MissingEventErr(id)));
+        };
+        let id = self.read_id;
+        self.read_id += 1;
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => return Some(Err(SidecarMatchError::ReadErr(err))),
+        };
+        let Some((_, expected)) = self.records.next_if(|(i, _)| *i == id)
+        else {
+            return Some(Ok(event));
+        };
+        let found = event.weights.first().and_then(|wt| wt.weight);
+        let tolerance = 1e-12 * expected.abs();
+        match found {
+            Some(found) if (found - expected).abs() <= tolerance => {
+                Some(Ok(event))
+            }
+            _ => Some(Err(SidecarMatchError::WeightMismatchErr {
+                id,
+                expected,
+                found,
+            })),
+        }
+    }
+}
+
+impl<R: SourceIndex> SourceIndex for CheckedReader<R> {
+    fn source_index(&self) -> usize {
+        self.reader.source_index()
+    }
+}
+
+/// Mismatch between sidecar records and input events
+#[derive(Debug, Error)]
+pub enum SidecarMatchError<E> {
+    /// Error reading an input event
+    #[error(transparent)]
+    ReadErr(E),
+    /// The input has fewer events than listed in the sidecar
+    #[error("Event {0} from the sidecar file is missing in the input")]
+    MissingEventErr(usize),
+    /// The original weight in the sidecar differs from the input
+    #[error("Event {id} has original weight {expected} in the sidecar file, but {found:?} in the input")]
+    WeightMismatchErr {
+        /// Event index
+        id: usize,
+        /// Original weight from the sidecar file
+        expected: f64,
+        /// Central weight of the input event
+        found: Option<f64>,
+    },
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64, io::Error> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f64(r: &mut impl Read) -> Result<f64, io::Error> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tst_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = [
+            SidecarRecord {
+                id: 0,
+                original_weight: -1.5,
+                weights: vec![0.5, 2.],
+                cell: Some(3),
+            },
+            SidecarRecord {
+                id: 2,
+                original_weight: 1.,
+                weights: vec![1., -0.25],
+                cell: None,
+            },
+        ];
+        let names = ["scale_up".to_owned()];
+        for format in [SidecarFormat::Csv, SidecarFormat::Binary] {
+            let file = dir.path().join(format.to_string());
+            let out = BufWriter::new(File::create(&file).unwrap());
+            let mut writer =
+                RecordWriter::new(out, format, &names, true).unwrap();
+            for record in &records {
+                writer.write(record).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let reader = SidecarReader::new(&file).unwrap();
+            assert_eq!(reader.weight_names(), names);
+            assert!(reader.has_cells());
+            let read: Vec<_> = reader.map(|r| r.unwrap()).collect();
+            assert_eq!(read, records);
+        }
+    }
+
+    #[test]
+    fn tst_missing_weight() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = SidecarWriter::builder()
+            .filename(dir.path().join("sidecar"))
+            .build();
+        let mut reader =
+            std::iter::once(Ok::<_, io::Error>(avery::Event::default()));
+        let res = writer.write(&mut reader, &[Event::default()]);
+        assert!(matches!(res, Err(EventWriteError::WriteErr(_))));
+    }
+
+    #[test]
+    fn tst_checked_reader() {
+        let input = || {
+            [1., 2., 3.].map(|weight| {
+                let weight = avery::event::WeightInfo {
+                    weight: Some(weight),
+                    ..Default::default()
+                };
+                Ok::<_, io::Error>(avery::Event {
+                    weights: vec![weight],
+                    ..Default::default()
+                })
+            })
+        };
+        let record = |id, original_weight| SidecarRecord {
+            id,
+            original_weight,
+            weights: vec![1.],
+            cell: None,
+        };
+
+        let records = [record(0, 1.), record(2, 3.)];
+        let reader = CheckedReader::new(input().into_iter(), &records);
+        assert!(reader.collect::<Result<Vec<_>, _>>().is_ok());
+
+        let records = [record(0, 1.), record(1, 3.)];
+        let mut reader = CheckedReader::new(input().into_iter(), &records);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(SidecarMatchError::WeightMismatchErr { id: 1, .. }))
+        ));
+
+        let records = [record(2, 3.), record(5, 1.)];
+        let reader = CheckedReader::new(input().into_iter(), &records);
+        let err = reader.collect::<Result<Vec<_>, _>>().unwrap_err();
+        assert!(matches!(err, SidecarMatchError::MissingEventErr(5)));
+    }
+}
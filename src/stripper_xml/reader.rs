@@ -4,7 +4,6 @@ use std::{
     fmt::{Debug, Display},
     io::{BufRead, BufReader, Error, ErrorKind, Read, Seek},
     num::ParseIntError,
-    path::{Path, PathBuf},
     str::Utf8Error,
 };
 
@@ -16,7 +15,9 @@ use thiserror::Error;
 
 use crate::{
     file::File,
-    reader::{CreateError, EventFileReader, EventReadError, RewindError},
+    reader::{
+        CreateError, EventFileReader, EventReadError, Input, RewindError,
+    },
     traits::{Rewind, TryClone},
     util::trim_ascii_start,
 };
@@ -307,22 +308,20 @@ pub enum ReadError {
     Io(#[from] std::io::Error),
 }
 
-pub(crate) fn extract_scaling<I, P>(
-    paths: I,
-) -> Result<(Vec<PathBuf>, HashMap<String, f64>), CreateError>
-where
-    I: IntoIterator<Item = P>,
-    P: AsRef<Path>,
-{
+pub(crate) fn extract_scaling(
+    inputs: Vec<Input>,
+) -> Result<(Vec<Input>, HashMap<String, f64>), CreateError> {
     let mut event_files = Vec::new();
     let mut rescale: HashMap<_, (f64, u64)> = HashMap::new();
-    for path in paths {
-        let path = path.as_ref();
-        let file = File::open(path)?;
+    for input in inputs {
+        // streams have already been spooled, so we can read them here
+        // and again later
+        let file = File::open(input.as_ref())?;
         let mut r = auto_decompress(BufReader::new(file));
         if let Ok(buf) = r.fill_buf() {
             let buf = trim_ascii_start(buf);
             if buf.starts_with(b"<?xml") {
+                let path = input.path();
                 debug!("extracting scaling information from {path:?}");
                 let tag = extract_xml_info(r).map_err(|err| {
                     crate::reader::CreateError::XMLError(path.to_owned(), err)
@@ -336,15 +335,15 @@ where
                     XMLTag::Eventrecord { name, nevents, .. } => {
                         let entry = rescale.entry(name).or_insert((-1., 0));
                         entry.1 += nevents;
-                        event_files.push(path.to_owned())
+                        event_files.push(input)
                     }
                 }
             } else {
                 // not a STRIPPER XML file
-                event_files.push(path.to_owned());
+                event_files.push(input);
             }
         } else {
-            event_files.push(path.to_owned())
+            event_files.push(input)
         }
     }
     let rescale = rescale
@@ -1,5 +1,6 @@
 use crate::cell::Cell;
 use crate::event::Event;
+use crate::report::CellSummary;
 
 pub use crate::distance::Distance;
 pub use crate::neighbour_search::{NeighbourData, NeighbourSearch};
@@ -24,6 +25,19 @@ pub trait TryConvert<From, To> {
 
     /// Convert between two types
     fn try_convert(&mut self, f: From) -> Result<To, Self::Error>;
+
+    /// Called after all conversions are done (optional)
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Whether converted events retain their particle momenta (optional)
+    ///
+    /// Events without momenta cannot be stored in an
+    /// [EventCache](crate::event_cache::EventCache).
+    fn keeps_momenta(&self) -> bool {
+        true
+    }
 }
 
 /// Resample events
@@ -33,6 +47,11 @@ pub trait Resample {
 
     /// Resample events
     fn resample(&mut self, e: Vec<Event>) -> Result<Vec<Event>, Self::Error>;
+
+    /// Statistics on the cells constructed in the last resampling (optional)
+    fn cell_summary(&self) -> Option<CellSummary> {
+        None
+    }
 }
 
 /// Unweight events
@@ -64,6 +83,14 @@ pub trait Write<Reader> {
         -> Result<(), Self::Error>;
 }
 
+impl<R, W: Write<R> + ?Sized> Write<R> for Box<W> {
+    type Error = W::Error;
+
+    fn write(&mut self, r: &mut R, e: &[Event]) -> Result<(), Self::Error> {
+        (**self).write(r, e)
+    }
+}
+
 /// Write a single event
 pub trait WriteEvent<Ev> {
     /// Write error
@@ -81,6 +108,16 @@ pub trait WriteEvent<Ev> {
     }
 }
 
+/// Index of the source an item was read from
+///
+/// This is implemented by readers combining several sources, like
+/// [CombinedReader](crate::reader::CombinedReader).
+pub trait SourceIndex {
+    /// Index of the source of the item returned by the last call to
+    /// [Iterator::next]
+    fn source_index(&self) -> usize;
+}
+
 /// Try to clone this object
 ///
 /// This trait is similar to [std::clone::Clone], but is allowed to fail.
@@ -111,6 +148,12 @@ pub trait ObserveCell {
     /// For example, this can be used to write out statistics.
     /// The default is to do nothing.
     fn finish(&mut self) {}
+    /// Statistics on the observed cells (optional)
+    ///
+    /// Only meaningful after [finish](ObserveCell::finish) has been called.
+    fn cell_summary(&self) -> Option<CellSummary> {
+        None
+    }
 }
 
 /// Progress indicator, e.g. a progress bar
@@ -0,0 +1,186 @@
+//! Solver for the transportation problem
+//!
+//! Finds the cheapest way to move the mass of a number of sources to a
+//! number of sinks with the same total mass, where moving a unit of
+//! mass from source `i` to sink `j` costs `cost(i, j)`. This uses
+//! successive shortest augmenting paths with Dijkstra's algorithm on
+//! the dense residual graph, which is fast for the small numbers of
+//! particles in an event.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use noisy_float::prelude::*;
+
+/// Minimum total cost of moving the mass from `supply` to `demand`
+///
+/// The total supply has to equal the total demand up to rounding
+/// errors. All costs have to be finite and non-negative.
+pub(crate) fn min_cost(
+    supply: &[f64],
+    demand: &[f64],
+    cost: impl Fn(usize, usize) -> f64,
+) -> f64 {
+    let (n, m) = (supply.len(), demand.len());
+    let total: f64 = supply.iter().sum();
+    // mass below this threshold is considered to be rounding errors
+    let eps = 1e-12 * total;
+    let cost: Vec<f64> = (0..n * m).map(|k| cost(k / m, k % m)).collect();
+    let mut supply = supply.to_vec();
+    let mut demand = demand.to_vec();
+    let mut flow = vec![0.; n * m];
+    // nodes are the sources followed by the sinks
+    // initialise the sink potentials to the column minima, so that
+    // the cheapest arcs into each sink have vanishing reduced cost
+    let mut potential = vec![0.; n + m];
+    for (j, p) in potential[n..].iter_mut().enumerate() {
+        *p = (0..n)
+            .map(|i| cost[i * m + j])
+            .fold(f64::INFINITY, f64::min);
+    }
+    let mut dist = vec![0.; n + m];
+    let mut prev = vec![usize::MAX; n + m];
+    let mut done = vec![false; n + m];
+    let mut queue = BinaryHeap::new();
+
+    while supply.iter().any(|&s| s > eps) {
+        // shortest path from any source with remaining supply to a
+        // sink with remaining demand, using the potentials to make
+        // all reduced costs non-negative
+        queue.clear();
+        for i in 0..n + m {
+            dist[i] = f64::INFINITY;
+            prev[i] = usize::MAX;
+            done[i] = false;
+            if i < n && supply[i] > eps {
+                dist[i] = 0.;
+                queue.push(Entry::new(0., i, n));
+            }
+        }
+        let mut sink = None;
+        while let Some(Entry { node, .. }) = queue.pop() {
+            if done[node] {
+                continue;
+            }
+            done[node] = true;
+            if node < n {
+                for j in 0..m {
+                    let reduced =
+                        cost[node * m + j] + potential[node] - potential[n + j];
+                    let d = dist[node] + reduced.max(0.);
+                    if d < dist[n + j] {
+                        dist[n + j] = d;
+                        prev[n + j] = node;
+                        queue.push(Entry::new(d, n + j, n));
+                    }
+                }
+            } else if demand[node - n] > eps {
+                sink = Some(node - n);
+                break;
+            } else {
+                let j = node - n;
+                for i in 0..n {
+                    if flow[i * m + j] <= eps {
+                        continue;
+                    }
+                    let reduced =
+                        potential[node] - cost[i * m + j] - potential[i];
+                    let d = dist[node] + reduced.max(0.);
+                    if d < dist[i] {
+                        dist[i] = d;
+                        prev[i] = node;
+                        queue.push(Entry::new(d, i, n));
+                    }
+                }
+            }
+        }
+        let Some(sink) = sink else {
+            break;
+        };
+        // nodes that have not been reached yet are at least as far
+        // away as the sink
+        let path_len = dist[n + sink];
+        for (p, d) in potential.iter_mut().zip(&dist) {
+            *p += d.min(path_len);
+        }
+
+        // find the bottleneck and augment along the path
+        let mut amount = demand[sink];
+        let mut node = n + sink;
+        while prev[node] != usize::MAX {
+            let from = prev[node];
+            if from >= n {
+                amount = amount.min(flow[node * m + from - n]);
+            }
+            node = from;
+        }
+        amount = amount.min(supply[node]);
+        supply[node] -= amount;
+        demand[sink] -= amount;
+        let mut node = n + sink;
+        while prev[node] != usize::MAX {
+            let from = prev[node];
+            if from < n {
+                flow[from * m + node - n] += amount;
+            } else {
+                flow[node * m + from - n] -= amount;
+            }
+            node = from;
+        }
+    }
+    flow.iter().zip(&cost).map(|(f, c)| f * c).sum()
+}
+
+// queue entry for Dijkstra's algorithm
+//
+// The entry with the smallest distance comes first. For equal
+// distances, sinks come before sources, so that we stop as early as
+// possible.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Entry {
+    key: Reverse<(N64, bool)>,
+    node: usize,
+}
+
+impl Entry {
+    fn new(dist: f64, node: usize, nsources: usize) -> Self {
+        Self {
+            key: Reverse((n64(dist), node < nsources)),
+            node,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::assignment;
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256Plus;
+
+    #[test]
+    fn tst_transport() {
+        // with unit masses, this is the assignment problem
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        for n in 1..7 {
+            for _ in 0..20 {
+                let cost: Vec<f64> =
+                    (0..n * n).map(|_| rng.gen_range(0. ..10.)).collect();
+                let expected = assignment::with_scratch(|scratch| {
+                    scratch.set_costs(n, |i, j| cost[i * n + j]);
+                    scratch.solve()
+                });
+                let ones = vec![1.; n];
+                let min = min_cost(&ones, &ones, |i, j| cost[i * n + j]);
+                assert!((min - expected).abs() < 1e-10);
+            }
+        }
+
+        // split the mass of one source
+        let cost = [[1., 2.], [3., 1.]];
+        let min = min_cost(&[2.], &[0.5, 1.5], |_, j| cost[0][j]);
+        assert!((min - 3.5).abs() < 1e-12);
+        let min = min_cost(&[1., 1.], &[0.5, 1.5], |i, j| cost[i][j]);
+        assert!((min - 2.5).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,319 @@
+//! Closure tests comparing distributions before and after resampling
+//!
+//! Resampling should leave all distributions unchanged within
+//! statistical uncertainties. To check this, histograms for a number
+//! of [observables](crate::observable) are filled with the events
+//! before resampling and with the final events. For each bin, the
+//! pull is the difference between the two sums of weights divided by
+//! the combined statistical uncertainty of both histograms. Since the
+//! two samples are strongly correlated, the pulls are typically
+//! smaller than for independent samples.
+use std::{fmt, str::FromStr};
+
+use noisy_float::prelude::*;
+use rayon::prelude::*;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    event::Event,
+    observable::{Observable, ObservableParseError},
+};
+
+/// Definition of a histogram
+///
+/// This can be parsed from a string of the form
+/// `OBSERVABLE:NBINS:MIN:MAX`, e.g. `pt(jet,1):50:0:500`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HistogramSpec {
+    /// The observable
+    pub observable: Observable,
+    /// Number of bins
+    pub nbins: usize,
+    /// Lower edge of the first bin
+    pub min: N64,
+    /// Upper edge of the last bin
+    pub max: N64,
+}
+
+impl HistogramSpec {
+    fn bin(&self, e: &Event) -> Option<usize> {
+        let x = self.observable.value(e)?;
+        if x < self.min || x >= self.max {
+            return None;
+        }
+        let bin = (x - self.min) / (self.max - self.min) * self.nbins as f64;
+        Some(std::cmp::min(f64::from(bin) as usize, self.nbins - 1))
+    }
+
+    fn bin_edges(&self, bin: usize) -> (f64, f64) {
+        let width = (self.max - self.min) / self.nbins as f64;
+        let lower = self.min + width * bin as f64;
+        (lower.into(), (lower + width).into())
+    }
+}
+
+/// Error parsing a [HistogramSpec]
+#[derive(Debug, Clone, Error)]
+pub enum HistogramSpecParseError {
+    /// Not of the form `OBSERVABLE:NBINS:MIN:MAX`
+    #[error(
+        "Failed to parse histogram `{0}`: expected `OBSERVABLE:NBINS:MIN:MAX`"
+    )]
+    SyntaxErr(String),
+    /// Error parsing the observable
+    #[error(transparent)]
+    ObservableErr(#[from] ObservableParseError),
+    /// Invalid number of bins
+    #[error("Invalid number of bins `{0}`: expected a positive integer")]
+    NBinsErr(String),
+    /// Invalid histogram range
+    #[error("Invalid histogram range from `{0}` to `{1}`")]
+    RangeErr(String, String),
+}
+
+impl FromStr for HistogramSpec {
+    type Err = HistogramSpecParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use HistogramSpecParseError::*;
+
+        let mut parts = s.rsplitn(4, ':');
+        let (Some(max), Some(min), Some(nbins), Some(observable)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(SyntaxErr(s.to_owned()));
+        };
+        let observable = observable.parse()?;
+        let nbins = match nbins.trim().parse() {
+            Ok(nbins) if nbins > 0 => nbins,
+            _ => return Err(NBinsErr(nbins.to_owned())),
+        };
+        let range_err = || RangeErr(min.to_owned(), max.to_owned());
+        let lower: f64 = min.trim().parse().map_err(|_| range_err())?;
+        let upper: f64 = max.trim().parse().map_err(|_| range_err())?;
+        let (Some(lower), Some(upper)) =
+            (N64::try_new(lower), N64::try_new(upper))
+        else {
+            return Err(range_err());
+        };
+        if lower >= upper {
+            return Err(range_err());
+        }
+        Ok(Self {
+            observable,
+            nbins,
+            min: lower,
+            max: upper,
+        })
+    }
+}
+
+impl fmt::Display for HistogramSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.observable, self.nbins, self.min, self.max
+        )
+    }
+}
+
+/// A histogram storing the sums of weights and squared weights
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    sum_wt: Vec<f64>,
+    sum_wt_sq: Vec<f64>,
+}
+
+impl Histogram {
+    /// Fill a new histogram with the central weights of the given events
+    pub fn new(spec: &HistogramSpec, events: &[Event]) -> Self {
+        let empty = || Self {
+            sum_wt: vec![0.; spec.nbins],
+            sum_wt_sq: vec![0.; spec.nbins],
+        };
+        events
+            .par_iter()
+            .fold(empty, |mut hist, e| {
+                if let Some(bin) = spec.bin(e) {
+                    let wt = f64::from(e.weight());
+                    hist.sum_wt[bin] += wt;
+                    hist.sum_wt_sq[bin] += wt * wt;
+                }
+                hist
+            })
+            .reduce(empty, |mut acc, hist| {
+                for (acc, wt) in acc.sum_wt.iter_mut().zip(hist.sum_wt) {
+                    *acc += wt;
+                }
+                for (acc, wt) in acc.sum_wt_sq.iter_mut().zip(hist.sum_wt_sq) {
+                    *acc += wt;
+                }
+                acc
+            })
+    }
+
+    /// Sums of weights in each bin
+    pub fn sum_weights(&self) -> &[f64] {
+        &self.sum_wt
+    }
+
+    /// Statistical uncertainty of the sums of weights in each bin
+    pub fn errors(&self) -> Vec<f64> {
+        self.sum_wt_sq.iter().map(|w| w.sqrt()).collect()
+    }
+}
+
+/// Fill histograms for the given specifications
+pub fn fill_histograms(
+    specs: &[HistogramSpec],
+    events: &[Event],
+) -> Vec<Histogram> {
+    specs
+        .iter()
+        .map(|spec| Histogram::new(spec, events))
+        .collect()
+}
+
+/// Comparison of histograms before and after resampling
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ValidationReport {
+    /// Comparison for each histogram
+    pub histograms: Vec<HistogramComparison>,
+}
+
+impl ValidationReport {
+    /// Compare histograms before and after resampling
+    ///
+    /// `initial` and `resampled` have to be filled with the same
+    /// `specs`.
+    pub fn new(
+        specs: &[HistogramSpec],
+        initial: &[Histogram],
+        resampled: &[Histogram],
+    ) -> Self {
+        let histograms = specs
+            .iter()
+            .zip(initial.iter().zip(resampled))
+            .map(|(spec, (initial, resampled))| {
+                HistogramComparison::new(spec, initial, resampled)
+            })
+            .collect();
+        Self { histograms }
+    }
+}
+
+/// Comparison of a single histogram before and after resampling
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct HistogramComparison {
+    /// Histogram definition in the format `OBSERVABLE:NBINS:MIN:MAX`
+    pub histogram: String,
+    /// Comparison in each bin
+    pub bins: Vec<BinComparison>,
+    /// Sum of the squared pulls
+    pub chi2: f64,
+    /// Number of bins with a non-zero uncertainty
+    pub ndf: usize,
+}
+
+impl HistogramComparison {
+    fn new(
+        spec: &HistogramSpec,
+        initial: &Histogram,
+        resampled: &Histogram,
+    ) -> Self {
+        let initial_err = initial.errors();
+        let resampled_err = resampled.errors();
+        let mut chi2 = 0.;
+        let mut ndf = 0;
+        let bins = (0..spec.nbins)
+            .map(|bin| {
+                let (lower, upper) = spec.bin_edges(bin);
+                let err = initial_err[bin].hypot(resampled_err[bin]);
+                let pull = if err > 0. {
+                    let pull =
+                        (resampled.sum_wt[bin] - initial.sum_wt[bin]) / err;
+                    chi2 += pull * pull;
+                    ndf += 1;
+                    pull
+                } else {
+                    0.
+                };
+                BinComparison {
+                    lower,
+                    upper,
+                    initial: initial.sum_wt[bin],
+                    initial_err: initial_err[bin],
+                    resampled: resampled.sum_wt[bin],
+                    resampled_err: resampled_err[bin],
+                    pull,
+                }
+            })
+            .collect();
+        Self {
+            histogram: spec.to_string(),
+            bins,
+            chi2,
+            ndf,
+        }
+    }
+
+    /// χ² per degree of freedom
+    ///
+    /// This is zero if there are no degrees of freedom.
+    pub fn chi2_per_ndf(&self) -> f64 {
+        if self.ndf > 0 {
+            self.chi2 / self.ndf as f64
+        } else {
+            0.
+        }
+    }
+}
+
+/// Comparison in a single histogram bin
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BinComparison {
+    /// Lower bin edge
+    pub lower: f64,
+    /// Upper bin edge
+    pub upper: f64,
+    /// Sum of weights before resampling
+    pub initial: f64,
+    /// Statistical uncertainty before resampling
+    pub initial_err: f64,
+    /// Sum of weights after resampling and unweighting
+    pub resampled: f64,
+    /// Statistical uncertainty after resampling and unweighting
+    pub resampled_err: f64,
+    /// Difference divided by the combined uncertainty
+    pub pull: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::event::test_event;
+
+    fn event(wt: f64, pt: f64) -> Event {
+        test_event(0, wt, &[(81, [pt, pt, 0., 0.])])
+    }
+
+    #[test]
+    fn tst_closure() {
+        let spec: HistogramSpec = "pt(jet,1):2:0:20".parse().unwrap();
+        assert_eq!(spec.to_string(), "pt(jet,1):2:0:20");
+
+        let initial = [event(2., 5.), event(-1., 6.), event(3., 15.)];
+        let resampled = [event(0.5, 5.), event(0.5, 6.), event(3., 15.)];
+        let initial = fill_histograms(&[spec], &initial);
+        let resampled = fill_histograms(&[spec], &resampled);
+        assert_eq!(initial[0].sum_weights(), [1., 3.]);
+
+        let report = ValidationReport::new(&[spec], &initial, &resampled);
+        let hist = &report.histograms[0];
+        assert_eq!(hist.ndf, 2);
+        assert_eq!(hist.chi2, 0.);
+    }
+}
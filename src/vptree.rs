@@ -272,7 +272,7 @@ impl<'x, P: Copy + Hash + Eq + 'x> VPTree<P> {
         dist: DF,
         max_dist: N64,
         exclude: &HashSet<P>,
-        cached_dist: &mut HashMap<P, N64>,
+        cached_dist: &mut HashMap<P, CachedDist>,
     ) -> Option<(P, N64)>
     where
         DF: Distance<P>,
@@ -306,16 +306,24 @@ impl<'x, P: Copy + Hash + Eq + 'x> VPTree<P> {
         idx: usize,
         max_dist: N64,
         exclude: &HashSet<P>,
-        cached_dist: &mut HashMap<P, N64>,
+        cached_dist: &mut HashMap<P, CachedDist>,
     ) -> Option<(usize, N64)>
     where
         DF: Distance<P>,
     {
         trace!("node at position {idx}");
         if let Some((node, tree)) = subtree.split_first() {
-            let d = *cached_dist
+            // Beyond this distance, the exact value doesn't affect the
+            // search: neither the vantage point nor the inner region
+            // can contain a neighbour within `max_dist`
+            let bound = match &node.children {
+                Some(children) => children.radius + max_dist,
+                None => max_dist,
+            };
+            let d = cached_dist
                 .entry(node.vantage_pt)
-                .or_insert_with(|| dist.distance(&pt, &node.vantage_pt));
+                .or_insert(CachedDist::Unknown)
+                .get(dist, &pt, &node.vantage_pt, bound);
             let mut nearest = if pt == node.vantage_pt
                 || exclude.contains(&node.vantage_pt)
             {
@@ -340,11 +348,14 @@ impl<'x, P: Copy + Hash + Eq + 'x> VPTree<P> {
                     pt,
                     dist,
                     idx + offsets.0,
-                    max_dist,
+                    Self::search_radius(nearest, max_dist),
                     exclude,
                     cached_dist,
                 );
                 nearest = Self::nearer(nearest, nearest_pref);
+                // points farther away than the nearest one found so
+                // far can't be the nearest neighbour
+                let max_dist = Self::search_radius(nearest, max_dist);
                 let possibly_in_less_promising =
                     (d - children.radius).abs() <= max_dist;
                 if !possibly_in_less_promising {
@@ -376,6 +387,13 @@ impl<'x, P: Copy + Hash + Eq + 'x> VPTree<P> {
         }
     }
 
+    fn search_radius<T>(nearest: Option<(T, N64)>, max_dist: N64) -> N64 {
+        match nearest {
+            Some((_, d)) => std::cmp::min(d, max_dist),
+            None => max_dist,
+        }
+    }
+
     fn nearer<T>(a: Option<(T, N64)>, b: Option<(T, N64)>) -> Option<(T, N64)> {
         match (&a, &b) {
             (&Some((_, d1)), &Some((_, d2))) => {
@@ -391,12 +409,50 @@ impl<'x, P: Copy + Hash + Eq + 'x> VPTree<P> {
     }
 }
 
+/// Cached distance between the query point and a vantage point
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum CachedDist {
+    Unknown,
+    Exact(N64),
+    /// The distance is larger than the given value
+    Above(N64),
+}
+
+impl CachedDist {
+    /// Get the distance if it doesn't exceed `bound`, infinity otherwise
+    fn get<P, DF: Distance<P>>(
+        &mut self,
+        dist: &DF,
+        pt: &P,
+        vantage_pt: &P,
+        bound: N64,
+    ) -> N64 {
+        match *self {
+            Self::Exact(d) => return d,
+            Self::Above(b) if b >= bound => return N64::infinity(),
+            _ => {}
+        }
+        let d = if dist.lower_bound(pt, vantage_pt) > bound {
+            None
+        } else {
+            dist.distance_within(pt, vantage_pt, bound)
+        };
+        if let Some(d) = d {
+            *self = Self::Exact(d);
+            d
+        } else {
+            *self = Self::Above(bound);
+            N64::infinity()
+        }
+    }
+}
+
 pub struct NearestNeighbourIter<'a, P: Hash + Eq, DF> {
     pt: P,
     dist: DF,
     tree: &'a VPTree<P>,
     exclude: HashSet<P>,
-    distance_cache: HashMap<P, N64>,
+    distance_cache: HashMap<P, CachedDist>,
 }
 
 impl<'a, P: Hash + Eq, DF> Iterator for NearestNeighbourIter<'a, P, DF>
@@ -421,3 +477,43 @@ where
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256Plus;
+
+    struct Points(Vec<[f64; 2]>);
+
+    impl Distance<usize> for Points {
+        fn distance(&self, a: &usize, b: &usize) -> N64 {
+            let (a, b) = (self.0[*a], self.0[*b]);
+            n64((a[0] - b[0]).hypot(a[1] - b[1]))
+        }
+
+        fn lower_bound(&self, a: &usize, b: &usize) -> N64 {
+            n64((self.0[*a][0] - self.0[*b][0]).abs())
+        }
+    }
+
+    #[test]
+    fn tst_nearest() {
+        let mut rng = Xoshiro256Plus::seed_from_u64(0);
+        let pts = Points((0..200).map(|_| rng.gen()).collect());
+        let max_dist = n64(0.3);
+        let tree = VPTree::seq_new((0..pts.0.len()).collect(), &pts)
+            .with_max_dist(max_dist);
+        for pt in [0, 17, 123] {
+            let mut expected: Vec<_> = (0..pts.0.len())
+                .filter(|&n| n != pt)
+                .map(|n| (n, pts.distance(&pt, &n)))
+                .filter(|(_, d)| *d <= max_dist)
+                .collect();
+            expected.sort_by_key(|(_, d)| *d);
+            let found: Vec<_> = tree.nearest_in(&pt, &pts).collect();
+            assert_eq!(found, expected);
+        }
+    }
+}
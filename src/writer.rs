@@ -16,7 +16,7 @@ use crate::{
     compression::Compression,
     event::Event,
     progress_bar::{Progress, ProgressBar},
-    traits::{Write, WriteEvent},
+    traits::{SourceIndex, Write, WriteEvent},
 };
 
 /// Supported output formats
@@ -31,6 +31,11 @@ pub enum OutputFormat {
     /// website](https://gitlab.cern.ch/hepmc/HepMC3) for details.
     #[default]
     HepMC2,
+    /// The HepMC3 ASCII format
+    ///
+    /// See the [official HepMC3
+    /// website](https://gitlab.cern.ch/hepmc/HepMC3) for details.
+    HepMC3,
     /// The [Les Houches Event File](https://arxiv.org/abs/hep-ph/0109068v1) format
     #[cfg(feature = "lhef")]
     Lhef,
@@ -43,9 +48,18 @@ pub enum OutputFormat {
 }
 
 /// General-purpose writer to some event file
+///
+/// By default, all events are written to a single file. With
+/// [per_source_filenames](FileWriterBuilder::per_source_filenames),
+/// events are instead written to separate files, depending on the
+/// [source](crate::traits::SourceIndex) they were read from.
 #[derive(Debug, TypedBuilder)]
 pub struct FileWriter {
     filename: PathBuf,
+    /// If not empty, events from the `n`th source are written to the
+    /// `n`th file and `filename` is ignored
+    #[builder(default)]
+    per_source_filenames: Vec<PathBuf>,
     #[builder(default)]
     format: OutputFormat,
     #[builder(default)]
@@ -67,13 +81,22 @@ impl FileWriter {
     where
         F: FnMut(&Path, Option<Compression>) -> Result<W, std::io::Error>,
         W: WriteEvent<avery::Event, Error = std::io::Error>,
-        R: Iterator<Item = Result<avery::Event, RE>>,
+        R: Iterator<Item = Result<avery::Event, RE>> + SourceIndex,
         RE: std::error::Error,
     {
         use EventWriteError::*;
 
-        let mut writer =
-            make_writer(&self.filename, self.compression).map_err(CreateErr)?;
+        let per_source = !self.per_source_filenames.is_empty();
+        let filenames = if per_source {
+            self.per_source_filenames.as_slice()
+        } else {
+            std::slice::from_ref(&self.filename)
+        };
+        // events are ordered by id, so we only ever need one open
+        // writer for the current source
+        let mut source = 0;
+        let mut writer = make_writer(&filenames[source], self.compression)
+            .map_err(CreateErr)?;
 
         let dump_event_to = self
             .cell_collector
@@ -91,15 +114,26 @@ impl FileWriter {
             }
         }
 
-        let mut reader_events = r.enumerate();
+        let mut read_id = 0;
         let progress = ProgressBar::new(events.len() as u64, "events written:");
         for event in events {
-            let (read_id, read_event) = reader_events.next().unwrap();
-            let mut read_event = read_event.map_err(ReadErr)?;
-            if read_id < event.id() {
-                for _ in read_id..event.id() {
-                    let (_id, ev) = reader_events.next().unwrap();
-                    read_event = ev.map_err(ReadErr)?;
+            let mut read_event = r.next().unwrap().map_err(ReadErr)?;
+            while read_id < event.id() {
+                read_event = r.next().unwrap().map_err(ReadErr)?;
+                read_id += 1;
+            }
+            read_id += 1;
+            if per_source {
+                while source < r.source_index() {
+                    source += 1;
+                    let filename = filenames
+                        .get(source)
+                        .ok_or(MissingOutputErr(source))?;
+                    let next = make_writer(filename, self.compression)
+                        .map_err(CreateErr)?;
+                    std::mem::replace(&mut writer, next)
+                        .finish()
+                        .map_err(WriteErr)?;
                 }
             }
             if read_event.id.is_none() {
@@ -136,6 +170,14 @@ impl FileWriter {
             progress.inc(1);
         }
         writer.finish().map_err(WriteErr)?;
+        // sources without any remaining events still get an (empty)
+        // output file
+        for filename in filenames.iter().skip(source + 1) {
+            make_writer(filename, self.compression)
+                .map_err(CreateErr)?
+                .finish()
+                .map_err(WriteErr)?;
+        }
         for (_, cell_writer) in cell_writers {
             cell_writer.finish().map_err(WriteErr)?;
         }
@@ -146,7 +188,7 @@ impl FileWriter {
 
 impl<R, RE> Write<R> for FileWriter
 where
-    R: Iterator<Item = Result<avery::Event, RE>>,
+    R: Iterator<Item = Result<avery::Event, RE>> + SourceIndex,
     RE: std::error::Error,
 {
     type Error = EventWriteError<RE, std::io::Error>;
@@ -159,6 +201,7 @@ where
         use OutputFormat::*;
         match self.format {
             HepMC2 => self.write_all(crate::hepmc2::Writer::try_new, r, events),
+            HepMC3 => self.write_all(crate::hepmc3::Writer::try_new, r, events),
             #[cfg(feature = "lhef")]
             Lhef => self.write_all(crate::lhef::Writer::try_new, r, events),
             #[cfg(feature = "ntuple")]
@@ -183,4 +226,64 @@ pub enum EventWriteError<RE, WE> {
     /// Error writing an event
     #[error("Failed to write event: {0}")]
     WriteErr(WE),
+    /// No output file for an event source
+    #[error("No output file for events from source {0}")]
+    MissingOutputErr(usize),
+}
+
+/// Output file names for each input file
+///
+/// In `template`, each occurrence of `{stem}` is replaced by the
+/// input file name without directory, format extension, and
+/// compression extension. Each occurrence of `{index}` is replaced by
+/// the position of the input file in `infiles`, starting at 0.
+///
+/// Returns `None` if the template contains neither `{stem}` nor
+/// `{index}`, and an error if it contains them but is not valid
+/// UTF-8.
+pub fn per_input_filenames<P: AsRef<Path>>(
+    template: &Path,
+    infiles: &[P],
+) -> Result<Option<Vec<PathBuf>>, TemplateError> {
+    const COMPRESSION_EXTENSIONS: [&str; 5] =
+        ["bz2", "gz", "lz4", "zst", "zstd"];
+
+    let lossy = template.to_string_lossy();
+    if !lossy.contains("{stem}") && !lossy.contains("{index}") {
+        return Ok(None);
+    }
+    let Some(template) = template.to_str() else {
+        return Err(TemplateError(template.to_owned()));
+    };
+    let filenames = infiles
+        .iter()
+        .enumerate()
+        .map(|(index, infile)| {
+            let mut infile = infile.as_ref().to_owned();
+            let compressed = infile
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| COMPRESSION_EXTENSIONS.contains(&ext))
+                .unwrap_or(false);
+            if compressed {
+                infile.set_extension("");
+            }
+            let stem = infile
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .unwrap_or_default();
+            template
+                .replace("{stem}", &stem)
+                .replace("{index}", &index.to_string())
+                .into()
+        })
+        .collect();
+    Ok(Some(filenames))
 }
+
+/// Output file name template that is not valid UTF-8
+#[derive(Debug, Error)]
+#[error(
+    "Output file name {0:?} with `{{stem}}` or `{{index}}` is not valid UTF-8"
+)]
+pub struct TemplateError(pub PathBuf);